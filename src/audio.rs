@@ -1,3 +1,4 @@
+use mockall::automock;
 use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone)]
@@ -17,7 +18,7 @@ pub struct NoiseOptions {
   pub short: bool
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Channel {
   CH1,
   CH2,
@@ -31,6 +32,7 @@ pub enum StereoChannel {
   Right,
 }
 
+#[automock]
 pub trait AudioDriver {
   fn play_pulse(&mut self, channel: Channel, pulse_options: PulseOptions);
   fn play_custom_wave(&mut self, channel: Channel, wave_options: CustomWaveOptions);
@@ -43,4 +45,24 @@ pub trait AudioDriver {
   fn mute_all(&mut self);
   fn unmute_all(&mut self);
   fn set_master_volume(&mut self, value: u8);
+
+  // How many audio frames the driver should keep buffered before the host drains them, trading latency for fewer
+  // underruns. The emulator doesn't produce samples itself, so drivers that do their own buffering can ignore this.
+  fn set_buffer_frames(&mut self, _frames: usize) {}
+}
+
+// Discards everything, for running the emulator without sound
+pub struct NullAudioDriver;
+
+impl AudioDriver for NullAudioDriver {
+  fn play_pulse(&mut self, _channel: Channel, _pulse_options: PulseOptions) {}
+  fn play_custom_wave(&mut self, _channel: Channel, _wave_options: CustomWaveOptions) {}
+  fn play_noise(&mut self, _channel: Channel, _noise_options: NoiseOptions) {}
+  fn stop(&mut self, _channel: Channel) {}
+  fn set_gain(&mut self, _channel: Channel, _gain: f32) {}
+  fn set_stereo_gain(&mut self, _channel: Channel, _stereo_channel: StereoChannel, _gain: f32) {}
+  fn set_frequency(&mut self, _channel: Channel, _frequency: f32) {}
+  fn mute_all(&mut self) {}
+  fn unmute_all(&mut self) {}
+  fn set_master_volume(&mut self, _value: u8) {}
 }
\ No newline at end of file
@@ -1,5 +1,39 @@
 use crate::memory::{CartridgeType, CGBMode, Licensee};
 use crate::memory::{RAMSize, ROMSize};
+use crate::internal::util::bit_util::BitUtil;
+
+// The cartridge type bytes (at 0x0147 in the header) of the MBCs the emulator can run: no MBC, MBC1, MBC2, MBC3 and MBC5.
+// This has to match the cartridge types Emulator::create_mbc accepts, which a test in emulator.rs checks.
+const SUPPORTED_CARTRIDGE_TYPES: [u8; 17] = [
+  0x00,
+  0x01, 0x02, 0x03,
+  0x05, 0x06,
+  0x0F, 0x10, 0x11, 0x12, 0x13,
+  0x19, 0x1A, 0x1B, 0x1C, 0x1D, 0x1E,
+];
+
+pub fn supported_cartridge_types() -> &'static [u8] {
+  &SUPPORTED_CARTRIDGE_TYPES
+}
+
+pub fn is_supported(cartridge_type_byte: u8) -> bool {
+  SUPPORTED_CARTRIDGE_TYPES.contains(&cartridge_type_byte)
+}
+
+// The logo at 0x0104-0x0133 in the header, which the boot ROM compares against before it hands off to the cartridge
+const NINTENDO_LOGO: [u8; 48] = [
+  0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+  0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+  0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+pub fn nintendo_logo() -> &'static [u8; 48] {
+  &NINTENDO_LOGO
+}
+
+pub fn logo_matches(rom_bytes: &[u8]) -> bool {
+  rom_bytes.get(0x0104..0x0134) == Some(&NINTENDO_LOGO[..])
+}
 
 pub struct CartridgeInfo {
   pub title: String,
@@ -25,14 +59,12 @@ impl CartridgeInfo {
     }
   }
 
+  // On CGB cartridges, the end of the title area holds the manufacturer code (0x013F-0x0142) and the CGB flag (0x0143)
   fn read_title(rom_bytes: &[u8]) -> String {
-    let mut title = String::new();
-    (0x134..=0x143)
-      .map(|index| rom_bytes[index])
-      .take_while(|byte| *byte != 0x00)
-      .map(|byte| byte as char)
-      .for_each(|character| title.push(character));
-    title
+    let title_bytes = if rom_bytes[0x0143].get_bit(7) { &rom_bytes[0x134..=0x13E] } else { &rom_bytes[0x134..=0x143] };
+    String::from_utf8_lossy(title_bytes)
+      .trim_end_matches('\0')
+      .to_string()
   }
 
   fn calculate_title_checksum(rom_bytes: &[u8]) -> u8 {
@@ -46,3 +78,60 @@ impl CartridgeInfo {
     self.title.as_str()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn create_header(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+    let mut rom_bytes = vec![0u8; 0x150];
+    rom_bytes[0x0134..0x0134 + title.len()].copy_from_slice(title);
+    rom_bytes[0x0143] = cgb_flag;
+    rom_bytes
+  }
+
+  #[test]
+  fn logo_matches_only_the_canonical_logo() {
+    let mut rom_bytes = create_header(b"TETRIS", 0x00);
+    assert!(!logo_matches(&rom_bytes));
+    rom_bytes[0x0104..0x0134].copy_from_slice(nintendo_logo());
+    assert!(logo_matches(&rom_bytes));
+    rom_bytes[0x0120] ^= 0x01;
+    assert!(!logo_matches(&rom_bytes));
+    // A ROM that's too small to contain a header doesn't match either
+    assert!(!logo_matches(&rom_bytes[..0x0120]));
+  }
+
+  #[test]
+  fn title_has_trailing_padding_trimmed() {
+    let info = CartridgeInfo::from_bytes(&create_header(b"TETRIS", 0x00));
+    assert_eq!(info.get_title(), "TETRIS");
+    let info = CartridgeInfo::from_bytes(&create_header(b"SUPER MARIOLAND", 0x00));
+    assert_eq!(info.get_title(), "SUPER MARIOLAND");
+  }
+
+  #[test]
+  fn cgb_title_excludes_manufacturer_code() {
+    let info = CartridgeInfo::from_bytes(&create_header(b"POKEMON GLDAAUE", 0x80));
+    assert_eq!(info.get_title(), "POKEMON GLD");
+    let info = CartridgeInfo::from_bytes(&create_header(b"ZELDA\0\0\0\0\0\0AZ7E", 0xC0));
+    assert_eq!(info.get_title(), "ZELDA");
+  }
+
+  #[test]
+  fn title_replaces_invalid_characters() {
+    let info = CartridgeInfo::from_bytes(&create_header(b"GAME\xFF", 0x00));
+    assert_eq!(info.get_title(), "GAME\u{FFFD}");
+  }
+
+  #[test]
+  fn reports_supported_cartridge_types() {
+    // No MBC, MBC1, MBC2, MBC3 and MBC5
+    [0x00, 0x01, 0x03, 0x05, 0x06, 0x0F, 0x13, 0x19, 0x1E].into_iter()
+      .for_each(|cartridge_type_byte| assert!(is_supported(cartridge_type_byte), "{:#04x}", cartridge_type_byte));
+    // MMM01, MBC6, MBC7, HuC1 and unknown codes
+    [0x04, 0x0B, 0x0D, 0x20, 0x22, 0xFF].into_iter()
+      .for_each(|cartridge_type_byte| assert!(!is_supported(cartridge_type_byte), "{:#04x}", cartridge_type_byte));
+    assert!(supported_cartridge_types().iter().all(|&cartridge_type_byte| is_supported(cartridge_type_byte)));
+  }
+}
@@ -1,4 +1,10 @@
-#[derive(Copy, Clone, Debug)]
+use serde::Serialize;
+
+use crate::internal::cpu::decoder::InstructionDecoder;
+
+pub use crate::internal::cpu::interrupts::Interrupt;
+
+#[derive(Copy, Clone, Debug, Serialize)]
 pub struct CPUInfo {
     pub af: u16,
     pub bc: u16,
@@ -8,4 +14,96 @@ pub struct CPUInfo {
     pub pc: u16,
     pub stopped: bool,
     pub enabled: bool,
-}
\ No newline at end of file
+    // The interrupt master enable flag, and whether an EI is waiting for the next instruction to set it
+    pub ime: bool,
+    pub ime_pending: bool,
+    // The number of instructions decoded so far, where a CB-prefixed instruction counts as one
+    pub instructions_executed: u64,
+}
+// The hardware models whose register state after running the boot ROM can be reproduced without running it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GbModel {
+    Dmg,
+    MgbPocket,
+    Sgb,
+    Cgb,
+    CgbInDmgCompat,
+}
+
+// What the emulator does when STOP is executed without a speed switch armed or a button press pending to wake it up
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum StopBehavior {
+    // Stop the system clock until a button is pressed, like the hardware does
+    #[default]
+    HardwareAccurate,
+    // Carry on with the instruction after STOP
+    TreatAsNop,
+    Panic,
+}
+
+// The operation of a decoded instruction. Opcodes the CPU doesn't know decode to Invalid.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum Mnemonic {
+    Nop, Ld, Ldh, Inc, Dec, Add, Adc, Sub, Sbc, And, Xor, Or, Cp,
+    Rlca, Rrca, Rla, Rra, Daa, Cpl, Scf, Ccf,
+    Jr, Jp, Call, Ret, Reti, Rst, Push, Pop,
+    Halt, Stop, Di, Ei,
+    Rlc, Rrc, Rl, Rr, Sla, Sra, Swap, Srl, Bit, Res, Set,
+    Invalid,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum Condition {
+    NotZero,
+    Zero,
+    NotCarry,
+    Carry,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum Register {
+    A, B, C, D, E, H, L,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum RegisterPair {
+    AF, BC, DE, HL, SP,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum Operand {
+    Register(Register),
+    RegisterPair(RegisterPair),
+    // The memory location a register pair points to, like (HL)
+    Indirect(RegisterPair),
+    // (HL+) and (HL-)
+    IndirectHLIncrement,
+    IndirectHLDecrement,
+    Immediate8(u8),
+    Immediate16(u16),
+    // The memory location at an immediate address, like (nn)
+    IndirectAddress(u16),
+    // (FF00+n) and (FF00+C)
+    HighIndirect(u8),
+    HighIndirectC,
+    // The signed offset of JR, ADD SP,e and LD HL,SP+e
+    Offset(i8),
+    // The bit index of BIT, RES and SET
+    Bit(u8),
+    // The call target of RST
+    Vector(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DecodedInstruction {
+    pub mnemonic: Mnemonic,
+    pub condition: Option<Condition>,
+    // The destination comes first, as in the assembly syntax
+    pub operands: Vec<Operand>,
+    pub length: u8,
+}
+
+// Decodes the instruction at the start of the given bytes. Operand bytes beyond the end of the slice read as 0.
+pub fn decode_instruction(bytes: &[u8]) -> DecodedInstruction {
+    InstructionDecoder::decode_to_descriptor(bytes)
+}
@@ -0,0 +1,174 @@
+use serde::Serialize;
+
+use crate::cpu::CPUInfo;
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct InterruptRegisters {
+  pub ime: bool,
+  pub ie: u8,
+  #[serde(rename = "if")]
+  pub if_: u8,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct LCDRegisters {
+  pub lcdc: u8,
+  pub stat: u8,
+  pub ly: u8,
+  pub lyc: u8,
+  pub scx: u8,
+  pub scy: u8,
+  pub wx: u8,
+  pub wy: u8,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct TimerRegisters {
+  pub div: u8,
+  pub tima: u8,
+  pub tma: u8,
+  pub tac: u8,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct BankSelection {
+  pub rom_bank: u16,
+  pub ram_bank: Option<u8>,
+  pub rtc_register: Option<u8>,
+  pub vram_bank: u8,
+  pub wram_bank: u8,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct DebugState {
+  pub cpu: CPUInfo,
+  pub interrupts: InterruptRegisters,
+  pub lcd: LCDRegisters,
+  pub timer: TimerRegisters,
+  pub banks: BankSelection,
+}
+
+impl DebugState {
+  pub fn registers(&self) -> [(&'static str, u16); 23] {
+    [
+      ("af", self.cpu.af),
+      ("bc", self.cpu.bc),
+      ("de", self.cpu.de),
+      ("hl", self.cpu.hl),
+      ("sp", self.cpu.sp),
+      ("pc", self.cpu.pc),
+      ("ime", self.interrupts.ime as u16),
+      ("ie", self.interrupts.ie as u16),
+      ("if", self.interrupts.if_ as u16),
+      ("lcdc", self.lcd.lcdc as u16),
+      ("stat", self.lcd.stat as u16),
+      ("ly", self.lcd.ly as u16),
+      ("lyc", self.lcd.lyc as u16),
+      ("scx", self.lcd.scx as u16),
+      ("scy", self.lcd.scy as u16),
+      ("wx", self.lcd.wx as u16),
+      ("wy", self.lcd.wy as u16),
+      ("div", self.timer.div as u16),
+      ("tima", self.timer.tima as u16),
+      ("tma", self.timer.tma as u16),
+      ("tac", self.timer.tac as u16),
+      ("vbk", self.banks.vram_bank as u16),
+      ("svbk", self.banks.wram_bank as u16),
+    ]
+  }
+}
+
+// A difference between the states of two emulators. Memory differences are reported by bank and by the address
+// at which the byte is mapped when that bank is selected.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StateDiff {
+  Register { name: &'static str, left: u16, right: u16 },
+  Memory { bank: usize, address: u16, left: u8, right: u8 },
+}
+
+impl StateDiff {
+  pub(crate) fn memory_diffs<'a>(bank: usize, start_address: u16, left: &'a [u8], right: &'a [u8]) -> impl Iterator<Item=StateDiff> + 'a {
+    left.iter()
+      .zip(right.iter())
+      .enumerate()
+      .filter(|(_, (left, right))| left != right)
+      .map(move |(offset, (&left, &right))| StateDiff::Memory { bank, address: start_address + offset as u16, left, right })
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum MemoryRegion {
+  ROM,
+  VRAM,
+  CartridgeRAM,
+  // An MBC3 clock register mapped to 0xA000-0xBFFF in place of a RAM bank
+  RTC,
+  WRAM,
+  OAM,
+  Unusable,
+  IO,
+  HRAM,
+  IE,
+}
+
+// Where a CPU address ends up with the current bank selections. The offset is relative to the start of the
+// region's backing store, so it includes the bank, e.g. 0x4000 * bank + the address within the bank for ROM.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct ResolvedAddress {
+  pub region: MemoryRegion,
+  pub bank: usize,
+  pub offset: usize,
+}
+
+// A change of the traced bits of an I/O register. Both values only hold the traced bits.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct SignalEvent {
+  pub machine_cycle: u64,
+  pub address: u16,
+  pub bit_mask: u8,
+  pub previous_value: u8,
+  pub value: u8,
+}
+
+// Which ROM addresses were executed as code and which ones were read as data, per ROM bank
+#[derive(Clone, Default)]
+pub struct Coverage {
+  banks: Vec<Vec<u8>>,
+}
+
+impl Coverage {
+  const BANK_SIZE: usize = 0x4000;
+  const EXECUTED: u8 = 0x01;
+  const READ: u8 = 0x02;
+
+  pub fn is_executed(&self, bank: usize, address: u16) -> bool {
+    self.flags(bank, address) & Coverage::EXECUTED != 0
+  }
+
+  pub fn is_read(&self, bank: usize, address: u16) -> bool {
+    self.flags(bank, address) & Coverage::READ != 0
+  }
+
+  pub fn executed_byte_count(&self) -> usize {
+    self.banks.iter().flatten().filter(|flags| **flags & Coverage::EXECUTED != 0).count()
+  }
+
+  pub(crate) fn mark_executed(&mut self, bank: usize, address: u16) {
+    self.mark(bank, address, Coverage::EXECUTED);
+  }
+
+  pub(crate) fn mark_read(&mut self, bank: usize, address: u16) {
+    self.mark(bank, address, Coverage::READ);
+  }
+
+  fn flags(&self, bank: usize, address: u16) -> u8 {
+    self.banks.get(bank).map_or(0, |flags| flags[address as usize % Coverage::BANK_SIZE])
+  }
+
+  fn mark(&mut self, bank: usize, address: u16, flag: u8) {
+    if self.banks.len() <= bank {
+      self.banks.resize(bank + 1, vec![0; Coverage::BANK_SIZE]);
+    }
+    self.banks[bank][address as usize % Coverage::BANK_SIZE] |= flag;
+  }
+}
@@ -1,22 +1,31 @@
-use std::borrow::BorrowMut;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io::Cursor;
 use std::panic;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bincode::{deserialize_from, serialize_into};
-use log::info;
 
-use crate::audio::AudioDriver;
-use crate::cartridge_info::CartridgeInfo;
-use crate::cpu::CPUInfo;
-use crate::input::Button;
+use crate::audio::{AudioDriver, Channel, NullAudioDriver};
+use crate::cartridge_info::{self, CartridgeInfo};
+use crate::cpu::{CPUInfo, GbModel, StopBehavior};
+use crate::debug::{BankSelection, Coverage, DebugState, InterruptRegisters, LCDRegisters, MemoryRegion, ResolvedAddress, SignalEvent, StateDiff, TimerRegisters};
+use crate::input::{Button, InputLog};
 use crate::internal::controllers::audio::AudioControllerImpl;
 use crate::internal::controllers::buttons::{ButtonController, ButtonControllerImpl};
 use crate::internal::controllers::dma::{DMAController, DMAControllerImpl};
-use crate::internal::controllers::lcd::LCDControllerImpl;
+use crate::internal::controllers::lcd::{LCDController, LCDControllerImpl, LCDMode};
+use crate::internal::controllers::serial::{SerialController, SerialControllerImpl};
 use crate::internal::controllers::speed::{SpeedController, SpeedControllerImpl};
 use crate::internal::controllers::timer::{TimerController, TimerControllerImpl};
 use crate::internal::cpu::cpu::{CPU, CPUImpl};
-use crate::internal::cpu::interrupts::InterruptControllerImpl;
+use crate::internal::cpu::interrupts::{InterruptController, InterruptControllerImpl};
+use crate::internal::infrastructure::coverage_tracker::CoverageTracker;
+use crate::internal::infrastructure::filtered_renderer::{FilteredRenderer, PreparedFilter};
+use crate::internal::infrastructure::input_recorder::{InputPlayer, InputRecorder};
+use crate::internal::infrastructure::logging;
+use crate::internal::infrastructure::rewind_buffer::RewindBuffer;
+use crate::internal::infrastructure::signal_tracer::SignalTracer;
 use crate::internal::memory::bus::MemoryBus;
 use crate::internal::memory::control::ControlRegisters;
 use crate::internal::memory::cram::{CRAM, CRAMImpl};
@@ -34,10 +43,13 @@ use crate::internal::memory::stack::Stack;
 use crate::internal::memory::unmapped::UnmappedMemory;
 use crate::internal::memory::vram::VRAMImpl;
 use crate::internal::memory::wram::WRAMImpl;
+use crate::internal::util::bit_util::BitUtil;
 use crate::internal::util::compatibility_palette::CompatibilityPaletteLoader;
 use crate::internal::util::instruction_label_provider::InstructionLabelProvider;
-use crate::memory::{CartridgeType, CGBMode, OAMObject};
-use crate::renderer::{Renderer, RenderTarget};
+use crate::logger::EmulatorLogger;
+use crate::memory::{CartridgeType, CGBMode, OAMObject, RAMSize, ROMSize, TileMapIndex};
+use crate::renderer::{AccessibilityFilter, CompatibilityPalette, NullRenderer, Renderer, RenderTarget};
+use crate::serial::SerialPeer;
 
 pub struct Emulator<A: AudioDriver, R: Renderer> {
   rom: Box<dyn MBC>,
@@ -54,59 +66,170 @@ pub struct Emulator<A: AudioDriver, R: Renderer> {
   interrupt_controller: InterruptControllerImpl,
   speed_controller: SpeedControllerImpl,
   button_controller: ButtonControllerImpl,
+  serial: SerialControllerImpl,
   audio_controller: AudioControllerImpl,
   stack: Stack,
   control_registers: ControlRegisters,
-  reserved_area_1: LinearMemory<0x1E00, 0xE000>,
   reserved_area_2: LinearMemory<0x0060, 0xFEA0>,
   unmapped_memory: UnmappedMemory,
   audio_driver: A,
+  audio_output_enabled: bool,
   paused: bool,
+  rewind_buffer: Option<RewindBuffer>,
+  compatibility_palette_id: Option<u8>,
+  frame_count: u64,
+  input_recorder: Option<InputRecorder>,
+  input_player: Option<InputPlayer>,
+  vblank_callback: Option<VBlankCallback<A, R>>,
+  scanline_callback: Option<ScanlineCallback<A, R>>,
+  coverage_tracker: CoverageTracker,
+  signal_tracer: SignalTracer,
+  timing_stats: TimingStats,
+  accessibility_filter: Option<PreparedFilter>,
+  stop_behavior: StopBehavior,
+  config: EmulatorConfig,
 }
 
+// The builder settings that apply to any cartridge, so load_cartridge can set up the next one the same way
+#[derive(Clone, Default)]
+struct EmulatorConfig {
+  model: Option<GbModel>,
+  ram_init: Option<u8>,
+  compatibility_palette: Option<CompatibilityPalette>,
+  boot_rom: Option<Vec<u8>>,
+}
+
+pub type VBlankCallback<A, R> = Box<dyn FnMut(&mut Emulator<A, R>)>;
+pub type ScanlineCallback<A, R> = Box<dyn FnMut(u8, &Emulator<A, R>)>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimeoutError {
+  pub max_cycles: u64,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TimingStats {
+  pub machine_cycles: u64,
+  // Always the same as the emulator's frame count, which is the only counter that's kept
+  pub frames: u64,
+  // How far run_for_nanos has run ahead of the time that was requested, in nanoseconds.
+  // It can only run whole machine cycles, so each call overshoots by less than one.
+  pub drift_nanos: u64,
+}
+
+// A frame lasts 17556 machine cycles at normal speed, so this leaves room for double speed mode
+const MAX_MACHINE_CYCLES_PER_FRAME: u32 = 2 * 17556;
+
 impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
   pub fn new(rom_bytes: &[u8], audio_driver: A, renderer: R) -> Self {
-    info!("Creating new emulator");
+    EmulatorBuilder::new().build(rom_bytes, audio_driver, renderer)
+      .unwrap_or_else(|error| panic!("{}", error))
+  }
+
+  fn create(rom_bytes: &[u8], audio_driver: A, renderer: R, config: EmulatorConfig) -> Self {
+    logging::info(format_args!("Creating new emulator"));
     let cartridge_info = CartridgeInfo::from_bytes(rom_bytes);
     let rom = Emulator::<A, R>::create_rom(rom_bytes, &cartridge_info);
-    let mut cpu = CPUImpl::new();
-    cpu.init();
-    let mut cram = CRAMImpl::new();
-    let vram = VRAMImpl::new();
-    let wram = WRAMImpl::new();
-    let oam = OAMImpl::new();
-    let mut lcd = LCDControllerImpl::new();
-    let mut timer = TimerControllerImpl::new();
-    timer.write(MemoryAddress::TAC, 0xF8);
-    let dma = DMAControllerImpl::new();
-    let button_controller = ButtonControllerImpl::new();
-    let audio_controller = AudioControllerImpl::new();
-    let stack = Stack::new();
-    let mut control_registers = ControlRegisters::new();
-    let reserved_area_1 = LinearMemory::<0x1E00, 0xE000>::new();
-    let reserved_area_2 = LinearMemory::<0x0060, 0xFEA0>::new();
-    let interrupt_controller = InterruptControllerImpl::new();
-    let speed_controller = SpeedControllerImpl::new();
-    let unmapped_memory = UnmappedMemory::new();
+
+    // Every component starts out in its power-on state
+    let mut emulator = Emulator {
+      cpu: CPUImpl::new(),
+      rom,
+      cartridge_info,
+      cram: CRAMImpl::new(),
+      vram: VRAMImpl::new(),
+      wram: WRAMImpl::new(),
+      oam: OAMImpl::new(),
+      lcd: LCDControllerImpl::new(),
+      timer: TimerControllerImpl::new(),
+      dma: DMAControllerImpl::new(),
+      stack: Stack::new(),
+      button_controller: ButtonControllerImpl::new(),
+      serial: SerialControllerImpl::new(),
+      audio_controller: AudioControllerImpl::new(),
+      control_registers: ControlRegisters::new(),
+      reserved_area_2: LinearMemory::<0x0060, 0xFEA0>::new(),
+      interrupt_controller: InterruptControllerImpl::new(),
+      speed_controller: SpeedControllerImpl::new(),
+      renderer,
+      unmapped_memory: UnmappedMemory::new(),
+      audio_driver,
+      audio_output_enabled: true,
+      paused: false,
+      rewind_buffer: None,
+      compatibility_palette_id: None,
+      frame_count: 0,
+      timing_stats: TimingStats::default(),
+      accessibility_filter: None,
+      stop_behavior: StopBehavior::default(),
+      input_recorder: None,
+      input_player: None,
+      vblank_callback: None,
+      scanline_callback: None,
+      coverage_tracker: CoverageTracker::new(),
+      signal_tracer: SignalTracer::new(),
+      config,
+    };
+    match &emulator.config.boot_rom {
+      Some(boot_rom) => emulator.control_registers.load_boot_rom(boot_rom),
+      None => emulator.skip_boot_rom(rom_bytes[0x0143]),
+    }
+    emulator.apply_config();
+    emulator
+  }
+
+  // Puts the system in the state the boot ROM leaves it in when it hands off to the cartridge
+  fn skip_boot_rom(&mut self, compatibility_byte: u8) {
+    let model = if let CGBMode::Color = self.cartridge_info.cgb_mode { GbModel::Cgb } else { GbModel::CgbInDmgCompat };
+    self.cpu.init_for_model(model);
+    self.timer.write(MemoryAddress::TAC, 0xF8);
+    // The boot ROM leaves the APU powered on
+    self.audio_controller.write(MemoryAddress::NR52, 0x80);
 
     // If we're in compatibility/color mode, write the compatibility flag as is to KEY0
     // otherwise, write 0x04 to KEY0 and set the OPRI flag on the LCD to 0x01
-    if let CGBMode::Color = cartridge_info.cgb_mode {
-      control_registers.write(MemoryAddress::KEY0, rom_bytes[0x0143]);
+    self.compatibility_palette_id = if let CGBMode::Color = self.cartridge_info.cgb_mode {
+      self.control_registers.write(MemoryAddress::KEY0, compatibility_byte);
+      self.lcd.write(MemoryAddress::KEY0, compatibility_byte);
+      None
     } else {
-      let compatibility_palettes = CompatibilityPaletteLoader::get_compatibility_palettes(&cartridge_info);
-      cram.write_compatibility_palettes(compatibility_palettes);
-      control_registers.write(MemoryAddress::KEY0, 0x04);
-      lcd.write(MemoryAddress::OPRI, 0x01);
-    }
+      let palette_id = CompatibilityPaletteLoader::get_palette_id(&self.cartridge_info);
+      self.cram.write_compatibility_palettes(CompatibilityPaletteLoader::get_palettes_for_id(palette_id));
+      self.control_registers.write(MemoryAddress::KEY0, 0x04);
+      self.lcd.write(MemoryAddress::KEY0, 0x04);
+      self.lcd.write(MemoryAddress::OPRI, 0x01);
+      Some(palette_id)
+    };
 
     // Write 0x11 to BANK to indicate we're unmapping the boot rom
-    control_registers.write(MemoryAddress::BANK, 0x11);
+    self.control_registers.write(MemoryAddress::BANK, 0x11);
+    self.lcd.write(MemoryAddress::BANK, 0x11);
+  }
 
-    Emulator {
-      cpu,
+  // The boot ROM sets up the registers itself, so the model override only applies when it's skipped. The
+  // compatibility palette override likewise only applies once a palette has been picked for a monochrome game.
+  fn apply_config(&mut self) {
+    if let (Some(model), None) = (self.config.model, &self.config.boot_rom) {
+      self.init_for_model(model);
+    }
+    if let Some(value) = self.config.ram_init {
+      self.wram.fill(value);
+    }
+    if let Some(palette) = self.config.compatibility_palette {
+      self.set_compatibility_palette(palette);
+    }
+  }
+
+  // Replaces the cartridge and powers the console back on with the builder settings it was created with, keeping the
+  // audio driver, renderer and host settings such as callbacks and filters. Recordings, rewind snapshots and coverage
+  // of the previous cartridge are discarded.
+  pub fn load_cartridge(&mut self, rom_bytes: &[u8]) -> Result<(), String> {
+    validate_rom(rom_bytes)?;
+    // Every field is listed, so adding one means deciding whether it survives a cartridge swap
+    let Emulator {
       rom,
       cartridge_info,
+      cpu,
       cram,
       vram,
       wram,
@@ -114,25 +237,95 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
       lcd,
       timer,
       dma,
-      stack,
+      renderer: _,
+      interrupt_controller,
+      speed_controller,
       button_controller,
+      serial,
       audio_controller,
+      stack,
       control_registers,
-      reserved_area_1,
       reserved_area_2,
-      interrupt_controller,
-      speed_controller,
-      renderer,
       unmapped_memory,
-      audio_driver,
-      paused: false,
+      audio_driver: _,
+      audio_output_enabled: _,
+      paused: _,
+      rewind_buffer: _,
+      compatibility_palette_id,
+      frame_count,
+      input_recorder,
+      input_player,
+      vblank_callback: _,
+      scanline_callback: _,
+      coverage_tracker: _,
+      signal_tracer: _,
+      timing_stats,
+      accessibility_filter: _,
+      stop_behavior: _,
+      config: _,
+    } = Emulator::create(rom_bytes, NullAudioDriver, NullRenderer, self.config.clone());
+    [Channel::CH1, Channel::CH2, Channel::CH3, Channel::CH4].into_iter()
+      .for_each(|channel| self.audio_driver.stop(channel));
+    let headless = self.lcd.headless();
+    self.rom = rom;
+    self.cartridge_info = cartridge_info;
+    self.cpu = cpu;
+    self.cram = cram;
+    self.vram = vram;
+    self.wram = wram;
+    self.oam = oam;
+    self.lcd = lcd;
+    self.lcd.set_headless(headless);
+    self.timer = timer;
+    self.dma = dma;
+    self.interrupt_controller = interrupt_controller;
+    self.speed_controller = speed_controller;
+    self.button_controller = button_controller;
+    let serial_peer = self.serial.take_peer();
+    self.serial = serial;
+    if let Some(peer) = serial_peer {
+      self.serial.connect_peer(peer);
+    }
+    self.audio_controller = audio_controller;
+    self.stack = stack;
+    self.control_registers = control_registers;
+    self.reserved_area_2 = reserved_area_2;
+    self.unmapped_memory = unmapped_memory;
+    self.compatibility_palette_id = compatibility_palette_id;
+    self.frame_count = frame_count;
+    self.timing_stats = timing_stats;
+    self.input_recorder = input_recorder;
+    self.input_player = input_player;
+    if let Some(rewind_buffer) = self.rewind_buffer.as_mut() {
+      rewind_buffer.clear();
     }
+    self.coverage_tracker.reset();
+    self.cpu.set_memory_access_tracking_enabled(self.coverage_tracker.enabled());
+    Ok(())
+  }
+
+  // Diagnostics are routed per thread, so the logger applies to every emulator running on the calling thread
+  pub fn set_logger(logger: Box<dyn EmulatorLogger>) {
+    logging::set_logger(logger);
   }
 
   pub fn get_cartridge_info(&self) -> &CartridgeInfo {
     &self.cartridge_info
   }
 
+  // Returns the id of the palette used to colorize a monochrome game, or None for games with color support
+  pub fn compatibility_palette_id(&self) -> Option<u8> {
+    self.compatibility_palette_id
+  }
+
+  // Replaces the automatically selected colorization of a monochrome game. This has no effect on color games.
+  pub fn set_compatibility_palette(&mut self, palette: CompatibilityPalette) {
+    if self.compatibility_palette_id.is_some() {
+      self.cram.write_compatibility_palettes(CompatibilityPaletteLoader::get_palettes_for_id(palette.id()));
+      self.compatibility_palette_id = Some(palette.id());
+    }
+  }
+
   pub fn get_state(&self) -> Result<Vec<u8>, String> {
     let mut buffer: Vec<u8> = Vec::new();
 
@@ -148,9 +341,9 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
     serialize_into(&mut buffer, &self.dma).map_err(stringify_error)?;
     serialize_into(&mut buffer, &self.stack).map_err(stringify_error)?;
     serialize_into(&mut buffer, &self.button_controller).map_err(stringify_error)?;
+    serialize_into(&mut buffer, &self.serial).map_err(stringify_error)?;
     serialize_into(&mut buffer, &self.audio_controller).map_err(stringify_error)?;
     serialize_into(&mut buffer, &self.control_registers).map_err(stringify_error)?;
-    serialize_into(&mut buffer, &self.reserved_area_1).map_err(stringify_error)?;
     serialize_into(&mut buffer, &self.reserved_area_2).map_err(stringify_error)?;
     serialize_into(&mut buffer, &self.interrupt_controller).map_err(stringify_error)?;
     serialize_into(&mut buffer, &self.speed_controller).map_err(stringify_error)?;
@@ -161,47 +354,240 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
   pub fn load_state(&mut self, buffer: &[u8]) {
     let mut cursor = Cursor::new(buffer);
     self.cpu = deserialize_from(&mut cursor).unwrap();
+    self.cpu.set_memory_access_tracking_enabled(self.coverage_tracker.enabled());
     self.cram = deserialize_from(&mut cursor).unwrap();
     self.vram = deserialize_from(&mut cursor).unwrap();
     self.wram = deserialize_from(&mut cursor).unwrap();
     self.oam = deserialize_from(&mut cursor).unwrap();
+    let headless = self.lcd.headless();
     self.lcd = deserialize_from(&mut cursor).unwrap();
+    self.lcd.set_headless(headless);
     self.timer = deserialize_from(&mut cursor).unwrap();
     self.dma = deserialize_from(&mut cursor).unwrap();
     self.stack = deserialize_from(&mut cursor).unwrap();
     self.button_controller = deserialize_from(&mut cursor).unwrap();
+    let serial_peer = self.serial.take_peer();
+    self.serial = deserialize_from(&mut cursor).unwrap();
+    if let Some(peer) = serial_peer {
+      self.serial.connect_peer(peer);
+    }
     self.audio_controller = deserialize_from(&mut cursor).unwrap();
     self.control_registers = deserialize_from(&mut cursor).unwrap();
-    self.reserved_area_1 = deserialize_from(&mut cursor).unwrap();
+    // The boot ROM isn't part of the saved state
+    if let Some(boot_rom) = &self.config.boot_rom {
+      self.control_registers.load_boot_rom(boot_rom);
+    }
     self.reserved_area_2 = deserialize_from(&mut cursor).unwrap();
     self.interrupt_controller = deserialize_from(&mut cursor).unwrap();
     self.speed_controller = deserialize_from(&mut cursor).unwrap();
     self.unmapped_memory = deserialize_from(&mut cursor).unwrap();
   }
 
-  fn create_rom(rom_bytes: &[u8], cartridge_info: &CartridgeInfo) -> Box<dyn MBC> {
-    let rom_size = cartridge_info.rom_size;
-    let ram_size = cartridge_info.ram_size;
-    let mut rom: Box<dyn MBC> = match cartridge_info.cartridge_type {
-      CartridgeType::MBC => Box::new(MBC0::new(rom_size)),
-      CartridgeType::MBC1 => Box::new(MBC1::new(rom_size, ram_size)),
-      CartridgeType::MBC2 => Box::new(MBC2::new(rom_size)),
-      CartridgeType::MBC3 => Box::new(MBC3::new(rom_size, ram_size)),
-      CartridgeType::MBC5 => Box::new(MBC5::new(rom_size, ram_size)),
-      _ => panic!("This emulator currently does not support {:?} cartridges", cartridge_info.cartridge_type)
+  // Exports the cartridge RAM in the common .sav format. MBC3 cartridges get the 48 byte RTC footer appended.
+  pub fn export_sav(&self) -> Vec<u8> {
+    let unix_timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or(0);
+    let mut sav = self.rom.ram().to_vec();
+    if let Some(footer) = self.rom.rtc_footer(unix_timestamp) {
+      sav.extend_from_slice(&footer);
+    }
+    sav
+  }
+
+  pub fn import_sav(&mut self, sav: &[u8]) {
+    let ram_length = self.rom.ram().len().min(sav.len());
+    self.rom.load_ram(&sav[..ram_length]);
+    self.rom.load_rtc_footer(&sav[ram_length..]);
+  }
+
+  pub fn state_hash(&self) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    self.get_state().unwrap().hash(&mut hasher);
+    hasher.finish()
+  }
+
+  // Lists the registers and memory locations whose values differ between this emulator and the other one
+  pub fn diff_state(&self, other: &Self) -> Vec<StateDiff> {
+    let mut diffs: Vec<StateDiff> = self.debug_state().registers().into_iter()
+      .zip(other.debug_state().registers())
+      .filter(|((_, left), (_, right))| left != right)
+      .map(|((name, left), (_, right))| StateDiff::Register { name, left, right })
+      .collect();
+    for bank in 0..2 {
+      diffs.extend(StateDiff::memory_diffs(bank, 0x8000, self.vram.bank(bank), other.vram.bank(bank)));
+    }
+    for (bank, (left, right)) in self.rom.ram().chunks(0x2000).zip(other.rom.ram().chunks(0x2000)).enumerate() {
+      diffs.extend(StateDiff::memory_diffs(bank, 0xA000, left, right));
+    }
+    for bank in 0..8 {
+      let start_address = if bank == 0 { 0xC000 } else { 0xD000 };
+      diffs.extend(StateDiff::memory_diffs(bank, start_address, self.wram.bank(bank), other.wram.bank(bank)));
+    }
+    let read_range = |memory: &dyn Memory, start_address: u16, end_address: u16| -> Vec<u8> {
+      (start_address..=end_address).map(|address| memory.read(address)).collect()
     };
+    diffs.extend(StateDiff::memory_diffs(0, 0xFE00, &read_range(&self.oam, 0xFE00, 0xFE9F), &read_range(&other.oam, 0xFE00, 0xFE9F)));
+    diffs.extend(StateDiff::memory_diffs(0, 0xFF80, &read_range(&self.stack, 0xFF80, 0xFFFE), &read_range(&other.stack, 0xFF80, 0xFFFE)));
+    diffs
+  }
+
+  pub fn enable_rewind(&mut self, frames_back: usize, interval: u32) {
+    self.rewind_buffer = Some(RewindBuffer::new(frames_back, interval));
+  }
+
+  pub fn disable_rewind(&mut self) {
+    self.rewind_buffer = None;
+  }
+
+  pub fn rewind_snapshot_count(&self) -> usize {
+    self.rewind_buffer.as_ref().map_or(0, |rewind_buffer| rewind_buffer.len())
+  }
+
+  pub fn rewind_step(&mut self) -> bool {
+    match self.rewind_buffer.as_mut().and_then(|rewind_buffer| rewind_buffer.pop()) {
+      Some(snapshot) => {
+        self.load_state(&snapshot);
+        true
+      }
+      None => false
+    }
+  }
+
+  fn frame_completed(&mut self) {
+    self.frame_count += 1;
+    if let Some(rewind_buffer) = self.rewind_buffer.as_mut() {
+      if rewind_buffer.frame_completed() {
+        let snapshot = self.get_state().unwrap();
+        self.rewind_buffer.as_mut().unwrap().push(snapshot);
+      }
+    }
+    self.apply_recorded_input();
+    // The callback is taken out while it runs, so it doesn't get invoked again if it runs the emulator itself
+    if let Some(mut vblank_callback) = self.vblank_callback.take() {
+      vblank_callback(self);
+      if self.vblank_callback.is_none() {
+        self.vblank_callback = Some(vblank_callback);
+      }
+    }
+  }
+
+  // Invoked once per frame at the start of VBlank. This is the place to poll input or present the frame.
+  pub fn set_vblank_callback(&mut self, callback: VBlankCallback<A, R>) {
+    self.vblank_callback = Some(callback);
+  }
+
+  pub fn clear_vblank_callback(&mut self) {
+    self.vblank_callback = None;
+  }
+
+  // Invoked with the line number each time the LCD finishes drawing one of the 144 visible lines
+  pub fn set_scanline_callback(&mut self, callback: ScanlineCallback<A, R>) {
+    self.scanline_callback = Some(callback);
+  }
+
+  pub fn clear_scanline_callback(&mut self) {
+    self.scanline_callback = None;
+  }
+
+  fn line_drawn(&mut self, line: u8) {
+    if let Some(mut scanline_callback) = self.scanline_callback.take() {
+      scanline_callback(line, self);
+      self.scanline_callback = Some(scanline_callback);
+    }
+  }
+
+  // Records every button press and release from here on, with the frame in which it happened
+  pub fn start_recording(&mut self) {
+    self.input_recorder = Some(InputRecorder::new(self.frame_count));
+  }
+
+  pub fn stop_recording(&mut self) -> InputLog {
+    self.input_recorder.take().map_or_else(InputLog::default, |recorder| recorder.finish())
+  }
+
+  // Replays a recorded input log, starting from the current frame. Inputs are applied at the start of the frame
+  // in which they were recorded, so replays are deterministic when inputs were only given in between frames.
+  pub fn play_input_log(&mut self, log: InputLog) {
+    self.input_player = Some(InputPlayer::new(self.frame_count, log));
+    self.apply_recorded_input();
+  }
+
+  fn apply_recorded_input(&mut self) {
+    if let Some(input_player) = self.input_player.as_mut() {
+      let events = input_player.take_due_events(self.frame_count);
+      if input_player.is_finished() {
+        self.input_player = None;
+      }
+      events.into_iter().for_each(|event| if event.pressed {
+        self.press_button(event.button);
+      } else {
+        self.release_button(event.button);
+      });
+    }
+  }
+
+  // Runs until the start of the next VBlank period. When the LCD is off, this runs for at most a frame's worth of cycles.
+  pub fn run_frame(&mut self) {
+    let frame_count = self.frame_count;
+    for _ in 0..MAX_MACHINE_CYCLES_PER_FRAME {
+      self.tick();
+      if self.frame_count != frame_count {
+        break;
+      }
+    }
+  }
+
+  fn create_rom(rom_bytes: &[u8], cartridge_info: &CartridgeInfo) -> Box<dyn MBC> {
+    let cartridge_type = cartridge_info.cartridge_type;
+    let mut rom = Emulator::<A, R>::create_mbc(cartridge_type, cartridge_info.rom_size, cartridge_info.ram_size)
+      .unwrap_or_else(|| {
+        logging::warn(format_args!("Unsupported cartridge type {:?}", cartridge_type));
+        panic!("This emulator currently does not support {:?} cartridges", cartridge_type)
+      });
     rom.load_bytes(0x0000, rom_bytes);
     rom
   }
 
+  // cartridge_info::supported_cartridge_types has to list exactly the type bytes this accepts
+  fn create_mbc(cartridge_type: CartridgeType, rom_size: ROMSize, ram_size: RAMSize) -> Option<Box<dyn MBC>> {
+    match cartridge_type {
+      CartridgeType::MBC => Some(Box::new(MBC0::new(rom_size))),
+      CartridgeType::MBC1 => Some(Box::new(MBC1::new(rom_size, ram_size))),
+      CartridgeType::MBC2 => Some(Box::new(MBC2::new(rom_size))),
+      CartridgeType::MBC3 => Some(Box::new(MBC3::new(rom_size, ram_size))),
+      CartridgeType::MBC5 => Some(Box::new(MBC5::new(rom_size, ram_size))),
+      _ => None
+    }
+  }
+
   pub fn press_button(&mut self, button: Button) {
+    if let Some(input_recorder) = self.input_recorder.as_mut() {
+      input_recorder.record(self.frame_count, button, true);
+    }
     self.button_controller.press_button(button, &mut self.interrupt_controller);
   }
 
   pub fn release_button(&mut self, button: Button) {
+    if let Some(input_recorder) = self.input_recorder.as_mut() {
+      input_recorder.record(self.frame_count, button, false);
+    }
     self.button_controller.release_button(button);
   }
 
+  // Without video, the LCD keeps running and raising interrupts, but nothing is drawn or sent to the renderer
+  pub fn set_video_enabled(&mut self, enabled: bool) {
+    self.lcd.set_headless(!enabled);
+  }
+
+  pub fn set_accessibility_filter(&mut self, filter: Option<AccessibilityFilter>) {
+    self.accessibility_filter = filter.map(PreparedFilter::new);
+  }
+
+  pub fn accessibility_filter(&self) -> Option<AccessibilityFilter> {
+    self.accessibility_filter.map(|filter| filter.filter())
+  }
+
   pub fn set_tile_atlas_rendering_enabled(&mut self, enabled: bool) {
     self.renderer.set_render_target_enabled(RenderTarget::TileAtlas, enabled);
   }
@@ -210,6 +596,10 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
     self.renderer.set_render_target_enabled(RenderTarget::ObjectAtlas, enabled);
   }
 
+  pub fn set_stop_behavior(&mut self, stop_behavior: StopBehavior) {
+    self.stop_behavior = stop_behavior;
+  }
+
   pub fn is_paused(&self) -> bool {
     self.paused
   }
@@ -223,31 +613,231 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
     self.paused = paused;
   }
 
+  // Stops sending anything to the audio driver while the APU keeps running, e.g. to silence fast-forwarding. When
+  // output is enabled again, the driver picks up whatever the channels are playing at that point.
+  pub fn set_audio_output_enabled(&mut self, enabled: bool) {
+    if enabled == self.audio_output_enabled {
+      return;
+    }
+    if enabled {
+      self.audio_controller.resume_output(&mut self.audio_driver);
+    } else {
+      [Channel::CH1, Channel::CH2, Channel::CH3, Channel::CH4].into_iter()
+        .for_each(|channel| self.audio_driver.stop(channel));
+    }
+    self.audio_output_enabled = enabled;
+  }
+
+  pub fn set_audio_buffer_frames(&mut self, frames: usize) {
+    self.audio_driver.set_buffer_frames(frames);
+  }
+
+  // Per channel amplitudes for CH1-CH4, meant for visualizing the audio output
+  pub fn channel_outputs(&self) -> [f32; 4] {
+    self.audio_controller.channel_outputs()
+  }
+
+  // Replaces the register state left behind by the boot ROM with the one of another model
+  pub fn init_for_model(&mut self, model: GbModel) {
+    self.cpu.init_for_model(model);
+  }
+
+  // Tracks which ROM addresses get executed and which ones are read as data, which is useful to find dead code
+  pub fn set_coverage_tracking_enabled(&mut self, enabled: bool) {
+    self.coverage_tracker.set_enabled(enabled);
+    self.cpu.set_memory_access_tracking_enabled(enabled);
+  }
+
+  pub fn coverage(&self) -> &Coverage {
+    self.coverage_tracker.coverage()
+  }
+
+  pub fn reset_coverage(&mut self) {
+    self.coverage_tracker.reset();
+  }
+
+  // Logs every change of the masked bits of the register at the given address, timestamped with the machine cycle
+  pub fn trace_signal(&mut self, address: u16, bit_mask: u8) {
+    self.signal_tracer.trace(address, bit_mask);
+  }
+
+  pub fn stop_signal_tracing(&mut self) {
+    self.signal_tracer.clear();
+  }
+
+  pub fn drain_signal_events(&mut self) -> Vec<SignalEvent> {
+    self.signal_tracer.drain_events()
+  }
+
+  // The OAM indices of the objects that are drawn on the current line, or an empty list outside of Mode 3
+  pub fn current_line_objects(&self) -> Vec<u8> {
+    self.lcd.current_line_objects().iter()
+      .map(|object_reference| object_reference.object_index)
+      .collect()
+  }
+
+  // The frame as drawn so far, as RGB888 row by row. Lines that haven't been drawn yet in this frame show the
+  // background color, i.e. color 0 of background palette 0.
+  pub fn read_partial_frame(&self) -> Vec<u8> {
+    const BYTES_PER_LINE: usize = 160 * 3;
+    let mut frame = self.renderer.read_partial_frame();
+    let drawn_bytes = self.lcd.lines_drawn() as usize * BYTES_PER_LINE;
+    frame.resize(144 * BYTES_PER_LINE, 0xFF);
+    let blank_color = self.lcd.blank_color(&self.cram);
+    let blank_color = match &self.accessibility_filter {
+      Some(filter) => filter.apply(blank_color),
+      None => blank_color,
+    }.to_rgb888();
+    frame[drawn_bytes..].chunks_exact_mut(3)
+      .for_each(|pixel| pixel.copy_from_slice(&[blank_color.red, blank_color.green, blank_color.blue]));
+    frame
+  }
+
+  // Encodes the main render target as a 160x144 PNG. Lines the renderer hasn't kept are white.
+  #[cfg(feature = "png")]
+  pub fn save_screenshot_png<W: std::io::Write>(&self, writer: W) -> Result<(), String> {
+    let mut frame = self.renderer.read_partial_frame();
+    frame.resize(160 * 144 * 3, 0xFF);
+    let mut encoder = png::Encoder::new(writer, 160, 144);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()
+      .and_then(|mut writer| writer.write_image_data(&frame))
+      .map_err(|error| format!("Failed to encode screenshot: {}", error))
+  }
+
+  pub fn timing_stats(&self) -> TimingStats {
+    TimingStats {
+      frames: self.frame_count,
+      ..self.timing_stats
+    }
+  }
+
+  // The bytes the game sent over the serial port since the last call
+  pub fn take_serial_output(&mut self) -> Vec<u8> {
+    self.serial.take_sent_bytes()
+  }
+
+  // Connects the other end of the link cable. To link two emulators, keep each in an Rc<RefCell<_>> and connect each
+  // to an Rc::downgrade of the other, so they don't keep each other alive.
+  pub fn connect_serial_peer(&mut self, peer: Box<dyn SerialPeer>) {
+    self.serial.connect_peer(peer);
+  }
+
+  pub fn disconnect_serial_peer(&mut self) {
+    self.serial.disconnect_peer();
+  }
+
+  // The number of dots until the LCD could next request a STAT or VBlank interrupt, for frontends that schedule
+  // their work around those events. A dot is a quarter of a machine cycle at normal speed. None while the LCD is off.
+  pub fn dots_until_next_lcd_event(&self) -> Option<u32> {
+    self.lcd.dots_until_next_stat_event()
+  }
+
   pub fn cpu_info(&self) -> CPUInfo {
     self.cpu.cpu_info()
   }
 
-  pub fn get_instruction_label(mut self, address: u16) -> String {
-    let memory_bus = MemoryBus {
-      rom: self.rom.borrow_mut(),
-      vram: &mut self.vram,
-      wram: &mut self.wram,
-      reserved_area_1: &mut self.reserved_area_1,
-      oam: &mut self.oam,
-      reserved_area_2: &mut self.reserved_area_2,
-      button_controller: &mut self.button_controller,
-      timer: &mut self.timer,
-      interrupt_controller: &mut self.interrupt_controller,
-      speed_controller: &mut self.speed_controller,
-      audio_controller: &mut self.audio_controller,
-      lcd: &mut self.lcd,
-      dma: &mut self.dma,
-      cram: &mut self.cram,
-      control_registers: &mut self.control_registers,
-      stack: &mut self.stack,
-      unmapped_memory: &mut self.unmapped_memory,
+  pub fn debug_state(&self) -> DebugState {
+    DebugState {
+      cpu: self.cpu_info(),
+      interrupts: InterruptRegisters {
+        ime: self.interrupt_controller.interrupts_enabled(),
+        ie: self.interrupt_controller.read(MemoryAddress::IE),
+        if_: self.interrupt_controller.read(MemoryAddress::IF),
+      },
+      lcd: LCDRegisters {
+        lcdc: self.lcd.read(MemoryAddress::LCDC),
+        stat: self.lcd.read(MemoryAddress::STAT),
+        ly: self.lcd.read(MemoryAddress::LY),
+        lyc: self.lcd.read(MemoryAddress::LYC),
+        scx: self.lcd.read(MemoryAddress::SCX),
+        scy: self.lcd.read(MemoryAddress::SCY),
+        wx: self.lcd.read(MemoryAddress::WX),
+        wy: self.lcd.read(MemoryAddress::WY),
+      },
+      timer: TimerRegisters {
+        div: self.timer.read(MemoryAddress::DIV),
+        tima: self.timer.read(MemoryAddress::TIMA),
+        tma: self.timer.read(MemoryAddress::TMA),
+        tac: self.timer.read(MemoryAddress::TAC),
+      },
+      banks: BankSelection {
+        rom_bank: self.current_rom_bank(),
+        ram_bank: self.current_ram_bank(),
+        rtc_register: self.current_rtc_register(),
+        vram_bank: self.vram.read(MemoryAddress::VBK),
+        wram_bank: self.wram.read(MemoryAddress::SVBK),
+      },
+    }
+  }
+
+  // The cartridge ROM bank that's mapped to 0x4000-0x7FFF
+  pub fn current_rom_bank(&self) -> u16 {
+    self.rom.current_rom_bank()
+  }
+
+  // The cartridge RAM bank that's mapped to 0xA000-0xBFFF, or None while an MBC3 RTC register is mapped there
+  pub fn current_ram_bank(&self) -> Option<u8> {
+    self.rom.current_ram_bank()
+  }
+
+  // The MBC3 RTC register that's mapped to 0xA000-0xBFFF, if any
+  pub fn current_rtc_register(&self) -> Option<u8> {
+    self.rom.rtc_register()
+  }
+
+  // A snapshot of the I/O registers at 0xFF00-0xFF7F, as the CPU would read them
+  pub fn io_registers(&mut self) -> [u8; 128] {
+    let memory_bus = self.memory_bus();
+    let mut registers = [0u8; 128];
+    for (offset, register) in registers.iter_mut().enumerate() {
+      *register = memory_bus.read(0xFF00 + offset as u16);
+    }
+    registers
+  }
+
+  pub fn resolve_address(&self, cpu_address: u16) -> ResolvedAddress {
+    let address = cpu_address as usize;
+    let (region, bank, offset) = match cpu_address {
+      0x0000..=0x7FFF => {
+        let bank = self.rom.rom_bank(cpu_address);
+        (MemoryRegion::ROM, bank, bank * 0x4000 + (address & 0x3FFF))
+      }
+      0x8000..=0x9FFF => {
+        let bank = self.vram.read(MemoryAddress::VBK) as usize;
+        (MemoryRegion::VRAM, bank, bank * 0x2000 + (address & 0x1FFF))
+      }
+      0xA000..=0xBFFF => match self.rom.rtc_register() {
+        // The whole range maps to the one selected clock register, so the offset is its index
+        Some(register) => (MemoryRegion::RTC, register as usize, register as usize - 0x08),
+        None => {
+          let bank = self.rom.ram_bank();
+          let ram_size = self.rom.ram().len();
+          let offset = bank * 0x2000 + (address & 0x1FFF);
+          (MemoryRegion::CartridgeRAM, bank, if ram_size > 0 { offset % ram_size } else { offset })
+        }
+      },
+      // Echo RAM mirrors 0xC000-0xDDFF
+      0xC000..=0xFDFF => {
+        let bank = if address & 0x1FFF < 0x1000 { 0 } else { self.wram.read(MemoryAddress::SVBK) as usize };
+        (MemoryRegion::WRAM, bank, bank * 0x1000 + (address & 0x0FFF))
+      }
+      0xFE00..=0xFE9F => (MemoryRegion::OAM, 0, address - 0xFE00),
+      0xFEA0..=0xFEFF => (MemoryRegion::Unusable, 0, address - 0xFEA0),
+      0xFF00..=0xFF7F => (MemoryRegion::IO, 0, address - 0xFF00),
+      0xFF80..=0xFFFE => (MemoryRegion::HRAM, 0, address - 0xFF80),
+      0xFFFF => (MemoryRegion::IE, 0, 0),
     };
-    InstructionLabelProvider::get_label(&memory_bus, address)
+    ResolvedAddress { region, bank, offset }
+  }
+
+  pub fn debug_state_json(&self) -> String {
+    serde_json::to_string_pretty(&self.debug_state()).unwrap()
+  }
+
+  pub fn get_instruction_label(mut self, address: u16) -> String {
+    InstructionLabelProvider::get_label(&self.memory_bus(), address)
   }
 
   pub fn get_object(&self, object_index: u8) -> OAMObject {
@@ -257,17 +847,154 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
     }, self.lcd.use_8_x_16_tiles())
   }
 
+  pub fn dump_tile_map(&self, tile_map_index: TileMapIndex) -> [u8; 1024] {
+    self.vram.dump_tile_map(tile_map_index)
+  }
+
+  pub fn dump_tile_map_attributes(&self, tile_map_index: TileMapIndex) -> [u8; 1024] {
+    self.vram.dump_tile_map_attributes(tile_map_index)
+  }
+
+  // Advances every component by the given number of T-cycles at normal speed, i.e. PPU dots, rounded to the nearest
+  // whole machine cycle. A machine cycle only lasts 2 dots in double speed mode, so 456 always advances LY by one.
+  // Returns the number of T-cycles that were actually run. Like step_instruction, this is meant for debugging, so it
+  // runs even while the emulator is paused.
+  pub fn step_cycles(&mut self, t_cycles: u32) -> u32 {
+    let mut cycles_run = 0;
+    loop {
+      let cycles_per_machine_cycle = if self.speed_controller.double_speed() { 2 } else { 4 };
+      if 2 * t_cycles.saturating_sub(cycles_run) < cycles_per_machine_cycle {
+        return cycles_run;
+      }
+      self.machine_cycle();
+      cycles_run += cycles_per_machine_cycle;
+    }
+  }
+
+  // Runs until the condition holds, for at most the given number of T-cycles. Returns the number of T-cycles that were
+  // run. Like step_instruction, this runs even while the emulator is paused.
+  pub fn run_until<F: FnMut(&Self) -> bool>(&mut self, mut condition: F, max_cycles: u64) -> Result<u64, TimeoutError> {
+    let mut cycles = 0u64;
+    while !condition(self) {
+      if cycles + 4 > max_cycles {
+        return Err(TimeoutError { max_cycles });
+      }
+      self.machine_cycle();
+      cycles += 4;
+    }
+    Ok(cycles)
+  }
+
+  // Runs a single machine cycle, unless the emulator is paused. Use execute_machine_cycle to run one regardless.
   pub fn tick(&mut self) {
+    if !self.paused {
+      self.machine_cycle();
+    }
+  }
+
+  // Runs until the CPU finishes its current instruction, even while paused. An interrupt dispatch counts as an
+  // instruction. Returns the number of T-cycles that were run.
+  pub fn step_instruction(&mut self) -> u32 {
+    let mut machine_cycles = 0;
+    loop {
+      self.machine_cycle();
+      machine_cycles += 1;
+      if self.cpu.at_instruction_boundary() {
+        return machine_cycles * 4;
+      }
+    }
+  }
+
+  fn handle_stop(&mut self) {
+    let speed_switch_armed = self.speed_controller.read(MemoryAddress::KEY1).get_bit(0);
+    let button_interrupt_pending = (self.interrupt_controller.read(MemoryAddress::IE) & self.interrupt_controller.read(MemoryAddress::IF)).get_bit(4);
+    if speed_switch_armed || button_interrupt_pending {
+      return;
+    }
+    match self.stop_behavior {
+      StopBehavior::HardwareAccurate => {}
+      StopBehavior::TreatAsNop => self.cpu.resume(),
+      StopBehavior::Panic => panic!("STOP executed at {:#06x} without a speed switch armed or a button press pending", self.cpu_info().pc.wrapping_sub(1)),
+    }
+  }
+
+  fn machine_cycle(&mut self) {
+    self.timing_stats.machine_cycles += 1;
     let double_speed = self.speed_controller.double_speed();
+    let was_stopped = self.cpu.stopped();
     {
-      let mut memory_bus = MemoryBus {
+      let (cpu, mut memory_bus) = self.cpu_and_memory_bus();
+      cpu.tick(&mut memory_bus);
+    }
+    if !was_stopped && self.cpu.stopped() {
+      self.handle_stop();
+    }
+    if self.coverage_tracker.enabled() {
+      self.coverage_tracker.track(self.cpu.take_memory_accesses(), self.rom.as_ref());
+    }
+    self.rom.tick(double_speed);
+    self.speed_controller.tick(&mut self.cpu);
+    self.button_controller.tick(&mut self.interrupt_controller);
+    if self.audio_output_enabled {
+      self.audio_controller.tick(&mut self.audio_driver, &self.timer, double_speed);
+    } else {
+      self.audio_controller.tick(&mut NullAudioDriver, &self.timer, double_speed);
+    }
+    // Unless STOP was used to switch speeds, it halts the system clock until a button is pressed
+    if self.cpu.stopped() && !self.speed_controller.switching_speed() {
+      if !was_stopped {
+        self.lcd.draw_blank_screen(&mut self.renderer);
+      }
+      return;
+    }
+    self.timer.tick(&mut self.interrupt_controller);
+    self.serial.tick(&mut self.interrupt_controller);
+    let previous_lcd_mode = self.lcd.get_mode();
+    let previous_lines_drawn = self.lcd.lines_drawn();
+    match &self.accessibility_filter {
+      Some(filter) => {
+        let mut filtered_renderer = FilteredRenderer::new(&mut self.renderer, filter);
+        self.lcd.tick(&self.vram, &self.cram, &self.oam, &mut filtered_renderer, &mut self.interrupt_controller, double_speed);
+      }
+      None => self.lcd.tick(&self.vram, &self.cram, &self.oam, &mut self.renderer, &mut self.interrupt_controller, double_speed),
+    }
+    self.cram.tick(&self.lcd);
+    {
+      let mut dma_memory_bus = DMAMemoryBus {
+        rom: &mut self.rom,
+        vram: &mut self.vram,
+        wram: &mut self.wram,
+        oam: &mut self.oam,
+      };
+      self.dma.tick(&mut dma_memory_bus, &mut self.cpu, &self.lcd, double_speed);
+    }
+    if self.signal_tracer.tracing() {
+      let mut signal_tracer = std::mem::take(&mut self.signal_tracer);
+      signal_tracer.sample(self.timing_stats.machine_cycles, &self.memory_bus());
+      self.signal_tracer = signal_tracer;
+    }
+    if self.lcd.lines_drawn() > previous_lines_drawn {
+      self.line_drawn(self.lcd.lines_drawn() - 1);
+    }
+    if previous_lcd_mode != LCDMode::VBlank && self.lcd.get_mode() == LCDMode::VBlank {
+      self.frame_completed();
+    }
+  }
+
+  fn memory_bus(&mut self) -> MemoryBus<'_> {
+    self.cpu_and_memory_bus().1
+  }
+
+  // The CPU sits outside the bus it's accessing memory through, so both are borrowed from the emulator at once
+  fn cpu_and_memory_bus(&mut self) -> (&mut CPUImpl, MemoryBus<'_>) {
+    (&mut self.cpu, MemoryBus {
         rom: &mut self.rom,
         vram: &mut self.vram,
         wram: &mut self.wram,
-        reserved_area_1: &mut self.reserved_area_1,
         oam: &mut self.oam,
         reserved_area_2: &mut self.reserved_area_2,
         button_controller: &mut self.button_controller,
+        serial: &mut self.serial,
         timer: &mut self.timer,
         interrupt_controller: &mut self.interrupt_controller,
         speed_controller: &mut self.speed_controller,
@@ -278,28 +1005,12 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
         control_registers: &mut self.control_registers,
         stack: &mut self.stack,
         unmapped_memory: &mut self.unmapped_memory,
-      };
-      self.cpu.tick(&mut memory_bus);
-    }
-    self.rom.tick(double_speed);
-    self.speed_controller.tick(&mut self.cpu);
-    self.button_controller.tick(&mut self.interrupt_controller);
-    self.audio_controller.tick(&mut self.audio_driver, &mut self.timer, double_speed);
-    self.timer.tick(&mut self.interrupt_controller);
-    self.lcd.tick(&self.vram, &self.cram, &self.oam, &mut self.renderer, &mut self.interrupt_controller, double_speed);
-    {
-      let mut dma_memory_bus = DMAMemoryBus {
-        rom: &mut self.rom,
-        vram: &mut self.vram,
-        wram: &mut self.wram,
-        oam: &mut self.oam,
-      };
-      self.dma.tick(&mut dma_memory_bus, &mut self.cpu, &self.lcd, double_speed);
-    }
+    })
   }
 
+  // Runs a single machine cycle, even while the emulator is paused
   pub fn execute_machine_cycle(&mut self) {
-    self.tick();
+    self.machine_cycle();
   }
 
   pub fn run_for_nanos(&mut self, nanos: u64) {
@@ -307,9 +1018,998 @@ impl<A: AudioDriver, R: Renderer> Emulator<A, R> {
       let mut remaining_nanos = nanos;
       while remaining_nanos > 0 {
         let double_speed = self.speed_controller.double_speed();
-        remaining_nanos = remaining_nanos.saturating_sub(if double_speed { 500 } else { 1000 });
+        let tick_nanos: u64 = if double_speed { 500 } else { 1000 };
+        self.timing_stats.drift_nanos += tick_nanos.saturating_sub(remaining_nanos);
+        remaining_nanos = remaining_nanos.saturating_sub(tick_nanos);
         self.tick();
       }
     }
   }
-}
\ No newline at end of file
+}
+
+impl<A: AudioDriver, R: Renderer> SerialPeer for Emulator<A, R> {
+  // The peer can clock in the last bit while this emulator isn't ticking, e.g. while STOPped, so the interrupt is
+  // requested straight away
+  fn exchange_bit(&mut self, bit: bool) -> bool {
+    let incoming_bit = self.serial.exchange_bit(bit);
+    self.serial.request_peer_completion_interrupt(&mut self.interrupt_controller);
+    incoming_bit
+  }
+}
+
+fn validate_rom(rom_bytes: &[u8]) -> Result<(), String> {
+  if rom_bytes.len() < 0x0150 {
+    return Err(format!("ROM of {} bytes is too small to contain a cartridge header", rom_bytes.len()));
+  }
+  let cartridge_type_byte = rom_bytes[0x0147];
+  if !cartridge_info::is_supported(cartridge_type_byte) {
+    logging::warn(format_args!("Unsupported cartridge type {:#04x}", cartridge_type_byte));
+    return Err(format!("This emulator currently does not support cartridge type {:#04x}", cartridge_type_byte));
+  }
+  Ok(())
+}
+
+#[derive(Default)]
+pub struct EmulatorBuilder {
+  config: EmulatorConfig,
+  save_ram: Option<Vec<u8>>,
+  rtc: Option<Vec<u8>>,
+  logger: Option<Box<dyn EmulatorLogger>>,
+  color_correction: Option<AccessibilityFilter>,
+}
+
+impl EmulatorBuilder {
+  pub fn new() -> EmulatorBuilder {
+    EmulatorBuilder::default()
+  }
+
+  // Overrides the model that's derived from the cartridge header
+  pub fn model(mut self, model: GbModel) -> Self {
+    self.config.model = Some(model);
+    self
+  }
+
+  // Fills the work RAM with the given value instead of zeroes
+  pub fn ram_init(mut self, value: u8) -> Self {
+    self.config.ram_init = Some(value);
+    self
+  }
+
+  // Cartridge RAM in the .sav format, including the RTC footer for MBC3 cartridges
+  pub fn save_ram(mut self, sav: &[u8]) -> Self {
+    self.save_ram = Some(sav.to_vec());
+    self
+  }
+
+  // An RTC footer in the .sav format, which takes precedence over the one included in the save RAM
+  pub fn rtc(mut self, footer: &[u8]) -> Self {
+    self.rtc = Some(footer.to_vec());
+    self
+  }
+
+  // Like Emulator::set_logger, this installs the logger for every emulator running on the calling thread. It's only
+  // installed once the ROM has been validated, so a failing build leaves the current logger in place.
+  pub fn logger(mut self, logger: Box<dyn EmulatorLogger>) -> Self {
+    self.logger = Some(logger);
+    self
+  }
+
+  // Applies the filter to everything drawn to the main render target, see Emulator::set_accessibility_filter
+  pub fn color_correction(mut self, filter: AccessibilityFilter) -> Self {
+    self.color_correction = Some(filter);
+    self
+  }
+
+  pub fn compatibility_palette(mut self, palette: CompatibilityPalette) -> Self {
+    self.config.compatibility_palette = Some(palette);
+    self
+  }
+
+  // Starts the emulator at the power-on state and runs the given boot ROM before handing off to the cartridge. Both the
+  // 256 byte DMG and the 2304 byte CGB boot ROM are accepted.
+  pub fn boot_rom(mut self, boot_rom: &[u8]) -> Self {
+    self.config.boot_rom = Some(boot_rom.to_vec());
+    self
+  }
+
+  pub fn build<A: AudioDriver, R: Renderer>(self, rom_bytes: &[u8], audio_driver: A, renderer: R) -> Result<Emulator<A, R>, String> {
+    validate_rom(rom_bytes)?;
+    if let Some(boot_rom) = &self.config.boot_rom {
+      if boot_rom.len() != 0x0100 && boot_rom.len() != 0x0900 {
+        return Err(format!("Boot ROM of {} bytes is neither a DMG nor a CGB boot ROM", boot_rom.len()));
+      }
+    }
+    if let Some(logger) = self.logger {
+      logging::set_logger(logger);
+    }
+    let mut emulator = Emulator::create(rom_bytes, audio_driver, renderer, self.config);
+    emulator.set_accessibility_filter(self.color_correction);
+    if let Some(sav) = self.save_ram {
+      emulator.import_sav(&sav);
+    }
+    if let Some(footer) = self.rtc {
+      emulator.rom.load_rtc_footer(&footer);
+    }
+    Ok(emulator)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::{Cell, RefCell};
+  use std::rc::Rc;
+
+  use assert_hex::assert_eq_hex;
+  use mockall::predicate::eq;
+  use test_case::test_case;
+
+  use crate::audio::MockAudioDriver;
+  use crate::internal::infrastructure::logging::test::CapturingLogger;
+  use crate::internal::memory::cram::ColorReference;
+  use crate::renderer::Color;
+
+  use super::*;
+
+  const TICKS_PER_FRAME: usize = 17556;
+
+  // Creates a 32kB ROM without MBC that increments register A in an endless loop
+  fn create_rom_bytes() -> Vec<u8> {
+    let mut rom_bytes = vec![0u8; 0x8000];
+    rom_bytes[0x0100..0x0103].copy_from_slice(&[0x3C, 0x18, 0xFD]); // INC A, JR -3
+    rom_bytes[0x0143] = 0x80;
+    rom_bytes
+  }
+
+  fn create_emulator() -> Emulator<NullAudioDriver, NullRenderer> {
+    Emulator::new(&create_rom_bytes(), NullAudioDriver, NullRenderer)
+  }
+
+  fn run_frames(emulator: &mut Emulator<NullAudioDriver, NullRenderer>, frames: usize) {
+    (0..frames * TICKS_PER_FRAME).for_each(|_| emulator.tick());
+  }
+
+  #[test]
+  fn boot_rom_overlays_cartridge_until_bank_is_written() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0000] = 0xC0;
+    rom_bytes[0x0200] = 0xC2;
+    let mut boot_rom = vec![0u8; 0x0900];
+    boot_rom[0x0000] = 0xB0;
+    boot_rom[0x0100] = 0xB1;
+    boot_rom[0x0200] = 0xB2;
+    let mut emulator = EmulatorBuilder::new().boot_rom(&boot_rom)
+      .build(&rom_bytes, NullAudioDriver, NullRenderer)
+      .unwrap();
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0000);
+    let mut memory_bus = emulator.memory_bus();
+    assert_eq_hex!(memory_bus.read(0x0000), 0xB0);
+    assert_eq_hex!(memory_bus.read(0x0100), rom_bytes[0x0100]);
+    assert_eq_hex!(memory_bus.read(0x0200), 0xB2);
+    memory_bus.write(MemoryAddress::BANK, 0x11);
+    assert_eq_hex!(memory_bus.read(0x0000), 0xC0);
+    assert_eq_hex!(memory_bus.read(0x0100), rom_bytes[0x0100]);
+    assert_eq_hex!(memory_bus.read(0x0200), 0xC2);
+  }
+
+  #[test]
+  fn boot_rom_starts_whole_system_at_power_on_state() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0134..0x013F].copy_from_slice(b"POKEMON RED");
+    rom_bytes[0x0143] = 0x00;
+    rom_bytes[0x014B] = 0x01; // Licensed by Nintendo
+    let emulator = EmulatorBuilder::new().boot_rom(&[0u8; 0x0900])
+      .build(&rom_bytes, NullAudioDriver, NullRenderer)
+      .unwrap();
+    assert_eq_hex!(emulator.audio_controller.read(MemoryAddress::NR52), 0x70);
+    assert_eq_hex!(emulator.control_registers.read(MemoryAddress::KEY0), 0x00);
+    assert_eq_hex!(emulator.control_registers.read(MemoryAddress::BANK), 0x00);
+    assert_eq!(emulator.compatibility_palette_id(), None);
+    let color_reference = ColorReference { color_index: 1, palette_index: 0, foreground: false };
+    assert_eq!(emulator.cram.background_color(color_reference), CRAMImpl::new().background_color(color_reference));
+  }
+
+  #[test]
+  fn boot_rom_of_unexpected_size_is_rejected() {
+    assert!(EmulatorBuilder::new().boot_rom(&[0u8; 0x0200]).build(&create_rom_bytes(), NullAudioDriver, NullRenderer).is_err());
+  }
+
+  #[test]
+  fn boot_rom_stays_mapped_after_loading_state() {
+    let mut boot_rom = vec![0u8; 0x0100];
+    boot_rom[0x0000] = 0xB0;
+    let mut emulator = EmulatorBuilder::new().boot_rom(&boot_rom)
+      .build(&create_rom_bytes(), NullAudioDriver, NullRenderer)
+      .unwrap();
+    let state = emulator.get_state().unwrap();
+    emulator.load_state(&state);
+    assert_eq_hex!(emulator.memory_bus().read(0x0000), 0xB0);
+  }
+
+  #[test]
+  fn loading_cartridge_replaces_rom_and_resets_state() {
+    let mut emulator = create_emulator();
+    run_frames(&mut emulator, 2);
+    emulator.wram.write(0xC000, 0x42);
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0134..0x013A].copy_from_slice(b"SECOND");
+    rom_bytes[0x0143] = 0x00;
+    emulator.load_cartridge(&rom_bytes).unwrap();
+    assert_eq!(emulator.get_cartridge_info().get_title(), "SECOND");
+    assert!(emulator.compatibility_palette_id().is_some());
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0100);
+    assert_eq!(emulator.cpu_info().instructions_executed, 0);
+    assert_eq!(emulator.timing_stats().machine_cycles, 0);
+    assert_eq_hex!(emulator.wram.read(0xC000), 0x00);
+    run_frames(&mut emulator, 1);
+    assert!(emulator.cpu_info().instructions_executed > 0);
+  }
+
+  #[test]
+  fn loading_cartridge_keeps_builder_settings() {
+    let mut emulator = EmulatorBuilder::new()
+      .ram_init(0xAA)
+      .compatibility_palette(CompatibilityPalette::LeftB)
+      .build(&create_rom_bytes(), NullAudioDriver, NullRenderer)
+      .unwrap();
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0143] = 0x00;
+    emulator.load_cartridge(&rom_bytes).unwrap();
+    assert_eq_hex!(emulator.wram.read(0xC000), 0xAA);
+    assert_eq!(emulator.compatibility_palette_id(), Some(CompatibilityPalette::LeftB.id()));
+  }
+
+  #[test]
+  fn loading_unsupported_cartridge_keeps_current_one() {
+    let mut emulator = create_emulator();
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x22;
+    assert!(emulator.load_cartridge(&rom_bytes).is_err());
+    assert!(emulator.load_cartridge(&rom_bytes[..0x0100]).is_err());
+    run_frames(&mut emulator, 1);
+    assert!(emulator.cpu_info().instructions_executed > 0);
+  }
+
+  fn create_permissive_audio_driver() -> MockAudioDriver {
+    let mut audio_driver = MockAudioDriver::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_set_frequency().return_const(());
+    audio_driver.expect_play_pulse().return_const(());
+    audio_driver.expect_stop().return_const(());
+    audio_driver
+  }
+
+  #[test]
+  fn disabled_audio_output_keeps_apu_running_without_driving_the_driver() {
+    let mut emulator = Emulator::new(&create_rom_bytes(), create_permissive_audio_driver(), NullRenderer);
+    emulator.memory_bus().write(MemoryAddress::NR12, 0xF0);
+    emulator.memory_bus().write(MemoryAddress::NR14, 0x80); // Trigger CH1 without a length
+    emulator.memory_bus().write(MemoryAddress::NR22, 0xF0);
+    emulator.memory_bus().write(MemoryAddress::NR24, 0xC0); // Trigger CH2 with a length of 1/4 s
+    emulator.run_frame();
+    assert_eq_hex!(emulator.memory_bus().read(MemoryAddress::NR52) & 0x03, 0x03);
+
+    emulator.audio_driver.checkpoint();
+    emulator.audio_driver.expect_stop().times(4).return_const(());
+    emulator.set_audio_output_enabled(false);
+    emulator.audio_driver.checkpoint();
+    emulator.audio_driver.expect_set_stereo_gain().never();
+    emulator.audio_driver.expect_set_gain().never();
+    emulator.audio_driver.expect_set_frequency().never();
+    emulator.audio_driver.expect_play_pulse().never();
+    emulator.audio_driver.expect_stop().never();
+    (0..20).for_each(|_| emulator.run_frame());
+    // CH2's length timer kept counting down while the output was disabled
+    assert_eq_hex!(emulator.memory_bus().read(MemoryAddress::NR52) & 0x03, 0x01);
+
+    emulator.audio_driver.checkpoint();
+    emulator.audio_driver.expect_set_stereo_gain().return_const(());
+    emulator.audio_driver.expect_set_gain().return_const(());
+    emulator.audio_driver.expect_play_pulse().withf(|channel, _| *channel == Channel::CH1).once().return_const(());
+    emulator.set_audio_output_enabled(true);
+  }
+
+  #[test]
+  fn audio_buffer_size_is_passed_on_to_audio_driver() {
+    let mut audio_driver = MockAudioDriver::new();
+    audio_driver.expect_set_buffer_frames().with(eq(4096)).once().return_const(());
+    let mut emulator = Emulator::new(&create_rom_bytes(), audio_driver, NullRenderer);
+    emulator.set_audio_buffer_frames(4096);
+  }
+
+  #[test]
+  fn io_registers_snapshot_reflects_register_writes() {
+    let mut emulator = create_emulator();
+    run_frames(&mut emulator, 1);
+    let mut memory_bus = emulator.memory_bus();
+    memory_bus.write(MemoryAddress::LCDC, 0x93);
+    memory_bus.write(MemoryAddress::STAT, 0x48);
+    memory_bus.write(MemoryAddress::TAC, 0x05);
+    let io_registers = emulator.io_registers();
+    assert_eq_hex!(io_registers[0x40], 0x93);
+    assert_eq_hex!(io_registers[0x41] & 0x78, 0x48);
+    assert_eq_hex!(io_registers[0x41], emulator.lcd.read(MemoryAddress::STAT));
+    assert_eq_hex!(io_registers[0x07], 0xFD);
+    // Unmapped registers read as 0xFF
+    assert_eq_hex!(io_registers[0x03], 0xFF);
+  }
+
+  #[test]
+  fn paused_emulator_only_advances_by_stepping_instructions() {
+    let mut emulator = create_emulator();
+    emulator.set_paused(true);
+    let cpu_info = emulator.cpu_info();
+    emulator.run_frame();
+    emulator.tick();
+    assert_eq!(emulator.cpu_info().pc, cpu_info.pc);
+    assert_eq!(emulator.timing_stats().machine_cycles, 0);
+    assert_eq!(emulator.step_instruction(), 4); // INC A
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0101);
+    assert_eq_hex!(emulator.cpu_info().af >> 8, (cpu_info.af >> 8) + 1);
+    assert_eq!(emulator.step_instruction(), 12); // JR -3
+    assert_eq_hex!(emulator.cpu_info().pc, 0x0100);
+    assert!(emulator.is_paused());
+    emulator.set_paused(false);
+    emulator.run_frame();
+    assert_eq!(emulator.frame_count, 1);
+  }
+
+  #[test]
+  fn cycle_stepping_runs_while_paused() {
+    let mut emulator = create_emulator();
+    emulator.set_paused(true);
+    assert_eq!(emulator.step_cycles(400), 400);
+    assert_eq!(emulator.timing_stats().machine_cycles, 100);
+    assert_eq!(emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 2, 10_000), Ok(2 * 456 - 400));
+    emulator.execute_machine_cycle();
+    assert_eq!(emulator.timing_stats().machine_cycles, 2 * 114 + 1);
+    assert!(emulator.is_paused());
+  }
+
+  #[test]
+  fn sav_round_trip_preserves_ram_and_rtc() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x10; // MBC3 + TIMER + RAM + BATTERY
+    rom_bytes[0x0149] = 0x03; // 32kB RAM
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    emulator.rom.write(0x0000, 0x0A); // Enable RAM
+    emulator.rom.write(0x4000, 0x03); // Select RAM bank 3
+    emulator.rom.write(0xA123, 0xAB);
+    emulator.rom.write(0x4000, 0x09); // Select RTC minutes
+    emulator.rom.write(0xA000, 17);
+    emulator.rom.write(0x6000, 0x00);
+    emulator.rom.write(0x6000, 0x01); // Latch clock data
+
+    let sav = emulator.export_sav();
+    assert_eq!(sav.len(), 0x8000 + 48);
+
+    let mut loaded_emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    loaded_emulator.import_sav(&sav);
+    loaded_emulator.rom.write(0x0000, 0x0A); // Enable RAM
+    loaded_emulator.rom.write(0x4000, 0x03);
+    assert_eq!(loaded_emulator.rom.read(0xA123), 0xAB);
+    loaded_emulator.rom.write(0x4000, 0x09);
+    assert_eq!(loaded_emulator.rom.read(0xA000), 17);
+    let exported_sav = loaded_emulator.export_sav();
+    assert_eq!(exported_sav[..sav.len() - 8], sav[..sav.len() - 8]);
+  }
+
+  #[test]
+  fn replaying_recorded_input_reproduces_the_run() {
+    let mut emulator = create_emulator();
+    emulator.start_recording();
+    emulator.run_frame();
+    emulator.press_button(Button::A);
+    emulator.run_frame();
+    emulator.run_frame();
+    emulator.release_button(Button::A);
+    emulator.press_button(Button::START);
+    emulator.run_frame();
+    emulator.press_button(Button::DOWN);
+    emulator.run_frame();
+    let log = emulator.stop_recording();
+    assert_eq!(log.events.len(), 4);
+
+    let mut replayed_emulator = create_emulator();
+    replayed_emulator.play_input_log(log);
+    (0..5).for_each(|_| replayed_emulator.run_frame());
+    assert_eq!(replayed_emulator.state_hash(), emulator.state_hash());
+
+    let mut idle_emulator = create_emulator();
+    (0..5).for_each(|_| idle_emulator.run_frame());
+    assert_ne!(idle_emulator.state_hash(), emulator.state_hash());
+  }
+
+  #[test]
+  fn vblank_callback_fires_once_per_frame() {
+    let mut emulator = create_emulator();
+    let invocations = Rc::new(Cell::new(0));
+    let callback_invocations = invocations.clone();
+    emulator.set_vblank_callback(Box::new(move |emulator| {
+      callback_invocations.set(callback_invocations.get() + 1);
+      if callback_invocations.get() == 2 {
+        emulator.press_button(Button::A);
+        emulator.run_frame(); // Running the emulator from within the callback shouldn't invoke it again
+      }
+    }));
+    emulator.run_frame();
+    emulator.button_controller.write(MemoryAddress::P1, 0x10); // Select action buttons
+    assert_eq!(emulator.button_controller.read(MemoryAddress::P1) & 0x01, 0x01);
+    (1..5).for_each(|_| emulator.run_frame());
+    assert_eq!(invocations.get(), 5);
+    emulator.button_controller.write(MemoryAddress::P1, 0x10);
+    assert_eq!(emulator.button_controller.read(MemoryAddress::P1) & 0x01, 0x00);
+  }
+
+  #[test]
+  fn scanline_callback_fires_for_each_visible_line_in_order() {
+    let mut emulator = create_emulator();
+    let lines: Rc<RefCell<Vec<u8>>> = Rc::new(RefCell::new(vec![]));
+    let callback_lines = lines.clone();
+    emulator.set_scanline_callback(Box::new(move |line, emulator| {
+      assert!(emulator.lcd.get_mode() == LCDMode::Mode3);
+      RefCell::borrow_mut(&callback_lines).push(line);
+    }));
+    emulator.run_frame();
+    emulator.run_frame();
+    let expected_lines: Vec<u8> = (0..144).chain(0..144).collect();
+    assert_eq!(*lines.borrow(), expected_lines);
+    emulator.clear_scanline_callback();
+    emulator.run_frame();
+    assert_eq!(lines.borrow().len(), 288);
+  }
+
+  #[test]
+  fn oam_dma_reads_from_selected_rom_bank() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x01; // MBC1
+    rom_bytes[0x0148] = 0x02; // 128kB ROM
+    rom_bytes.resize(0x20000, 0);
+    (0..0xA0).for_each(|offset| rom_bytes[0x8100 + offset] = 0x80 | offset as u8); // Bank 2
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    emulator.memory_bus().write(0x2000, 0x02);
+    emulator.memory_bus().write(MemoryAddress::DMA, 0x41);
+    (0..162).for_each(|_| emulator.tick());
+    (0..0xA0u16).for_each(|offset| {
+      assert_eq_hex!(emulator.oam.read(0xFE00 + offset), 0x80 | offset as u8);
+    });
+  }
+
+  #[test]
+  fn echo_ram_mirrors_work_ram() {
+    let mut emulator = create_emulator();
+    let mut memory_bus = emulator.memory_bus();
+    memory_bus.write(0xE000, 0x12);
+    assert_eq_hex!(memory_bus.read(0xC000), 0x12);
+    memory_bus.write(0xC123, 0x34);
+    assert_eq_hex!(memory_bus.read(0xE123), 0x34);
+    // The upper part of echo RAM follows the selected WRAM bank
+    memory_bus.write(MemoryAddress::SVBK, 3);
+    memory_bus.write(0xF456, 0x56);
+    assert_eq_hex!(memory_bus.read(0xD456), 0x56);
+    memory_bus.write(MemoryAddress::SVBK, 5);
+    assert_eq_hex!(memory_bus.read(0xF456), 0x00);
+    memory_bus.write(0xD456, 0x78);
+    assert_eq_hex!(memory_bus.read(0xF456), 0x78);
+    memory_bus.write(MemoryAddress::SVBK, 3);
+    assert_eq_hex!(memory_bus.read(0xF456), 0x56);
+  }
+
+  #[test]
+  fn oam_dma_from_echo_ram_reads_work_ram() {
+    let mut emulator = create_emulator();
+    (0..0xA0u16).for_each(|offset| emulator.wram.write(0xC100 + offset, 0x40 + offset as u8));
+    emulator.memory_bus().write(MemoryAddress::DMA, 0xE1);
+    (0..162).for_each(|_| emulator.tick());
+    (0..0xA0u16).for_each(|offset| {
+      assert_eq_hex!(emulator.oam.read(0xFE00 + offset), 0x40 + offset as u8);
+    });
+  }
+
+  #[test]
+  fn coverage_marks_executed_and_read_rom_addresses() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0100..0x0105].copy_from_slice(&[0xFA, 0x00, 0x40, 0x18, 0xFB]); // LD A, (0x4000), JR -5
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    assert!(!emulator.coverage().is_executed(0, 0x0100));
+    emulator.set_coverage_tracking_enabled(true);
+    (0..100).for_each(|_| emulator.tick());
+    (0x0100u16..0x0105).for_each(|address| assert!(emulator.coverage().is_executed(0, address), "{:#06x}", address));
+    assert!(!emulator.coverage().is_executed(0, 0x0105));
+    assert!(!emulator.coverage().is_executed(0, 0x0200));
+    assert!(emulator.coverage().is_read(1, 0x4000));
+    assert!(!emulator.coverage().is_executed(1, 0x4000));
+    assert_eq!(emulator.coverage().executed_byte_count(), 5);
+  }
+
+  #[test]
+  fn run_until_stops_when_condition_holds() {
+    let mut emulator = create_emulator();
+    let cycles = emulator.run_until(|emulator| emulator.debug_state().lcd.ly == 80, 70224);
+    assert_eq!(cycles, Ok(80 * 456));
+    assert_eq!(emulator.run_until(|emulator| emulator.debug_state().lcd.ly == 80, 0), Ok(0));
+  }
+
+  #[test]
+  fn run_until_times_out() {
+    let mut emulator = create_emulator();
+    let result = emulator.run_until(|emulator| emulator.cpu_info().pc == 0x5000, 1000);
+    assert_eq!(result, Err(TimeoutError { max_cycles: 1000 }));
+  }
+
+  #[test]
+  fn diff_state_reports_poked_wram_byte() {
+    let mut emulator = create_emulator();
+    run_frames(&mut emulator, 1);
+    let state = emulator.get_state().unwrap();
+    let mut other_emulator = create_emulator();
+    other_emulator.load_state(&state);
+    assert!(emulator.diff_state(&other_emulator).is_empty());
+
+    other_emulator.wram.write(MemoryAddress::SVBK, 3);
+    other_emulator.wram.write(0xD456, 0x42);
+    other_emulator.wram.write(MemoryAddress::SVBK, 1);
+    assert_eq!(emulator.diff_state(&other_emulator), vec![StateDiff::Memory { bank: 3, address: 0xD456, left: 0x00, right: 0x42 }]);
+  }
+
+  // Keeps the main render target as RGB888, filled with a marker value until pixels are drawn
+  struct FrameBufferRenderer {
+    frame: Vec<u8>,
+  }
+
+  impl Renderer for FrameBufferRenderer {
+    fn render_target_is_enabled(&self, target: RenderTarget) -> bool { target == RenderTarget::Main }
+    fn set_render_target_enabled(&mut self, _target: RenderTarget, _enabled: bool) {}
+    fn draw_pixel(&mut self, x: usize, y: usize, _z: u8, color: Color, _target: RenderTarget) {
+      let color = color.to_rgb888();
+      let offset = 3 * (160 * y + x);
+      self.frame[offset..offset + 3].copy_from_slice(&[color.red, color.green, color.blue]);
+    }
+    fn flush(&mut self) {}
+    fn read_partial_frame(&self) -> Vec<u8> { self.frame.clone() }
+  }
+
+  #[test]
+  fn partial_frame_blanks_lines_not_drawn_yet() {
+    let renderer = FrameBufferRenderer { frame: vec![0x12; 160 * 144 * 3] };
+    let mut emulator = Emulator::new(&create_rom_bytes(), NullAudioDriver, renderer);
+    emulator.cram.write(MemoryAddress::BCPS, 0x80);
+    emulator.cram.write(MemoryAddress::BCPD, 0x00);
+    emulator.cram.write(MemoryAddress::BCPD, 0x00); // Background color 0 of palette 0 is black
+    emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 144, 1_000_000).unwrap();
+    emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 10, 1_000_000).unwrap();
+    emulator.cram.write(MemoryAddress::BCPS, 0x80);
+    emulator.cram.write(MemoryAddress::BCPD, 0x1F);
+    emulator.cram.write(MemoryAddress::BCPD, 0x00); // Background color 0 of palette 0 is now red
+    let frame = emulator.read_partial_frame();
+    assert_eq!(frame.len(), 160 * 144 * 3);
+    assert!(frame[..10 * 160 * 3].iter().all(|&byte| byte == 0x00));
+    assert!(frame[10 * 160 * 3..].chunks_exact(3).all(|pixel| pixel == [0xFF, 0x00, 0x00]));
+  }
+
+  #[cfg(feature = "png")]
+  #[test]
+  fn screenshot_is_encoded_as_png() {
+    let renderer = FrameBufferRenderer { frame: [0x12, 0x34, 0x56].repeat(160 * 144) };
+    let emulator = Emulator::new(&create_rom_bytes(), NullAudioDriver, renderer);
+    let mut png_bytes = Vec::new();
+    emulator.save_screenshot_png(&mut png_bytes).unwrap();
+    let mut reader = png::Decoder::new(png_bytes.as_slice()).read_info().unwrap();
+    let mut frame = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut frame).unwrap();
+    assert_eq!((info.width, info.height), (160, 144));
+    let offset = 3 * (160 * 100 + 80);
+    assert_eq!(frame[offset..offset + 3], [0x12, 0x34, 0x56]);
+  }
+
+  #[test_case(LCDMode::HBlank; "hblank")]
+  #[test_case(LCDMode::VBlank; "vblank")]
+  fn vram_and_oam_are_accessible_outside_of_drawing(mode: LCDMode) {
+    let mut emulator = create_emulator();
+    emulator.run_until(|emulator| emulator.lcd.get_mode() == mode, 1_000_000).unwrap();
+    let mut memory_bus = emulator.memory_bus();
+    for (address, value) in [(0x8000, 0x12), (0x9FFF, 0x34), (0xFE00, 0x56), (0xFE9F, 0x78)] {
+      memory_bus.write(address, value);
+      assert_eq_hex!(memory_bus.read(address), value, "Address {:#06x}", address);
+    }
+    assert!(emulator.lcd.get_mode() == mode);
+    assert_eq_hex!(emulator.vram.read(0x8000), 0x12);
+    assert_eq_hex!(emulator.oam.read(0xFE9F), 0x78);
+  }
+
+  #[test]
+  fn signal_tracer_logs_stat_mode_changes_over_a_line() {
+    let mut emulator = create_emulator();
+    emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 10, 1_000_000).unwrap();
+    emulator.trace_signal(MemoryAddress::STAT, 0x03);
+    emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 12, 1_000_000).unwrap();
+    let events = emulator.drain_signal_events();
+    // Start from the OAM scan of line 11, with 4 dots per machine cycle
+    let line_start = events.iter().find(|event| event.value == 0x02).unwrap().machine_cycle;
+    let transitions: Vec<(u64, u8, u8)> = events.iter()
+      .filter(|event| event.machine_cycle > line_start)
+      .map(|event| (4 * (event.machine_cycle - line_start), event.previous_value, event.value))
+      .collect();
+    assert_eq!(transitions, vec![(80, 0x02, 0x03), (248, 0x03, 0x00), (456, 0x00, 0x02)]);
+    assert!(events.iter().all(|event| event.address == MemoryAddress::STAT && event.bit_mask == 0x03));
+    emulator.stop_signal_tracing();
+    emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 13, 1_000_000).unwrap();
+    assert!(emulator.drain_signal_events().is_empty());
+  }
+
+  #[test]
+  fn accessibility_filter_maps_drawn_colors() {
+    let renderer = FrameBufferRenderer { frame: vec![0x12; 160 * 144 * 3] };
+    let mut emulator = Emulator::new(&create_rom_bytes(), NullAudioDriver, renderer);
+    emulator.cram.write(MemoryAddress::BCPS, 0x80);
+    emulator.cram.write(MemoryAddress::BCPD, 0x1F);
+    emulator.cram.write(MemoryAddress::BCPD, 0x00); // Background color 0 of palette 0 is red
+    let first_pixel = |emulator: &mut Emulator<NullAudioDriver, FrameBufferRenderer>| {
+      emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 144, 1_000_000).unwrap();
+      emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 0, 1_000_000).unwrap();
+      emulator.renderer.frame[..3].to_vec()
+    };
+    let red = Color::from_rgb(0x1F, 0x00, 0x00);
+    assert_eq!(first_pixel(&mut emulator), vec![0xFF, 0x00, 0x00]);
+
+    emulator.set_accessibility_filter(Some(AccessibilityFilter::Protanopia));
+    let filtered_red = AccessibilityFilter::Protanopia.apply(red);
+    assert_eq!(filtered_red.red, 0x1F); // Protanopia keeps the red channel but adds green and blue
+    assert!(filtered_red.green > 0 && filtered_red.blue > 0);
+    let filtered_red = filtered_red.to_rgb888();
+    assert_eq!(first_pixel(&mut emulator), vec![filtered_red.red, filtered_red.green, filtered_red.blue]);
+
+    emulator.set_accessibility_filter(None);
+    assert_eq!(first_pixel(&mut emulator), vec![0xFF, 0x00, 0x00]);
+  }
+
+  #[test]
+  fn accessibility_filters_keep_greys_and_transparency() {
+    let filters = [AccessibilityFilter::Protanopia, AccessibilityFilter::Deuteranopia, AccessibilityFilter::Tritanopia];
+    for filter in filters {
+      for grey in [Color::black(), Color::dark_grey(), Color::light_grey(), Color::white()] {
+        assert_eq!(filter.apply(grey), grey, "{:?}", filter);
+      }
+      assert_eq!(filter.apply(Color::transparent()), Color::transparent());
+    }
+    assert_eq!(AccessibilityFilter::HighContrast.apply(Color::from_rgb(0x04, 0x0C, 0x14)), Color::from_rgb(0x00, 0x08, 0x18));
+    assert_eq!(AccessibilityFilter::HighContrast.apply(Color::white()), Color::white());
+  }
+
+  #[test]
+  fn timing_stats_match_emulated_time() {
+    let mut emulator = create_emulator();
+    emulator.run_for_nanos(1_000_000_000);
+    let stats = emulator.timing_stats();
+    assert_eq!(stats.machine_cycles, 1_000_000);
+    assert_eq!(stats.drift_nanos, 0);
+    // The first frame completes once the LCD first enters VBlank, 144 lines in
+    assert_eq!(stats.frames, (stats.machine_cycles + (154 - 144) * 114) / TICKS_PER_FRAME as u64);
+    assert_eq!(stats.frames, emulator.frame_count);
+
+    emulator.run_for_nanos(2_500);
+    let stats = emulator.timing_stats();
+    assert_eq!(stats.machine_cycles, 1_000_003);
+    assert_eq!(stats.drift_nanos, 500);
+  }
+
+  #[test]
+  fn builder_applies_options() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x03; // MBC1 + RAM + BATTERY
+    rom_bytes[0x0149] = 0x03; // 32kB RAM
+    let mut sav = vec![0u8; 0x8000];
+    sav[0x0010] = 0x5A;
+    let mut emulator = EmulatorBuilder::new()
+      .model(GbModel::Dmg)
+      .ram_init(0xFF)
+      .save_ram(&sav)
+      .color_correction(AccessibilityFilter::Deuteranopia)
+      .build(&rom_bytes, NullAudioDriver, NullRenderer)
+      .unwrap();
+    assert_eq!(emulator.cpu_info().af, 0x01B0);
+    assert_eq!(emulator.accessibility_filter(), Some(AccessibilityFilter::Deuteranopia));
+    assert_eq!(emulator.wram.read(0xC000), 0xFF);
+    assert_eq!(emulator.wram.read(0xDFFF), 0xFF);
+    emulator.rom.write(0x0000, 0x0A); // Enable RAM
+    assert_eq!(emulator.rom.read(0xA010), 0x5A);
+  }
+
+  #[test]
+  fn builder_rejects_unsupported_cartridges() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x22; // MBC7
+    assert!(EmulatorBuilder::new().build(&rom_bytes, NullAudioDriver, NullRenderer).is_err());
+    assert!(EmulatorBuilder::new().build(&rom_bytes[..0x0100], NullAudioDriver, NullRenderer).is_err());
+  }
+
+  #[test]
+  fn failing_build_keeps_current_logger() {
+    let current_logger = CapturingLogger::default();
+    let builder_logger = CapturingLogger::default();
+    logging::set_logger(Box::new(current_logger.clone()));
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x22; // MBC7
+    assert!(EmulatorBuilder::new()
+      .logger(Box::new(builder_logger.clone()))
+      .build(&rom_bytes, NullAudioDriver, NullRenderer)
+      .is_err());
+    assert!(EmulatorBuilder::new()
+      .logger(Box::new(builder_logger.clone()))
+      .boot_rom(&[0u8; 0x0200])
+      .build(&create_rom_bytes(), NullAudioDriver, NullRenderer)
+      .is_err());
+    logging::warn(format_args!("After failed builds"));
+    assert!(builder_logger.messages.borrow().is_empty());
+    assert_eq!(current_logger.messages.borrow().last().unwrap(), &("warn", String::from("After failed builds")));
+  }
+
+  #[test]
+  fn stepping_a_scanline_of_cycles_advances_ly_by_one() {
+    let mut emulator = create_emulator();
+    run_frames(&mut emulator, 1);
+    assert_eq!(emulator.step_cycles(100), 100);
+    let line = emulator.lcd.read(MemoryAddress::LY);
+    assert_eq!(emulator.step_cycles(456), 456);
+    assert_eq!(emulator.lcd.read(MemoryAddress::LY), line + 1);
+    assert_eq!(emulator.step_cycles(6), 8);
+    assert_eq!(emulator.step_cycles(1), 0);
+  }
+
+  #[test]
+  fn compatibility_palette_can_be_overridden() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0134..0x013F].copy_from_slice(b"POKEMON RED");
+    rom_bytes[0x0143] = 0x00;
+    rom_bytes[0x014B] = 0x01; // Licensed by Nintendo
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    assert_eq!(emulator.compatibility_palette_id(), Some(0x30));
+    let color_reference = ColorReference { color_index: 1, palette_index: 0, foreground: false };
+    assert_eq!(emulator.cram.background_color(color_reference), Color::from_rgb(0x1F, 0x10, 0x10));
+    emulator.set_compatibility_palette(CompatibilityPalette::LeftB);
+    assert_eq!(emulator.compatibility_palette_id(), Some(0x16));
+    assert_eq!(emulator.cram.background_color(color_reference), Color::from_rgb(0x14, 0x14, 0x14));
+  }
+
+  #[test]
+  fn color_games_have_no_compatibility_palette() {
+    let mut emulator = create_emulator();
+    emulator.set_compatibility_palette(CompatibilityPalette::Up);
+    assert_eq!(emulator.compatibility_palette_id(), None);
+  }
+
+  #[test]
+  fn stop_resets_div_until_button_is_pressed() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0100..0x010B].copy_from_slice(&[
+      0x06, 0xFF, // LD B, 0xFF
+      0x05, // DEC B
+      0x20, 0xFD, // JR NZ, -3
+      0xFB, // EI
+      0x10, 0x00, // STOP
+      0x3C, // INC A
+      0x18, 0xFD, // JR -3
+    ]);
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    emulator.interrupt_controller.write(MemoryAddress::IE, 0x10);
+    emulator.button_controller.write(MemoryAddress::P1, 0x10); // Select action buttons
+    (0..1000).for_each(|_| emulator.tick());
+    assert_ne!(emulator.timer.read(MemoryAddress::DIV), 0);
+    (0..2000).for_each(|_| emulator.tick());
+    assert!(emulator.cpu.stopped());
+    assert_eq!(emulator.timer.read(MemoryAddress::DIV), 0);
+    emulator.press_button(Button::A);
+    (0..10).for_each(|_| emulator.tick());
+    assert!(!emulator.cpu.stopped());
+    assert_eq!(emulator.cpu_info().pc & 0xFFF0, 0x0060);
+  }
+
+  fn create_stop_rom_bytes() -> Vec<u8> {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0100..0x0105].copy_from_slice(&[
+      0x10, 0x00, // STOP
+      0x3C, // INC A
+      0x18, 0xFD, // JR -3
+    ]);
+    rom_bytes
+  }
+
+  #[test]
+  fn hardware_accurate_stop_waits_for_button_press() {
+    let mut emulator = Emulator::new(&create_stop_rom_bytes(), NullAudioDriver, NullRenderer);
+    let a = emulator.cpu_info().af >> 8;
+    (0..100).for_each(|_| emulator.tick());
+    assert!(emulator.cpu.stopped());
+    assert_eq!(emulator.cpu_info().af >> 8, a);
+  }
+
+  #[test]
+  fn stop_treated_as_nop_keeps_executing_next_instructions() {
+    let mut emulator = Emulator::new(&create_stop_rom_bytes(), NullAudioDriver, NullRenderer);
+    emulator.set_stop_behavior(StopBehavior::TreatAsNop);
+    let a = emulator.cpu_info().af >> 8;
+    (0..100).for_each(|_| emulator.tick());
+    assert!(!emulator.cpu.stopped());
+    assert_ne!(emulator.cpu_info().af >> 8, a);
+    assert!((0x0102..=0x0105).contains(&emulator.cpu_info().pc));
+  }
+
+  #[test]
+  #[should_panic(expected = "STOP executed at 0x0100")]
+  fn stop_panics_when_configured_to() {
+    let mut emulator = Emulator::new(&create_stop_rom_bytes(), NullAudioDriver, NullRenderer);
+    emulator.set_stop_behavior(StopBehavior::Panic);
+    (0..100).for_each(|_| emulator.tick());
+  }
+
+  #[test]
+  fn speed_switch_stalls_cpu_while_system_keeps_running() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0100..0x0109].copy_from_slice(&[
+      0x3E, 0x01, // LD A, 0x01
+      0xE0, 0x4D, // LDH (KEY1), A
+      0x10, 0x00, // STOP
+      0x04, // INC B
+      0x18, 0xFD, // JR -3
+    ]);
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    while !emulator.cpu.stopped() {
+      emulator.tick();
+    }
+    let ly = emulator.lcd.read(MemoryAddress::LY);
+    let mut stalled_cycles = 0;
+    while emulator.cpu.stopped() && stalled_cycles < 10000 {
+      emulator.tick();
+      stalled_cycles += 1;
+    }
+    assert_eq!(stalled_cycles, 2050);
+    assert_ne!(emulator.timer.read(MemoryAddress::DIV), 0);
+    assert_ne!(emulator.lcd.read(MemoryAddress::LY), ly);
+    assert_eq!(emulator.speed_controller.read(MemoryAddress::KEY1), 0x80);
+    let b = emulator.cpu_info().bc >> 8;
+    (0..100).for_each(|_| emulator.tick());
+    assert_ne!(emulator.cpu_info().bc >> 8, b);
+  }
+
+  #[test]
+  fn stepping_a_line_of_cycles_advances_ly_by_one_in_double_speed() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0100..0x0108].copy_from_slice(&[
+      0x3E, 0x01, // LD A, 0x01
+      0xE0, 0x4D, // LDH (KEY1), A
+      0x10, 0x00, // STOP
+      0x18, 0xFE, // JR -2
+    ]);
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    emulator.run_until(|emulator| emulator.speed_controller.double_speed() && !emulator.cpu.stopped(), 100_000).unwrap();
+    let ly = emulator.lcd.read(MemoryAddress::LY);
+    emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) != ly, 100_000).unwrap();
+    let ly = emulator.lcd.read(MemoryAddress::LY);
+    assert_eq!(emulator.step_cycles(456), 456);
+    assert_eq!(emulator.lcd.read(MemoryAddress::LY), (ly + 1) % 154);
+    // Machine cycles last 2 T-cycles, so odd counts round up like 2 does at normal speed
+    assert_eq!(emulator.step_cycles(1), 2);
+    assert_eq!(emulator.step_cycles(6), 6);
+  }
+
+  #[test]
+  fn linked_emulator_requests_interrupt_as_soon_as_peer_completes_transfer() {
+    let master = Rc::new(RefCell::new(create_emulator()));
+    let slave = Rc::new(RefCell::new(create_emulator()));
+    RefCell::borrow_mut(&master).connect_serial_peer(Box::new(Rc::downgrade(&slave)));
+    RefCell::borrow_mut(&slave).connect_serial_peer(Box::new(Rc::downgrade(&master)));
+    RefCell::borrow_mut(&slave).serial.write(MemoryAddress::SB, 0x99);
+    RefCell::borrow_mut(&slave).serial.write(MemoryAddress::SC, 0x80);
+    RefCell::borrow_mut(&master).serial.write(MemoryAddress::SB, 0x42);
+    RefCell::borrow_mut(&master).serial.write(MemoryAddress::SC, 0x81);
+    for _ in 0..(8 * 128) {
+      RefCell::borrow_mut(&master).tick();
+    }
+    let slave = slave.borrow();
+    assert_eq!(slave.serial.read(MemoryAddress::SB), 0x42);
+    assert!(slave.interrupt_controller.read(MemoryAddress::IF).get_bit(3));
+    assert_eq!(master.borrow().serial.read(MemoryAddress::SB), 0x99);
+  }
+
+  #[test]
+  fn supported_cartridge_types_match_created_mbcs() {
+    for cartridge_type_byte in 0..=0xFFu8 {
+      let can_create = CartridgeType::try_from_byte(cartridge_type_byte)
+        .and_then(|cartridge_type| Emulator::<NullAudioDriver, NullRenderer>::create_mbc(cartridge_type, ROMSize::KB32, RAMSize::KB8))
+        .is_some();
+      assert_eq!(can_create, cartridge_info::is_supported(cartridge_type_byte), "{:#04x}", cartridge_type_byte);
+    }
+  }
+
+  #[test]
+  fn next_lcd_event_is_when_stat_interrupt_is_requested() {
+    let mut emulator = create_emulator();
+    emulator.run_until(|emulator| emulator.lcd.read(MemoryAddress::LY) == 10, 1_000_000).unwrap();
+    emulator.lcd.write(MemoryAddress::STAT, 0x08); // HBlank interrupt
+    emulator.interrupt_controller.write(MemoryAddress::IF, 0x00);
+    let machine_cycles = (emulator.dots_until_next_lcd_event().unwrap() + 3) / 4;
+    (1..machine_cycles).for_each(|_| emulator.tick());
+    assert!(!emulator.interrupt_controller.read(MemoryAddress::IF).get_bit(1));
+    emulator.tick();
+    assert!(emulator.interrupt_controller.read(MemoryAddress::IF).get_bit(1));
+  }
+
+  #[test]
+  fn resolve_address_follows_selected_rom_bank() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x19; // MBC5
+    rom_bytes[0x0148] = 0x02; // 128 KiB
+    rom_bytes.resize(0x20000, 0);
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    assert_eq!(emulator.resolve_address(0x1ABC), ResolvedAddress { region: MemoryRegion::ROM, bank: 0, offset: 0x1ABC });
+    emulator.rom.write(0x2000, 0x05);
+    assert_eq!(emulator.resolve_address(0x5ABC), ResolvedAddress { region: MemoryRegion::ROM, bank: 5, offset: 0x15ABC });
+    emulator.rom.write(0x2000, 0x02);
+    assert_eq!(emulator.resolve_address(0x5ABC), ResolvedAddress { region: MemoryRegion::ROM, bank: 2, offset: 0x9ABC });
+    assert_eq!(emulator.resolve_address(0x1ABC), ResolvedAddress { region: MemoryRegion::ROM, bank: 0, offset: 0x1ABC });
+  }
+
+  #[test]
+  fn resolve_address_reports_selected_rtc_register() {
+    let mut rom_bytes = create_rom_bytes();
+    rom_bytes[0x0147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+    rom_bytes[0x0149] = 0x03; // 32 KiB
+    let mut emulator = Emulator::new(&rom_bytes, NullAudioDriver, NullRenderer);
+    emulator.rom.write(0x4000, 0x02);
+    assert_eq!(emulator.resolve_address(0xA123), ResolvedAddress { region: MemoryRegion::CartridgeRAM, bank: 2, offset: 0x4123 });
+    emulator.rom.write(0x4000, 0x0A); // RTC hours
+    assert_eq!(emulator.resolve_address(0xA123), ResolvedAddress { region: MemoryRegion::RTC, bank: 0x0A, offset: 2 });
+    assert_eq!(emulator.resolve_address(0xBFFF), ResolvedAddress { region: MemoryRegion::RTC, bank: 0x0A, offset: 2 });
+  }
+
+  #[test]
+  fn resolve_address_follows_selected_wram_bank() {
+    let mut emulator = create_emulator();
+    assert_eq!(emulator.resolve_address(0xD123), ResolvedAddress { region: MemoryRegion::WRAM, bank: 1, offset: 0x1123 });
+    emulator.wram.write(MemoryAddress::SVBK, 0x05);
+    assert_eq!(emulator.resolve_address(0xD123), ResolvedAddress { region: MemoryRegion::WRAM, bank: 5, offset: 0x5123 });
+    assert_eq!(emulator.resolve_address(0xC123), ResolvedAddress { region: MemoryRegion::WRAM, bank: 0, offset: 0x0123 });
+    assert_eq!(emulator.resolve_address(0xF123), ResolvedAddress { region: MemoryRegion::WRAM, bank: 5, offset: 0x5123 });
+    assert_eq!(emulator.resolve_address(0xFF80), ResolvedAddress { region: MemoryRegion::HRAM, bank: 0, offset: 0 });
+  }
+
+  #[test]
+  fn debug_state_json_contains_registers() {
+    let mut emulator = create_emulator();
+    run_frames(&mut emulator, 3);
+    (0..150).for_each(|_| emulator.tick()); // Advance to line 1
+    let debug_state: serde_json::Value = serde_json::from_str(&emulator.debug_state_json()).unwrap();
+    assert_eq!(debug_state["cpu"]["pc"], emulator.cpu_info().pc);
+    assert_eq!(debug_state["lcd"]["ly"], 1);
+    assert_eq!(debug_state["interrupts"]["if"], emulator.interrupt_controller.read(MemoryAddress::IF));
+    assert_eq!(debug_state["banks"]["wram_bank"], emulator.wram.read(MemoryAddress::SVBK));
+  }
+
+  #[test]
+  fn rewind_restores_earlier_state() {
+    let mut emulator = create_emulator();
+    emulator.enable_rewind(10, 1);
+    while emulator.rewind_snapshot_count() == 0 {
+      emulator.tick();
+    }
+    let first_snapshot_hash = emulator.state_hash();
+    run_frames(&mut emulator, 3);
+    assert_eq!(emulator.rewind_snapshot_count(), 4);
+    assert_ne!(emulator.state_hash(), first_snapshot_hash);
+    assert!(emulator.rewind_step());
+    assert!(emulator.rewind_step());
+    assert!(emulator.rewind_step());
+    assert!(emulator.rewind_step());
+    assert_eq!(emulator.state_hash(), first_snapshot_hash);
+    assert!(!emulator.rewind_step());
+  }
+
+  #[test]
+  fn rewind_step_without_rewind_enabled_does_nothing() {
+    let mut emulator = create_emulator();
+    run_frames(&mut emulator, 1);
+    let state_hash = emulator.state_hash();
+    assert!(!emulator.rewind_step());
+    assert_eq!(emulator.state_hash(), state_hash);
+  }
+}
@@ -1,9 +1,11 @@
+use serde::{Deserialize, Serialize};
+
 pub enum ButtonType {
     ACTION,
     DIRECTION,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Button {
     A,
     B,
@@ -41,4 +43,17 @@ impl Button {
             Button::DOWN => ButtonType::DIRECTION
         }
     }
-}
\ No newline at end of file
+}
+
+// A button press or release, relative to the frame in which the recording started
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputLog {
+    pub events: Vec<InputEvent>,
+}
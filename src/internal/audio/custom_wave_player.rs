@@ -47,12 +47,22 @@ impl CustomWavePlayer {
     audio_driver.stop(self.channel);
   }
 
-  pub fn get_lower_wavelength_bits(&self) -> u8 {
-    (self.wavelength & 0xFF) as u8
+  pub fn resume(&self, audio_driver: &mut dyn AudioDriver) {
+    if self.playing {
+      audio_driver.set_frequency(self.channel, self.frequency());
+      audio_driver.set_gain(self.channel, self.gain_ratio());
+      self.play_custom_wave(audio_driver);
+    }
+  }
+
+  fn frequency(&self) -> f32 {
+    65536.0f32 / (2048.0 - self.wavelength as f32)
   }
 
-  pub fn get_upper_wavelength_bits(&self) -> u8 {
-    ((self.wavelength & 0xFF00) >> 8) as u8
+  fn play_custom_wave(&self, audio_driver: &mut dyn AudioDriver) {
+    audio_driver.play_custom_wave(self.channel, CustomWaveOptions {
+      data: self.waveform,
+    });
   }
 
   pub fn set_lower_wavelength_bits(&mut self, value: u8) {
@@ -74,31 +84,36 @@ impl CustomWavePlayer {
     if enabled != self.dac_enabled {
       self.dac_enabled_changed.set();
     }
+    // Turning the DAC off disables the channel right away, turning it back on needs a trigger to restart it
+    if !enabled {
+      self.playing = false;
+    }
     self.dac_enabled = enabled;
   }
 
+  // NR32 right-shifts the 4 bit samples by 0, 1 or 2 bits, which the audio driver applies as a gain on the waveform
+  pub fn gain_ratio(&self) -> f32 {
+    match self.gain {
+      1 => 1.0f32,
+      2 => 0.5f32,
+      3 => 0.25f32,
+      _ => 0.0f32,
+    }
+  }
+
   pub fn tick(&mut self, audio_driver: &mut dyn AudioDriver) -> CustomWavePlayerTickResult {
     if self.dac_enabled_changed.get_and_clear() && !self.dac_enabled {
       return CustomWavePlayerTickResult::DacShutOff;
     }
     if self.frequency_changed.get_and_clear() {
-      let frequency = 65536.0f32 / (2048.0 - self.wavelength as f32);
-      audio_driver.set_frequency(self.channel, frequency);
+      audio_driver.set_frequency(self.channel, self.frequency());
     }
     if self.gain_changed.get_and_clear() {
-      let gain = match self.gain {
-        1 => 1.0f32,
-        2 => 0.5f32,
-        3 => 0.25f32,
-        _ => 0.0f32,
-      };
-      audio_driver.set_gain(self.channel, gain);
+      audio_driver.set_gain(self.channel, self.gain_ratio());
     }
-    if self.triggered.get_and_clear() {
+    if self.triggered.get_and_clear() && self.dac_enabled {
       self.playing = true;
-      audio_driver.play_custom_wave(self.channel, CustomWaveOptions {
-        data: self.waveform,
-      });
+      self.play_custom_wave(audio_driver);
     }
     CustomWavePlayerTickResult::Ok
   }
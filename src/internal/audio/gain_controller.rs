@@ -27,22 +27,25 @@ impl GainControllerSettings {
 #[derive(Serialize, Deserialize)]
 pub struct GainController {
   channel: Channel,
-  current_tick: u8,
+  period_timer: u8,
   current_value: u8,
   current_settings: GainControllerSettings,
   pub new_settings: GainControllerSettings,
   active: bool,
+  // The envelope stops updating once the volume can't go any further in its direction
+  updating: bool,
 }
 
 impl GainController {
   pub fn new(channel: Channel) -> Self {
     GainController {
       channel,
-      current_tick: 0,
+      period_timer: 0,
       current_value: 0,
       current_settings: GainControllerSettings::new(),
       new_settings: GainControllerSettings::new(),
       active: false,
+      updating: false,
     }
   }
 
@@ -50,11 +53,35 @@ impl GainController {
     self.active = false;
   }
 
-  pub fn trigger(&mut self) {
+  // When the next frame sequencer step clocks the envelope, the period timer is reloaded with one more than the pace
+  pub fn trigger(&mut self, next_step_clocks_envelope: bool) {
     self.current_settings = self.new_settings;
-    self.current_tick = 0;
+    self.period_timer = self.current_settings.pace + next_step_clocks_envelope as u8;
     self.current_value = self.current_settings.initial_value;
     self.active = true;
+    self.updating = true;
+  }
+
+  // Writing NRx2 while the channel is playing changes its volume right away ("zombie mode"), based on the previous
+  // contents of the register. The new settings only take full effect on the next trigger.
+  pub fn write_settings(&mut self, settings: GainControllerSettings) {
+    if self.active {
+      let mut volume = self.current_value;
+      if self.new_settings.pace == 0 && self.updating {
+        volume += 1;
+      } else if !self.new_settings.ascending {
+        volume += 2;
+      }
+      if self.new_settings.ascending != settings.ascending {
+        volume = 16u8.wrapping_sub(volume);
+      }
+      self.current_value = volume & 0xF;
+    }
+    self.new_settings = settings;
+  }
+
+  pub fn current_gain(&self) -> f32 {
+    (self.current_value as f32) / 15.0
   }
 
   fn dac_shut_off(&self) -> bool {
@@ -66,16 +93,95 @@ impl GainController {
       return GainControllerTickResult::DacShutOff;
     }
     if self.active && self.current_settings.pace != 0 {
-      self.current_tick = (self.current_tick + 1) % self.current_settings.pace;
-      if self.current_tick == 0 {
+      self.period_timer = self.period_timer.saturating_sub(1);
+      if self.period_timer == 0 {
+        self.period_timer = self.current_settings.pace;
         if self.current_settings.ascending && self.current_value < 0xF {
-          self.current_value = self.current_value.saturating_add(1);
+          self.current_value += 1;
         } else if !self.current_settings.ascending && self.current_value > 0 {
-          self.current_value = self.current_value.saturating_sub(1);
+          self.current_value -= 1;
+        } else {
+          self.updating = false;
         }
       }
     }
-    audio_driver.set_gain(self.channel, (self.current_value as f32) / 15.0);
+    audio_driver.set_gain(self.channel, self.current_gain());
     GainControllerTickResult::Ok
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::audio::MockAudioDriver;
+
+  use super::*;
+
+  fn create_gain_controller(initial_value: u8, pace: u8, ascending: bool) -> (GainController, MockAudioDriver) {
+    let mut gain_controller = GainController::new(Channel::CH1);
+    gain_controller.new_settings = GainControllerSettings { initial_value, pace, ascending };
+    let mut audio_driver = MockAudioDriver::new();
+    audio_driver.expect_set_gain().return_const(());
+    (gain_controller, audio_driver)
+  }
+
+  #[test]
+  fn trigger_reloads_initial_volume_and_period() {
+    let (mut gain_controller, mut audio_driver) = create_gain_controller(0xA, 2, false);
+    gain_controller.trigger(false);
+    gain_controller.tick(&mut audio_driver);
+    assert_eq!(gain_controller.current_value, 0xA);
+    gain_controller.tick(&mut audio_driver);
+    assert_eq!(gain_controller.current_value, 0x9);
+    gain_controller.tick(&mut audio_driver);
+    gain_controller.trigger(false);
+    assert_eq!(gain_controller.current_value, 0xA);
+    gain_controller.tick(&mut audio_driver);
+    assert_eq!(gain_controller.current_value, 0xA);
+    gain_controller.tick(&mut audio_driver);
+    assert_eq!(gain_controller.current_value, 0x9);
+  }
+
+  #[test]
+  fn trigger_right_before_envelope_step_delays_first_update() {
+    let (mut gain_controller, mut audio_driver) = create_gain_controller(0xA, 1, true);
+    gain_controller.trigger(true);
+    gain_controller.tick(&mut audio_driver);
+    assert_eq!(gain_controller.current_value, 0xA);
+    gain_controller.tick(&mut audio_driver);
+    assert_eq!(gain_controller.current_value, 0xB);
+    gain_controller.tick(&mut audio_driver);
+    assert_eq!(gain_controller.current_value, 0xC);
+  }
+
+  #[test]
+  fn zombie_mode_write_with_zero_pace_increments_volume() {
+    let (mut gain_controller, _) = create_gain_controller(0x5, 0, true);
+    gain_controller.trigger(false);
+    gain_controller.write_settings(GainControllerSettings { initial_value: 0x5, pace: 0, ascending: true });
+    assert_eq!(gain_controller.current_value, 0x6);
+  }
+
+  #[test]
+  fn zombie_mode_write_after_descending_envelope_adds_two() {
+    let (mut gain_controller, _) = create_gain_controller(0x5, 3, false);
+    gain_controller.trigger(false);
+    gain_controller.write_settings(GainControllerSettings { initial_value: 0x5, pace: 3, ascending: false });
+    assert_eq!(gain_controller.current_value, 0x7);
+  }
+
+  #[test]
+  fn zombie_mode_write_changing_direction_inverts_volume() {
+    let (mut gain_controller, _) = create_gain_controller(0x5, 3, true);
+    gain_controller.trigger(false);
+    gain_controller.write_settings(GainControllerSettings { initial_value: 0x5, pace: 3, ascending: false });
+    assert_eq!(gain_controller.current_value, 0xB);
+  }
+
+  #[test]
+  fn writing_settings_while_stopped_leaves_volume_alone() {
+    let (mut gain_controller, _) = create_gain_controller(0x5, 0, true);
+    gain_controller.write_settings(GainControllerSettings { initial_value: 0x8, pace: 0, ascending: false });
+    assert_eq!(gain_controller.current_value, 0x0);
+    assert_eq!(gain_controller.new_settings.initial_value, 0x8);
+  }
+}
@@ -1,34 +1,21 @@
 use serde::{Deserialize, Serialize};
 
 use crate::audio::Channel;
+use crate::internal::util::request_flag::RequestFlag;
 
 pub enum LengthTimerTickResult {
   Ok,
   Expired,
 }
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
-pub struct LengthTimerSettings {
-  pub initial_value: u16,
-}
-
-impl LengthTimerSettings {
-  pub fn new() -> Self {
-    LengthTimerSettings {
-      initial_value: 0
-    }
-  }
-}
-
 #[derive(Serialize, Deserialize)]
 pub struct LengthTimer {
   channel: Channel,
   current_value: u16,
   max_value: u16,
-  current_settings: LengthTimerSettings,
-  pub new_settings: LengthTimerSettings,
-  pub enabled: bool,
+  enabled: bool,
   counting: bool,
+  expired_request: RequestFlag,
 }
 
 impl LengthTimer {
@@ -37,10 +24,31 @@ impl LengthTimer {
       channel,
       current_value: 0,
       max_value,
-      current_settings: LengthTimerSettings::new(),
-      new_settings: LengthTimerSettings::new(),
       enabled: false,
       counting: false,
+      expired_request: RequestFlag::new(),
+    }
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  // Writing the initial length reloads the counter right away, even while the channel is playing
+  pub fn set_initial_value(&mut self, initial_value: u16) {
+    self.current_value = self.max_value - initial_value;
+  }
+
+  // When the next frame sequencer step won't clock the length timer, enabling it clocks it once right away.
+  // If that makes the timer expire, the channel is stopped on the next tick, unless it's triggered by the same write.
+  pub fn set_enabled(&mut self, enabled: bool, extra_clock: bool) {
+    let was_enabled = self.enabled;
+    self.enabled = enabled;
+    if enabled && !was_enabled && extra_clock && self.current_value > 0 {
+      self.current_value -= 1;
+      if self.current_value == 0 {
+        self.expired_request.set();
+      }
     }
   }
 
@@ -48,18 +56,25 @@ impl LengthTimer {
     self.counting = false;
   }
 
-  pub fn trigger(&mut self) {
-    self.current_settings = self.new_settings;
-    self.current_value = self.max_value - self.current_settings.initial_value;
+  // Triggering only reloads the counter if it had run out. The extra clock also applies to the reloaded value.
+  pub fn trigger(&mut self, extra_clock: bool) {
+    self.expired_request.get_and_clear();
+    if self.current_value == 0 {
+      self.current_value = if self.enabled && extra_clock { self.max_value - 1 } else { self.max_value };
+    }
     self.counting = true;
   }
 
+  pub fn take_expired_request(&mut self) -> bool {
+    self.expired_request.get_and_clear()
+  }
+
   pub fn tick(&mut self) -> LengthTimerTickResult {
-    if self.counting && self.enabled {
-      self.current_value = self.current_value.saturating_sub(1);
+    if self.counting && self.enabled && self.current_value > 0 {
+      self.current_value -= 1;
       if self.current_value == 0 { LengthTimerTickResult::Expired } else { LengthTimerTickResult::Ok }
     } else {
       LengthTimerTickResult::Ok
     }
   }
-}
\ No newline at end of file
+}
@@ -30,13 +30,23 @@ impl NoisePlayer {
     audio_driver.stop(self.channel);
   }
 
+  pub fn resume(&self, audio_driver: &mut dyn AudioDriver) {
+    if self.playing {
+      self.play_noise(audio_driver);
+    }
+  }
+
+  fn play_noise(&self, audio_driver: &mut dyn AudioDriver) {
+    audio_driver.play_noise(self.channel, NoiseOptions {
+      frequency: 262144.0 / (if self.clock_divider == 0 { 0.5 } else { self.clock_divider as f32 } * (1u8 << self.clock_shift) as f32),
+      short: self.short,
+    })
+  }
+
   pub fn tick(&mut self, audio_driver: &mut dyn AudioDriver) {
     if self.triggered.get_and_clear() {
       self.playing = true;
-      audio_driver.play_noise(self.channel, NoiseOptions {
-        frequency: 262144.0 / (if self.clock_divider == 0 { 0.5 } else { self.clock_divider as f32 } * (1u8 << self.clock_shift) as f32),
-        short: self.short,
-      })
+      self.play_noise(audio_driver);
     }
   }
 
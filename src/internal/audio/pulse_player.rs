@@ -29,14 +29,6 @@ impl PulsePlayerSettings {
     }
   }
 
-  pub fn get_lower_wavelength_bits(&self) -> u8 {
-    (self.initial_wavelength & 0xFF) as u8
-  }
-
-  pub fn get_upper_wavelength_bits(&self) -> u8 {
-    ((self.initial_wavelength & 0xFF00) >> 8) as u8
-  }
-
   pub fn set_lower_wavelength_bits(&mut self, value: u8) {
     self.initial_wavelength = (self.initial_wavelength & 0xFF00) | (value as u16);
   }
@@ -50,8 +42,11 @@ impl PulsePlayerSettings {
 pub struct PulsePlayer {
   channel: Channel,
   triggered: RequestFlag,
+  frequency_changed: RequestFlag,
   current_tick: u8,
   wavelength: u16,
+  // The sweep works off its own copy of the wavelength, which only a trigger reloads
+  shadow_wavelength: u16,
   current_settings: PulsePlayerSettings,
   pub new_settings: PulsePlayerSettings,
   pub playing: bool,
@@ -62,8 +57,10 @@ impl PulsePlayer {
     PulsePlayer {
       channel,
       triggered: RequestFlag::new(),
+      frequency_changed: RequestFlag::new(),
       current_tick: 0,
       wavelength: 0,
+      shadow_wavelength: 0,
       current_settings: PulsePlayerSettings::new(),
       new_settings: PulsePlayerSettings::new(),
       playing: false,
@@ -75,6 +72,7 @@ impl PulsePlayer {
     self.current_settings = self.new_settings;
     self.current_tick = 0;
     self.wavelength = self.current_settings.initial_wavelength;
+    self.shadow_wavelength = self.wavelength;
     self.playing = true;
   }
 
@@ -86,13 +84,35 @@ impl PulsePlayer {
     }
   }
 
+  // Writing the wavelength of a playing channel changes its pitch right away, without restarting the duty cycle
+  pub fn set_lower_wavelength_bits(&mut self, value: u8) {
+    self.new_settings.set_lower_wavelength_bits(value);
+    self.update_wavelength();
+  }
+
+  pub fn set_upper_wavelength_bits(&mut self, value: u8) {
+    self.new_settings.set_upper_wavelength_bits(value);
+    self.update_wavelength();
+  }
+
+  fn update_wavelength(&mut self) {
+    if self.playing {
+      self.wavelength = self.new_settings.initial_wavelength;
+      self.frequency_changed.set();
+    }
+  }
+
   fn wavelength_overflowed(&self) -> bool {
-    self.wavelength > 0x7FF
+    self.shadow_wavelength > 0x7FF
+  }
+
+  fn frequency(&self) -> f32 {
+    131072.0f32 / (2048.0 - self.wavelength as f32)
   }
 
   fn play_pulse(&self, audio_driver: &mut dyn AudioDriver) {
     audio_driver.play_pulse(self.channel, PulseOptions {
-      frequency: 131072.0f32 / (2048.0 - self.wavelength as f32),
+      frequency: self.frequency(),
       duty_cycle: self.current_settings.duty_cycle.to_ratio(),
     });
   }
@@ -102,19 +122,36 @@ impl PulsePlayer {
     audio_driver.stop(self.channel);
   }
 
-  pub fn tick(&mut self, audio_driver: &mut dyn AudioDriver) -> PulsePlayerTickResult {
+  // Restarts the driver at the current pitch, without touching the player's own state
+  pub fn resume(&self, audio_driver: &mut dyn AudioDriver) {
+    if self.playing {
+      self.play_pulse(audio_driver);
+    }
+  }
+
+  // Passes triggers and wavelength writes on to the driver, which has to happen every machine cycle
+  pub fn tick(&mut self, audio_driver: &mut dyn AudioDriver) {
     if self.triggered.get_and_clear() {
+      self.frequency_changed.get_and_clear();
       self.play_pulse(audio_driver);
-    } else if self.playing && self.current_settings.pace != 0 && self.current_settings.shift != 0 {
+    } else if self.frequency_changed.get_and_clear() {
+      audio_driver.set_frequency(self.channel, self.frequency());
+    }
+  }
+
+  // Clocked by the frame sequencer, which only does so for CH1
+  pub fn sweep_tick(&mut self, audio_driver: &mut dyn AudioDriver) -> PulsePlayerTickResult {
+    if self.playing && self.current_settings.pace != 0 && self.current_settings.shift != 0 {
       self.current_tick = (self.current_tick + 1) % self.current_settings.pace;
       if self.current_tick == 0 {
         if self.current_settings.decrease {
-          self.wavelength -= self.wavelength >> self.current_settings.shift;
+          self.shadow_wavelength -= self.shadow_wavelength >> self.current_settings.shift;
         } else {
-          self.wavelength += self.wavelength >> self.current_settings.shift;
+          self.shadow_wavelength += self.shadow_wavelength >> self.current_settings.shift;
         }
         if !self.wavelength_overflowed() {
-          self.play_pulse(audio_driver);
+          self.wavelength = self.shadow_wavelength;
+          audio_driver.set_frequency(self.channel, self.frequency());
         }
       }
     }
@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::audio::{AudioDriver, Channel, StereoChannel};
 use crate::internal::audio::custom_wave_player::{CustomWavePlayer, CustomWavePlayerTickResult};
-use crate::internal::audio::gain_controller::{GainController, GainControllerTickResult};
+use crate::internal::audio::gain_controller::{GainController, GainControllerSettings, GainControllerTickResult};
 use crate::internal::audio::length_timer::{LengthTimer, LengthTimerTickResult};
 use crate::internal::audio::noise_player::NoisePlayer;
 use crate::internal::audio::pulse_player::{PulsePlayer, PulsePlayerTickResult};
@@ -38,7 +38,6 @@ pub struct AudioControllerImpl {
   master_volume: u8,
   mixing_control: u8,
   mixing_control_changed: RequestFlag,
-  waveform_ram: [u8; 16],
 }
 
 impl AudioControllerImpl {
@@ -62,11 +61,46 @@ impl AudioControllerImpl {
       master_volume: 0,
       mixing_control: 0,
       mixing_control_changed: RequestFlag(true),
-      waveform_ram: [0; 16],
     };
     controller_impl
   }
 
+  // The length timers are clocked on every other frame sequencer step. Enabling or triggering a length timer when the
+  // next step won't clock it clocks it once extra.
+  fn length_extra_clock(&self) -> bool {
+    self.div_apu % 2 == 1
+  }
+
+  fn stop_expired_channels(&mut self, audio_driver: &mut dyn AudioDriver) {
+    if self.ch1_length_timer.take_expired_request() {
+      self.stop(Channel::CH1, audio_driver);
+    }
+    if self.ch2_length_timer.take_expired_request() {
+      self.stop(Channel::CH2, audio_driver);
+    }
+    if self.ch3_length_timer.take_expired_request() {
+      self.stop(Channel::CH3, audio_driver);
+    }
+    if self.ch4_length_timer.take_expired_request() {
+      self.stop(Channel::CH4, audio_driver);
+    }
+  }
+
+  // Steps 0, 2, 4 and 6 clock the length timers, steps 2 and 6 the frequency sweep and step 7 the envelopes
+  fn frame_sequencer_step(&mut self, audio_driver: &mut dyn AudioDriver) {
+    let step = self.div_apu;
+    self.div_apu = self.div_apu.wrapping_add(1);
+    if step % 2 == 0 {
+      self.length_timer_tick(audio_driver);
+    }
+    if step % 4 == 2 {
+      self.player_tick(audio_driver);
+    }
+    if step % 8 == 7 {
+      self.gain_controller_tick(audio_driver);
+    }
+  }
+
   fn length_timer_tick(&mut self, audio_driver: &mut dyn AudioDriver) {
     if let LengthTimerTickResult::Expired = self.ch1_length_timer.tick() {
       self.stop(Channel::CH1, audio_driver);
@@ -95,7 +129,7 @@ impl AudioControllerImpl {
   }
 
   fn player_tick(&mut self, audio_driver: &mut dyn AudioDriver) {
-    if let PulsePlayerTickResult::WavelengthOverflowed = self.ch1_pulse_player.tick(audio_driver) {
+    if let PulsePlayerTickResult::WavelengthOverflowed = self.ch1_pulse_player.sweep_tick(audio_driver) {
       self.stop(Channel::CH1, audio_driver);
     }
   }
@@ -119,23 +153,14 @@ impl AudioControllerImpl {
     if !self.enabled {
       return;
     }
+    self.stop_expired_channels(audio_driver);
     let new_timer_div = timer.get_divider().get_upper_byte();
     let divider_bit = if double_speed { 5 } else { 4 };
     if self.previous_timer_div.get_bit(divider_bit) && !new_timer_div.get_bit(divider_bit) {
-      self.div_apu = self.div_apu.wrapping_add(1);
-      if self.div_apu % 2 == 0 {
-        self.length_timer_tick(audio_driver);
-      }
-      if self.div_apu % 4 == 0 {
-        self.player_tick(audio_driver);
-      }
-      if self.div_apu % 8 == 0 {
-        self.gain_controller_tick(audio_driver);
-      }
-    }
-    if let PulsePlayerTickResult::WavelengthOverflowed = self.ch2_pulse_player.tick(audio_driver) {
-      self.stop(Channel::CH2, audio_driver);
+      self.frame_sequencer_step(audio_driver);
     }
+    self.ch1_pulse_player.tick(audio_driver);
+    self.ch2_pulse_player.tick(audio_driver);
     if let CustomWavePlayerTickResult::DacShutOff = self.ch3_custom_wave_player.tick(audio_driver) {
       self.stop(Channel::CH3, audio_driver);
     }
@@ -143,25 +168,50 @@ impl AudioControllerImpl {
     self.previous_timer_div = new_timer_div;
   }
 
+  // Brings a driver that hasn't been receiving any updates back in sync by replaying the channels that are playing
+  pub fn resume_output(&mut self, audio_driver: &mut dyn AudioDriver) {
+    self.set_stereo_gains(audio_driver);
+    audio_driver.set_gain(Channel::CH1, self.ch1_gain_controller.current_gain());
+    audio_driver.set_gain(Channel::CH2, self.ch2_gain_controller.current_gain());
+    audio_driver.set_gain(Channel::CH4, self.ch4_gain_controller.current_gain());
+    self.ch1_pulse_player.resume(audio_driver);
+    self.ch2_pulse_player.resume(audio_driver);
+    self.ch3_custom_wave_player.resume(audio_driver);
+    self.ch4_noise_player.resume(audio_driver);
+  }
+
+  // Returns the current amplitude of each channel between 0 and 1, after its volume envelope or output level is applied
+  // but before the channels are mixed
+  pub fn channel_outputs(&self) -> [f32; 4] {
+    [
+      if self.ch1_pulse_player.playing { self.ch1_gain_controller.current_gain() } else { 0.0 },
+      if self.ch2_pulse_player.playing { self.ch2_gain_controller.current_gain() } else { 0.0 },
+      if self.ch3_custom_wave_player.playing && self.ch3_custom_wave_player.dac_enabled { self.ch3_custom_wave_player.gain_ratio() } else { 0.0 },
+      if self.ch4_noise_player.playing { self.ch4_gain_controller.current_gain() } else { 0.0 },
+    ]
+  }
+
   fn trigger(&mut self, channel: Channel) {
+    let extra_clock = self.length_extra_clock();
+    let next_step_clocks_envelope = self.div_apu % 8 == 7;
     match channel {
       Channel::CH1 => {
-        self.ch1_length_timer.trigger();
-        self.ch1_gain_controller.trigger();
+        self.ch1_length_timer.trigger(extra_clock);
+        self.ch1_gain_controller.trigger(next_step_clocks_envelope);
         self.ch1_pulse_player.trigger();
       }
       Channel::CH2 => {
-        self.ch2_length_timer.trigger();
-        self.ch2_gain_controller.trigger();
+        self.ch2_length_timer.trigger(extra_clock);
+        self.ch2_gain_controller.trigger(next_step_clocks_envelope);
         self.ch2_pulse_player.trigger();
       }
       Channel::CH3 => {
-        self.ch3_length_timer.trigger();
+        self.ch3_length_timer.trigger(extra_clock);
         self.ch3_custom_wave_player.trigger();
       }
       Channel::CH4 => {
-        self.ch4_length_timer.trigger();
-        self.ch4_gain_controller.trigger();
+        self.ch4_length_timer.trigger(extra_clock);
+        self.ch4_gain_controller.trigger(next_step_clocks_envelope);
         self.ch4_noise_player.trigger();
       }
     }
@@ -191,8 +241,20 @@ impl AudioControllerImpl {
     }
   }
 
+  // Powering off the APU clears all registers right away. The channels are stopped on the next tick.
+  fn power_off(&mut self) {
+    if self.enabled {
+      (MemoryAddress::NR10..=MemoryAddress::NR51).for_each(|address| self.write(address, 0x00));
+    }
+    self.enabled = false;
+    self.disabled_request.set();
+  }
+
   fn disable(&mut self, audio_driver: &mut dyn AudioDriver) {
     self.enabled = false;
+    // The frame sequencer restarts from its first step once the APU is powered on again
+    self.div_apu = 0;
+    self.previous_timer_div = 0;
     self.stop(Channel::CH1, audio_driver);
     self.stop(Channel::CH2, audio_driver);
     self.stop(Channel::CH3, audio_driver);
@@ -203,6 +265,7 @@ impl AudioControllerImpl {
 impl AudioController for AudioControllerImpl {}
 
 impl Memory for AudioControllerImpl {
+  // Write-only bits, like the wavelengths, length timer values and trigger bits, always read back as 1
   fn read(&self, address: u16) -> u8 {
     match address {
       MemoryAddress::NR10 => {
@@ -217,19 +280,16 @@ impl Memory for AudioControllerImpl {
           DutyCycle::Duty500 => 2,
           DutyCycle::Duty750 => 3
         };
-        (duty_cycle_bits << 6) | (self.ch1_length_timer.new_settings.initial_value as u8)
+        (duty_cycle_bits << 6) | 0x3F
       }
       MemoryAddress::NR12 => {
         self.ch1_gain_controller.new_settings.pace |
           ((self.ch1_gain_controller.new_settings.ascending as u8) << 3) |
           (self.ch1_gain_controller.new_settings.initial_value << 4)
       }
-      MemoryAddress::NR13 => self.ch1_pulse_player.new_settings.get_lower_wavelength_bits(),
-      MemoryAddress::NR14 => {
-        0x38 | self.ch1_pulse_player.new_settings.get_upper_wavelength_bits() |
-          ((self.ch1_length_timer.enabled as u8) << 6)
-      }
-      0xFF15 => 0,
+      MemoryAddress::NR13 => 0xFF,
+      MemoryAddress::NR14 => 0xBF | ((self.ch1_length_timer.enabled() as u8) << 6),
+      0xFF15 => 0xFF,
       MemoryAddress::NR21 => {
         let duty_cycle_bits: u8 = match self.ch2_pulse_player.new_settings.duty_cycle {
           DutyCycle::Duty125 => 0,
@@ -237,35 +297,29 @@ impl Memory for AudioControllerImpl {
           DutyCycle::Duty500 => 2,
           DutyCycle::Duty750 => 3
         };
-        (duty_cycle_bits << 6) | (self.ch2_length_timer.new_settings.initial_value as u8)
+        (duty_cycle_bits << 6) | 0x3F
       }
       MemoryAddress::NR22 => {
         self.ch2_gain_controller.new_settings.pace |
           ((self.ch2_gain_controller.new_settings.ascending as u8) << 3) |
           (self.ch2_gain_controller.new_settings.initial_value << 4)
       }
-      MemoryAddress::NR23 => self.ch2_pulse_player.new_settings.get_lower_wavelength_bits(),
-      MemoryAddress::NR24 => {
-        self.ch2_pulse_player.new_settings.get_upper_wavelength_bits() |
-          ((self.ch2_length_timer.enabled as u8) << 6)
-      }
+      MemoryAddress::NR23 => 0xFF,
+      MemoryAddress::NR24 => 0xBF | ((self.ch2_length_timer.enabled() as u8) << 6),
       MemoryAddress::NR30 => if self.ch3_custom_wave_player.dac_enabled { 0xFF } else { 0x7F },
-      MemoryAddress::NR31 => self.ch3_length_timer.new_settings.initial_value as u8,
+      MemoryAddress::NR31 => 0xFF,
       MemoryAddress::NR32 => 0x9F | (self.ch3_custom_wave_player.gain << 5),
-      MemoryAddress::NR33 => self.ch3_custom_wave_player.get_lower_wavelength_bits(),
-      MemoryAddress::NR34 => {
-        self.ch3_custom_wave_player.get_upper_wavelength_bits() |
-          ((self.ch3_length_timer.enabled as u8) << 6)
-      }
-      0xFF1F => 0,
-      MemoryAddress::NR41 => 0xC0 | self.ch4_length_timer.new_settings.initial_value as u8,
+      MemoryAddress::NR33 => 0xFF,
+      MemoryAddress::NR34 => 0xBF | ((self.ch3_length_timer.enabled() as u8) << 6),
+      0xFF1F => 0xFF,
+      MemoryAddress::NR41 => 0xFF,
       MemoryAddress::NR42 => self.ch4_gain_controller.new_settings.pace |
         ((self.ch4_gain_controller.new_settings.ascending as u8) << 3) |
         (self.ch4_gain_controller.new_settings.initial_value << 4),
       MemoryAddress::NR43 => (self.ch4_noise_player.clock_shift << 4) |
         ((self.ch4_noise_player.short as u8) << 3) |
         self.ch4_noise_player.clock_divider,
-      MemoryAddress::NR44 => 0x3F | ((self.ch4_length_timer.enabled as u8) << 6),
+      MemoryAddress::NR44 => 0xBF | ((self.ch4_length_timer.enabled() as u8) << 6),
       MemoryAddress::NR50 => self.master_volume,
       MemoryAddress::NR51 => self.mixing_control,
       MemoryAddress::NR52 => {
@@ -276,13 +330,17 @@ impl Memory for AudioControllerImpl {
           ((self.ch4_noise_player.playing as u8) << 3) |
           ((self.enabled as u8) << 7)
       }
-      0xFF27..=0xFF2F => 0,
-      0xFF30..=0xFF3F => self.waveform_ram[address as usize - 0xFF30],
+      0xFF27..=0xFF2F => 0xFF,
+      0xFF30..=0xFF3F => self.ch3_custom_wave_player.waveform[address as usize - 0xFF30],
       _ => panic!("AudioController can't read from address {}", address)
     }
   }
 
   fn write(&mut self, address: u16, value: u8) {
+    // While the APU is powered off, only NR52 and the wave RAM can be written to
+    if !self.enabled && (MemoryAddress::NR10..=MemoryAddress::NR51).contains(&address) {
+      return;
+    }
     match address {
       MemoryAddress::NR10 => {
         self.ch1_pulse_player.new_settings.shift = value & 0x7;
@@ -297,19 +355,21 @@ impl Memory for AudioControllerImpl {
           2 => DutyCycle::Duty500,
           _ => DutyCycle::Duty750,
         };
-        self.ch1_length_timer.new_settings.initial_value = (value & 0x3F) as u16;
+        self.ch1_length_timer.set_initial_value((value & 0x3F) as u16);
       }
       MemoryAddress::NR12 => {
-        self.ch1_gain_controller.new_settings.pace = value & 0x7;
-        self.ch1_gain_controller.new_settings.ascending = value.get_bit(3);
-        self.ch1_gain_controller.new_settings.initial_value = value >> 4;
+        self.ch1_gain_controller.write_settings(GainControllerSettings {
+          pace: value & 0x7,
+          ascending: value.get_bit(3),
+          initial_value: value >> 4,
+        });
       }
       MemoryAddress::NR13 => {
-        self.ch1_pulse_player.new_settings.set_lower_wavelength_bits(value);
+        self.ch1_pulse_player.set_lower_wavelength_bits(value);
       }
       MemoryAddress::NR14 => {
-        self.ch1_pulse_player.new_settings.set_upper_wavelength_bits(value);
-        self.ch1_length_timer.enabled = value.get_bit(6);
+        self.ch1_pulse_player.set_upper_wavelength_bits(value);
+        self.ch1_length_timer.set_enabled(value.get_bit(6), self.length_extra_clock());
         if value.get_bit(7) {
           self.trigger(Channel::CH1);
         }
@@ -323,19 +383,21 @@ impl Memory for AudioControllerImpl {
           2 => DutyCycle::Duty500,
           _ => DutyCycle::Duty750,
         };
-        self.ch2_length_timer.new_settings.initial_value = (value & 0x3F) as u16;
+        self.ch2_length_timer.set_initial_value((value & 0x3F) as u16);
       }
       MemoryAddress::NR22 => {
-        self.ch2_gain_controller.new_settings.pace = value & 0x7;
-        self.ch2_gain_controller.new_settings.ascending = value.get_bit(3);
-        self.ch2_gain_controller.new_settings.initial_value = value >> 4;
+        self.ch2_gain_controller.write_settings(GainControllerSettings {
+          pace: value & 0x7,
+          ascending: value.get_bit(3),
+          initial_value: value >> 4,
+        });
       }
       MemoryAddress::NR23 => {
-        self.ch2_pulse_player.new_settings.set_lower_wavelength_bits(value);
+        self.ch2_pulse_player.set_lower_wavelength_bits(value);
       }
       MemoryAddress::NR24 => {
-        self.ch2_pulse_player.new_settings.set_upper_wavelength_bits(value);
-        self.ch2_length_timer.enabled = value.get_bit(6);
+        self.ch2_pulse_player.set_upper_wavelength_bits(value);
+        self.ch2_length_timer.set_enabled(value.get_bit(6), self.length_extra_clock());
         if value.get_bit(7) {
           self.trigger(Channel::CH2);
         }
@@ -344,7 +406,7 @@ impl Memory for AudioControllerImpl {
         self.ch3_custom_wave_player.set_dac_enabled(value.get_bit(7));
       }
       MemoryAddress::NR31 => {
-        self.ch3_length_timer.new_settings.initial_value = value as u16;
+        self.ch3_length_timer.set_initial_value(value as u16);
       }
       MemoryAddress::NR32 => {
         let gain = (value >> 5) & 0x3;
@@ -355,19 +417,21 @@ impl Memory for AudioControllerImpl {
       }
       MemoryAddress::NR34 => {
         self.ch3_custom_wave_player.set_upper_wavelength_bits(value);
-        self.ch3_length_timer.enabled = value.get_bit(6);
+        self.ch3_length_timer.set_enabled(value.get_bit(6), self.length_extra_clock());
         if value.get_bit(7) {
           self.trigger(Channel::CH3);
         }
       }
       0xFF1F => {}
       MemoryAddress::NR41 => {
-        self.ch4_length_timer.new_settings.initial_value = (value & 0x3F) as u16;
+        self.ch4_length_timer.set_initial_value((value & 0x3F) as u16);
       }
       MemoryAddress::NR42 => {
-        self.ch4_gain_controller.new_settings.pace = value & 0x7;
-        self.ch4_gain_controller.new_settings.ascending = value.get_bit(3);
-        self.ch4_gain_controller.new_settings.initial_value = value >> 4;
+        self.ch4_gain_controller.write_settings(GainControllerSettings {
+          pace: value & 0x7,
+          ascending: value.get_bit(3),
+          initial_value: value >> 4,
+        });
       }
       MemoryAddress::NR43 => {
         self.ch4_noise_player.clock_divider = value & 0x7;
@@ -375,7 +439,7 @@ impl Memory for AudioControllerImpl {
         self.ch4_noise_player.clock_shift = value >> 4;
       }
       MemoryAddress::NR44 => {
-        self.ch4_length_timer.enabled = value.get_bit(6);
+        self.ch4_length_timer.set_enabled(value.get_bit(6), self.length_extra_clock());
         if value.get_bit(7) {
           self.trigger(Channel::CH4);
         }
@@ -387,7 +451,7 @@ impl Memory for AudioControllerImpl {
       }
       MemoryAddress::NR52 => {
         if !value.get_bit(7) {
-          self.disabled_request.set();
+          self.power_off();
         } else {
           self.enabled = true;
         }
@@ -408,12 +472,458 @@ pub enum DutyCycle {
 }
 
 impl DutyCycle {
-  pub fn to_ratio(&self) -> f32 {
+  // The 8 steps of the waveform, starting at bit 7. The duty position moves on to the next step every time the
+  // frequency timer runs out, so the whole waveform plays at the channel's frequency.
+  pub fn waveform(&self) -> u8 {
     match self {
-      DutyCycle::Duty125 => 0.125,
-      DutyCycle::Duty250 => 0.250,
-      DutyCycle::Duty500 => 0.500,
-      DutyCycle::Duty750 => 0.250
+      DutyCycle::Duty125 => 0b00000001,
+      DutyCycle::Duty250 => 0b10000001,
+      DutyCycle::Duty500 => 0b10000111,
+      DutyCycle::Duty750 => 0b01111110,
+    }
+  }
+
+  pub fn to_ratio(&self) -> f32 {
+    self.waveform().count_ones() as f32 / 8.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use assert_hex::assert_eq_hex;
+  use mockall::predicate::{always, eq};
+
+  use crate::audio::MockAudioDriver;
+  use crate::internal::controllers::timer::TimerControllerImpl;
+  use crate::internal::cpu::interrupts::MockInterruptController;
+
+  use super::*;
+
+  fn create_permissive_audio_driver() -> MockAudioDriver {
+    let mut audio_driver = MockAudioDriver::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_frequency().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_play_pulse().return_const(());
+    audio_driver.expect_stop().return_const(());
+    audio_driver
+  }
+
+  #[test]
+  fn duty_cycles_match_hardware_waveforms() {
+    [
+      (DutyCycle::Duty125, 0b00000001, 0.125f32),
+      (DutyCycle::Duty250, 0b10000001, 0.25f32),
+      (DutyCycle::Duty500, 0b10000111, 0.5f32),
+      (DutyCycle::Duty750, 0b01111110, 0.75f32),
+    ].into_iter().for_each(|(duty_cycle, expected_waveform, expected_ratio)| {
+      assert_eq_hex!(duty_cycle.waveform(), expected_waveform);
+      assert_eq!(duty_cycle.to_ratio(), expected_ratio);
+    });
+  }
+
+  #[test]
+  fn ch2_plays_pulse_with_duty_ratio_from_nr21() {
+    let mut controller = AudioControllerImpl::new();
+    let timer = TimerControllerImpl::new();
+    let mut audio_driver = MockAudioDriver::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_set_frequency().return_const(());
+    audio_driver.expect_stop().with(eq(Channel::CH3)).return_const(()); // CH3's DAC is off
+    audio_driver.expect_play_pulse().withf(|channel, options| *channel == Channel::CH2 && options.duty_cycle == 0.75).once().return_const(());
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR21, 0xC0); // 75% duty cycle
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0x80);
+    controller.tick(&mut audio_driver, &timer, false);
+  }
+
+  fn ch2_playing(controller: &AudioControllerImpl) -> bool {
+    controller.read(MemoryAddress::NR52).get_bit(1)
+  }
+
+  // Steps the frame sequencer until the channel stops and returns how many of those steps clocked the length timer
+  fn length_clocks_until_stopped(controller: &mut AudioControllerImpl, audio_driver: &mut MockAudioDriver, channel: Channel) -> usize {
+    let mut length_clocks = 0;
+    while controller.read(MemoryAddress::NR52).get_bit(channel as u8) {
+      if controller.div_apu % 2 == 0 {
+        length_clocks += 1;
+      }
+      controller.frame_sequencer_step(audio_driver);
+    }
+    length_clocks
+  }
+
+  #[test]
+  fn write_only_bits_read_back_as_ones() {
+    let mut controller = AudioControllerImpl::new();
+    controller.write(MemoryAddress::NR52, 0x80);
+    let registers = [
+      (MemoryAddress::NR10, 0x80), (MemoryAddress::NR11, 0x3F), (MemoryAddress::NR12, 0x00),
+      (MemoryAddress::NR13, 0xFF), (MemoryAddress::NR14, 0xBF),
+      (MemoryAddress::NR21, 0x3F), (MemoryAddress::NR22, 0x00), (MemoryAddress::NR23, 0xFF), (MemoryAddress::NR24, 0xBF),
+      (MemoryAddress::NR30, 0x7F), (MemoryAddress::NR31, 0xFF), (MemoryAddress::NR32, 0x9F),
+      (MemoryAddress::NR33, 0xFF), (MemoryAddress::NR34, 0xBF),
+      (MemoryAddress::NR41, 0xFF), (MemoryAddress::NR42, 0x00), (MemoryAddress::NR43, 0x00), (MemoryAddress::NR44, 0xBF),
+      (MemoryAddress::NR50, 0x00), (MemoryAddress::NR51, 0x00),
+    ];
+    for (address, read_mask) in registers {
+      // Leave the trigger bit cleared so no channel gets started
+      for value in [0x00u8, 0x5A, 0x65, 0x7F] {
+        controller.write(address, value);
+        assert_eq_hex!(controller.read(address), value | read_mask, "Register {:#06x}", address);
+      }
+    }
+  }
+
+  #[test]
+  fn channel_outputs_reflect_playing_channels() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = MockAudioDriver::new();
+    let timer = TimerControllerImpl::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_play_pulse().return_const(());
+    audio_driver.expect_set_frequency().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_stop().return_const(());
+    controller.write(MemoryAddress::NR52, 0x80);
+    assert_eq!(controller.channel_outputs(), [0.0; 4]);
+    controller.write(MemoryAddress::NR11, 0x80); // 50% duty cycle
+    controller.write(MemoryAddress::NR12, 0xA0); // Volume 10, no envelope
+    controller.write(MemoryAddress::NR13, 0x00);
+    controller.write(MemoryAddress::NR14, 0x87); // Trigger
+    controller.tick(&mut audio_driver, &timer, false);
+    assert_eq!(controller.channel_outputs(), [10.0 / 15.0, 0.0, 0.0, 0.0]);
+  }
+
+  #[test]
+  fn clearing_nr30_dac_bit_stops_ch3_until_triggered_again() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    audio_driver.expect_play_custom_wave().return_const(());
+    let timer = TimerControllerImpl::new();
+    let ch3_playing = |controller: &AudioControllerImpl| controller.read(MemoryAddress::NR52).get_bit(2);
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR30, 0x80); // DAC on
+    controller.write(MemoryAddress::NR34, 0x80); // Trigger
+    controller.tick(&mut audio_driver, &timer, false);
+    assert!(ch3_playing(&controller));
+
+    audio_driver.checkpoint();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_stop().with(eq(Channel::CH3)).once().return_const(());
+    controller.write(MemoryAddress::NR30, 0x00); // DAC off
+    assert!(!ch3_playing(&controller));
+    controller.tick(&mut audio_driver, &timer, false);
+
+    let mut audio_driver = create_permissive_audio_driver();
+    audio_driver.expect_play_custom_wave().never();
+    controller.write(MemoryAddress::NR30, 0x80); // DAC on
+    (0..100).for_each(|_| controller.tick(&mut audio_driver, &timer, false));
+    assert!(!ch3_playing(&controller));
+
+    let mut audio_driver = create_permissive_audio_driver();
+    audio_driver.expect_play_custom_wave().once().return_const(());
+    controller.write(MemoryAddress::NR34, 0x80); // Trigger
+    controller.tick(&mut audio_driver, &timer, false);
+    assert!(ch3_playing(&controller));
+  }
+
+  #[test]
+  fn ch3_output_level_follows_nr32() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = MockAudioDriver::new();
+    let timer = TimerControllerImpl::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_frequency().return_const(());
+    audio_driver.expect_set_gain().with(always(), always()).return_const(());
+    audio_driver.expect_play_custom_wave().return_const(());
+    controller.write(MemoryAddress::NR52, 0x80);
+    (0xFF30u16..=0xFF3F).for_each(|address| controller.write(address, 0xF0));
+    controller.write(MemoryAddress::NR30, 0x80); // DAC on
+    controller.write(MemoryAddress::NR32, 0x20); // 100%
+    controller.write(MemoryAddress::NR34, 0x80); // Trigger
+    controller.tick(&mut audio_driver, &timer, false);
+    let full_output = controller.channel_outputs()[2];
+    assert_eq!(full_output, 1.0);
+
+    audio_driver.checkpoint();
+    audio_driver.expect_set_gain().with(eq(Channel::CH3), eq(0.5f32)).once().return_const(());
+    controller.write(MemoryAddress::NR32, 0x40); // 50%
+    controller.tick(&mut audio_driver, &timer, false);
+    assert_eq!(controller.channel_outputs()[2], full_output / 2.0);
+
+    audio_driver.checkpoint();
+    audio_driver.expect_set_gain().with(eq(Channel::CH3), eq(0.0f32)).once().return_const(());
+    controller.write(MemoryAddress::NR32, 0x00); // Mute
+    controller.tick(&mut audio_driver, &timer, false);
+    assert_eq!(controller.channel_outputs()[2], 0.0);
+  }
+
+  #[test]
+  fn unused_registers_read_as_ff_and_ignore_writes() {
+    let mut controller = AudioControllerImpl::new();
+    let unused_addresses: Vec<u16> = [0xFF15u16, 0xFF1F].into_iter().chain(0xFF27..=0xFF2F).collect();
+    for enabled in [false, true] {
+      controller.write(MemoryAddress::NR52, if enabled { 0x80 } else { 0x00 });
+      let nr52 = controller.read(MemoryAddress::NR52);
+      for &address in &unused_addresses {
+        controller.write(address, 0x00);
+        assert_eq_hex!(controller.read(address), 0xFF, "Register {:#06x}", address);
+      }
+      assert_eq_hex!(controller.read(MemoryAddress::NR52), nr52);
+    }
+  }
+
+  #[test]
+  fn only_ch1_sweep_stops_channel_on_wavelength_overflow() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = MockAudioDriver::new();
+    let timer = TimerControllerImpl::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_frequency().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_play_pulse().return_const(());
+    audio_driver.expect_stop().with(eq(Channel::CH3)).return_const(()); // CH3's DAC is off
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR10, 0x11); // Pace 1, increasing, shift 1
+    controller.write(MemoryAddress::NR12, 0xF0);
+    controller.write(MemoryAddress::NR13, 0x00);
+    controller.write(MemoryAddress::NR14, 0x87); // Wavelength 0x700, trigger
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR23, 0xFF);
+    controller.write(MemoryAddress::NR24, 0x87); // Wavelength 0x7FF, trigger
+    controller.tick(&mut audio_driver, &timer, false);
+    assert_eq_hex!(controller.read(MemoryAddress::NR52) & 0x03, 0x03);
+
+    audio_driver.expect_stop().with(eq(Channel::CH1)).once().return_const(());
+    controller.player_tick(&mut audio_driver); // 0x700 + (0x700 >> 1) overflows
+    for _ in 0..0x1000 {
+      controller.tick(&mut audio_driver, &timer, false);
+    }
+    assert_eq_hex!(controller.read(MemoryAddress::NR52) & 0x03, 0x02);
+  }
+
+  #[test]
+  fn wavelength_writes_change_pitch_without_restarting_duty_cycle() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = MockAudioDriver::new();
+    let timer = TimerControllerImpl::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_stop().with(eq(Channel::CH3)).return_const(()); // CH3's DAC is off
+    audio_driver.expect_set_frequency().withf(|channel, _| *channel != Channel::CH2).return_const(());
+    audio_driver.expect_play_pulse().withf(|channel, _| *channel == Channel::CH2).once().return_const(());
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR23, 0x00);
+    controller.write(MemoryAddress::NR24, 0x87); // Wavelength 0x700, trigger
+    controller.tick(&mut audio_driver, &timer, false);
+
+    audio_driver.checkpoint();
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_set_frequency().withf(|channel, _| *channel != Channel::CH2).return_const(());
+    audio_driver.expect_set_frequency().with(eq(Channel::CH2), eq(131072.0f32 / 128.0)).once().return_const(());
+    audio_driver.expect_play_pulse().never();
+    controller.write(MemoryAddress::NR23, 0x80);
+    controller.write(MemoryAddress::NR24, 0x07); // Wavelength 0x780, no trigger
+    controller.tick(&mut audio_driver, &timer, false);
+    controller.tick(&mut audio_driver, &timer, false);
+
+    audio_driver.checkpoint();
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_set_frequency().withf(|channel, _| *channel != Channel::CH2).return_const(());
+    audio_driver.expect_set_frequency().with(eq(Channel::CH2), always()).never();
+    audio_driver.expect_play_pulse().withf(|channel, options| *channel == Channel::CH2 && options.frequency == 131072.0f32 / 128.0).once().return_const(());
+    controller.write(MemoryAddress::NR24, 0x87); // Trigger
+    controller.tick(&mut audio_driver, &timer, false);
+  }
+
+  #[test]
+  fn ch1_wavelength_writes_reach_driver_between_sweep_steps() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    let timer = TimerControllerImpl::new();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR10, 0x11); // Pace 1, increasing, shift 1
+    controller.write(MemoryAddress::NR12, 0xF0);
+    controller.write(MemoryAddress::NR13, 0x00);
+    controller.write(MemoryAddress::NR14, 0x81); // Wavelength 0x100, trigger
+    controller.tick(&mut audio_driver, &timer, false);
+
+    audio_driver.checkpoint();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_stop().return_const(());
+    audio_driver.expect_set_frequency().withf(|channel, _| *channel != Channel::CH1).return_const(());
+    audio_driver.expect_set_frequency().with(eq(Channel::CH1), eq(131072.0f32 / 1024.0)).once().return_const(());
+    controller.write(MemoryAddress::NR14, 0x04); // Wavelength 0x400, no trigger
+    controller.tick(&mut audio_driver, &timer, false);
+
+    // The sweep still steps from the wavelength it was triggered with
+    audio_driver.checkpoint();
+    audio_driver.expect_set_frequency().withf(|channel, _| *channel != Channel::CH1).return_const(());
+    audio_driver.expect_set_frequency().with(eq(Channel::CH1), eq(131072.0f32 / (2048.0 - 384.0))).once().return_const(());
+    controller.player_tick(&mut audio_driver);
+    assert_eq_hex!(controller.read(MemoryAddress::NR52) & 0x01, 0x01);
+  }
+
+  #[test]
+  fn powering_off_restarts_frame_sequencer_from_first_step() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = MockAudioDriver::new();
+    let mut timer = TimerControllerImpl::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    audio_driver.expect_set_stereo_gain().return_const(());
+    audio_driver.expect_set_frequency().return_const(());
+    audio_driver.expect_set_gain().return_const(());
+    audio_driver.expect_play_pulse().return_const(());
+    audio_driver.expect_stop().return_const(());
+    // Every 0x800 machine cycles, bit 4 of DIV falls and the frame sequencer takes a step
+    let mut run = |controller: &mut AudioControllerImpl, machine_cycles: usize| {
+      for _ in 0..machine_cycles {
+        timer.tick(&mut interrupt_controller);
+        controller.tick(&mut audio_driver, &timer, false);
+      }
+    };
+    controller.write(MemoryAddress::NR52, 0x80);
+    run(&mut controller, 3 * 0x800);
+    controller.write(MemoryAddress::NR52, 0x00);
+    run(&mut controller, 1);
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR21, 0x3F); // A single length step left
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0xC0); // Trigger with length enabled
+    assert_eq_hex!(controller.read(MemoryAddress::NR52) & 0x02, 0x02);
+    // The first step clocks the length timer
+    run(&mut controller, 0x800);
+    assert_eq_hex!(controller.read(MemoryAddress::NR52) & 0x02, 0x00);
+  }
+
+  #[test]
+  fn powering_off_clears_registers_and_blocks_writes() {
+    let mut controller = AudioControllerImpl::new();
+    controller.write(MemoryAddress::NR52, 0x80);
+    let registers = [
+      (MemoryAddress::NR10, 0x80), (MemoryAddress::NR11, 0x3F), (MemoryAddress::NR12, 0x00), (MemoryAddress::NR14, 0xBF),
+      (MemoryAddress::NR21, 0x3F), (MemoryAddress::NR22, 0x00), (MemoryAddress::NR24, 0xBF),
+      (MemoryAddress::NR30, 0x7F), (MemoryAddress::NR32, 0x9F), (MemoryAddress::NR34, 0xBF),
+      (MemoryAddress::NR42, 0x00), (MemoryAddress::NR43, 0x00), (MemoryAddress::NR44, 0xBF),
+      (MemoryAddress::NR50, 0x00), (MemoryAddress::NR51, 0x00),
+    ];
+    registers.iter().for_each(|(address, _)| controller.write(*address, 0x5A));
+    controller.write(0xFF30, 0xAB);
+    controller.write(MemoryAddress::NR52, 0x00);
+    for (address, cleared_value) in registers {
+      assert_eq_hex!(controller.read(address), cleared_value, "Register {:#06x}", address);
+      controller.write(address, 0x5A);
+      assert_eq_hex!(controller.read(address), cleared_value, "Register {:#06x}", address);
     }
+    assert_eq_hex!(controller.read(MemoryAddress::NR52), 0x70);
+    controller.write(0xFF30, 0xCD); // Wave RAM is still accessible
+    assert_eq_hex!(controller.read(0xFF30), 0xCD);
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR50, 0x77);
+    assert_eq_hex!(controller.read(MemoryAddress::NR50), 0x77);
+  }
+
+  #[test]
+  fn enabling_length_before_a_step_that_does_not_clock_it_clocks_it_once() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.frame_sequencer_step(&mut audio_driver);
+    controller.write(MemoryAddress::NR21, 0x3E); // Two length steps left
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0x80);
+    controller.write(MemoryAddress::NR24, 0x40);
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH2), 1);
+  }
+
+  #[test]
+  fn enabling_length_before_a_step_that_clocks_it_has_no_extra_clock() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR21, 0x3E); // Two length steps left
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0x80);
+    controller.write(MemoryAddress::NR24, 0x40);
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH2), 2);
+  }
+
+  #[test]
+  fn extra_length_clock_down_to_zero_stops_channel() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    let timer = TimerControllerImpl::new();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.frame_sequencer_step(&mut audio_driver);
+    controller.write(MemoryAddress::NR21, 0x3F); // A single length step left
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0x80);
+    controller.write(MemoryAddress::NR24, 0x40);
+    controller.tick(&mut audio_driver, &timer, false);
+    assert!(!ch2_playing(&controller));
+  }
+
+  #[test]
+  fn extra_length_clock_down_to_zero_is_undone_by_trigger_in_same_write() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    let timer = TimerControllerImpl::new();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.frame_sequencer_step(&mut audio_driver);
+    controller.write(MemoryAddress::NR21, 0x3F); // A single length step left
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0xC0);
+    controller.tick(&mut audio_driver, &timer, false);
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH2), 63);
+  }
+
+  #[test]
+  fn trigger_reloads_expired_length_timer() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0xC0);
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH2), 64);
+    // Triggering again doesn't reload the length timer if it hasn't run out
+    controller.write(MemoryAddress::NR21, 0x3C);
+    controller.write(MemoryAddress::NR24, 0xC0);
+    controller.write(MemoryAddress::NR24, 0xC0);
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH2), 4);
+  }
+
+  #[test]
+  fn trigger_with_expired_ch3_length_plays_for_full_length() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    audio_driver.expect_play_custom_wave().return_const(());
+    let timer = TimerControllerImpl::new();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.write(MemoryAddress::NR30, 0x80);
+    controller.write(MemoryAddress::NR31, 0xFF); // A single length step left
+    controller.write(MemoryAddress::NR34, 0xC0);
+    controller.tick(&mut audio_driver, &timer, false); // CH3 starts playing on the next tick
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH3), 1);
+    // The length timer expired on the last step, so triggering reloads it to the full length
+    controller.frame_sequencer_step(&mut audio_driver);
+    controller.write(MemoryAddress::NR34, 0xC0);
+    controller.tick(&mut audio_driver, &timer, false);
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH3), 256);
+  }
+
+  #[test]
+  fn trigger_before_a_step_that_does_not_clock_length_reloads_one_less() {
+    let mut controller = AudioControllerImpl::new();
+    let mut audio_driver = create_permissive_audio_driver();
+    controller.write(MemoryAddress::NR52, 0x80);
+    controller.frame_sequencer_step(&mut audio_driver);
+    controller.write(MemoryAddress::NR22, 0xF0);
+    controller.write(MemoryAddress::NR24, 0xC0);
+    assert_eq!(length_clocks_until_stopped(&mut controller, &mut audio_driver, Channel::CH2), 63);
   }
 }
@@ -121,6 +121,7 @@ impl Memory for ButtonControllerImpl {
 #[cfg(test)]
 mod tests {
   use assert_hex::assert_eq_hex;
+  use test_case::test_case;
 
   use crate::internal::cpu::interrupts::MockInterruptController;
   use crate::internal::memory::memory::MemoryAddress;
@@ -215,4 +216,19 @@ mod tests {
     interrupt_controller.expect_request_interrupt().once().return_const(());
     controller.tick(&mut interrupt_controller);
   }
-}
\ No newline at end of file
+
+  #[test_case(0x30, 0xFF; "no group selected")]
+  #[test_case(0x20, 0xED; "direction buttons selected")]
+  #[test_case(0x10, 0xDE; "action buttons selected")]
+  #[test_case(0x00, 0xCC; "both groups selected")]
+  #[test_case(0xF0, 0xFF; "unused bits are ignored on write")]
+  fn p1_reflects_selected_button_groups(value: u8, expected: u8) {
+    let mut controller = ButtonControllerImpl::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    interrupt_controller.expect_request_interrupt().return_const(());
+    controller.press_button(Button::A, &mut interrupt_controller);
+    controller.press_button(Button::LEFT, &mut interrupt_controller);
+    controller.write(MemoryAddress::P1, value);
+    assert_eq_hex!(controller.read(MemoryAddress::P1), expected);
+  }
+}
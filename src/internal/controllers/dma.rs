@@ -1,12 +1,16 @@
-use log::info;
 use serde::{Deserialize, Serialize};
 
 use crate::internal::controllers::lcd::{LCDController, LCDMode};
 use crate::internal::cpu::cpu::CPU;
+use crate::internal::infrastructure::logging;
 use crate::internal::infrastructure::toggle::Toggle;
 use crate::internal::memory::memory::{Memory, MemoryAddress};
 use crate::internal::util::bit_util::BitUtil;
 
+// The machine cycle in which DMA is written to is followed by another machine cycle of setup,
+// before the first byte is copied to OAM
+const LEGACY_DMA_STARTUP_DELAY: u8 = 2;
+
 #[derive(PartialEq, Serialize, Deserialize, Debug)]
 enum DMATransferType {
     Inactive,
@@ -22,6 +26,7 @@ struct DMATransfer {
     destination_address: u16,
     bytes_transferred: u16,
     bytes_to_transfer: u16,
+    startup_delay: u8,
 }
 
 impl DMATransfer {
@@ -32,6 +37,7 @@ impl DMATransfer {
             destination_address: 0,
             bytes_transferred: 0,
             bytes_to_transfer: 0,
+            startup_delay: 0,
         }
     }
 
@@ -42,6 +48,7 @@ impl DMATransfer {
             destination_address,
             bytes_to_transfer,
             bytes_transferred: 0,
+            startup_delay: 0,
         }
     }
 
@@ -52,8 +59,27 @@ impl DMATransfer {
             destination_address: 0,
             bytes_transferred: 0,
             bytes_to_transfer: 0,
+            startup_delay: LEGACY_DMA_STARTUP_DELAY,
         }
     }
+
+    // The source address is a plain 16 bit counter, but the destination address always stays within VRAM
+    fn source_address_at(&self, offset: u16) -> u16 {
+        self.source_address.wrapping_add(offset)
+    }
+
+    // VRAM can't be read while it's being written to, and the DMA can't reach the area from echo RAM onwards,
+    // so those sources read as 0xFF
+    fn read_source_byte(&self, memory: &dyn Memory, offset: u16) -> u8 {
+        match self.source_address_at(offset) {
+            0x8000..=0x9FFF | 0xE000..=0xFFFF => 0xFF,
+            address => memory.read(address)
+        }
+    }
+
+    fn destination_address_at(&self, offset: u16) -> u16 {
+        0x8000 | (self.destination_address.wrapping_add(offset) & 0x1FFF)
+    }
 }
 
 pub trait DMAController {
@@ -89,6 +115,10 @@ impl DMAControllerImpl {
     }
 
     fn handle_legacy_transfer(&mut self, memory: &mut dyn Memory) {
+        if self.active_transfer.startup_delay > 0 {
+            self.active_transfer.startup_delay -= 1;
+            return;
+        }
         let mut bytes_transferred = self.active_transfer.bytes_transferred;
         let current_byte = memory.read(self.active_transfer.source_address + bytes_transferred);
         memory.write(0xFE00 + bytes_transferred, current_byte);
@@ -105,12 +135,11 @@ impl DMAControllerImpl {
         }
         cpu.disable();
         let mut bytes_transferred = self.active_transfer.bytes_transferred;
-        let DMATransfer { source_address, destination_address, bytes_to_transfer, .. } = self.active_transfer;
-        let current_byte = memory.read(source_address + bytes_transferred);
-        memory.write(destination_address + (bytes_transferred as u16), current_byte);
+        let current_byte = self.active_transfer.read_source_byte(memory, bytes_transferred);
+        memory.write(self.active_transfer.destination_address_at(bytes_transferred), current_byte);
         bytes_transferred += 1;
         self.active_transfer.bytes_transferred = bytes_transferred;
-        if bytes_transferred == (bytes_to_transfer as u16) {
+        if bytes_transferred == self.active_transfer.bytes_to_transfer {
             self.active_transfer.transfer_type = DMATransferType::Inactive;
             self.hdma5 = 0xFF;
             cpu.enable();
@@ -132,15 +161,15 @@ impl DMAControllerImpl {
             return;
         }
         let mut bytes_transferred = self.active_transfer.bytes_transferred;
-        let DMATransfer { source_address, destination_address, bytes_to_transfer, .. } = self.active_transfer;
+        let bytes_to_transfer = self.active_transfer.bytes_to_transfer;
         if let LCDMode::HBlank = lcd.get_mode() {
             if self.should_cancel_hblank_transfer(cpu) {
                 self.cancel_hblank_transfer();
                 return;
             }
             cpu.disable();
-            let current_byte = memory.read(source_address + bytes_transferred as u16);
-            memory.write(destination_address + bytes_transferred as u16, current_byte);
+            let current_byte = self.active_transfer.read_source_byte(memory, bytes_transferred);
+            memory.write(self.active_transfer.destination_address_at(bytes_transferred), current_byte);
             bytes_transferred += 1;
             self.active_transfer.bytes_transferred = bytes_transferred;
             if bytes_transferred == bytes_to_transfer {
@@ -189,7 +218,8 @@ impl Memory for DMAControllerImpl {
             MemoryAddress::DMA => {
                 self.dma = value;
                 let source_address = (value as u16) * 0x100;
-                info!("Setting up Legacy DMATransfer from source address {:#x}", source_address);
+                logging::info(format_args!("Setting up Legacy DMATransfer from source address {:#x}", source_address));
+                // Writing DMA while a transfer is running abandons it and restarts from the new source
                 self.active_transfer = DMATransfer::legacy(source_address);
             }
             MemoryAddress::HDMA1 => self.high_source_address = value,
@@ -208,14 +238,15 @@ impl Memory for DMAControllerImpl {
                         } else {
                             DMATransferType::GeneralPurpose
                         };
-                        info!("Setting up {:?} DMATransfer from source address {:#x} to destination {:#x} of length {}", transfer_type, source_address, destination_address, bytes_to_transfer);
+                        logging::info(format_args!("Setting up {:?} DMATransfer from source address {:#x} to destination {:#x} of length {}", transfer_type, source_address, destination_address, bytes_to_transfer));
                         self.active_transfer = DMATransfer::new(
                             source_address,
                             destination_address,
                             bytes_to_transfer,
                             transfer_type,
                         );
-                        self.hdma5 = 0x00;
+                        // Bit 7 reads 0 while the transfer is active, the lower bits hold the number of remaining lines - 1
+                        self.hdma5 = value & 0x7F;
                     }
                     DMATransferType::HBlank if !value.get_bit(7) => {
                         self.cancel_requested.check();
@@ -232,8 +263,9 @@ impl Memory for DMAControllerImpl {
 mod tests {
     use assert_hex::assert_eq_hex;
 
-    use crate::internal::controllers::lcd::MockLCDController;
+    use crate::internal::controllers::lcd::{LCDControllerImpl, MockLCDController};
     use crate::internal::cpu::cpu::MockCPU;
+    use crate::internal::infrastructure::logging::test::CapturingLogger;
     use crate::internal::memory::memory::MemoryAddress;
     use crate::internal::memory::memory::test::MockMemory;
 
@@ -247,6 +279,15 @@ mod tests {
         memory
     }
 
+    #[test]
+    fn setting_up_transfer_emits_info_message() {
+        let logger = CapturingLogger::default();
+        logging::set_logger(Box::new(logger.clone()));
+        let mut dma = DMAControllerImpl::new();
+        dma.write(MemoryAddress::DMA, 0xC0);
+        assert_eq!(*logger.messages.borrow(), vec![("info", String::from("Setting up Legacy DMATransfer from source address 0xc000"))]);
+    }
+
     #[test]
     fn start_legacy_dma_transfer() {
         let mut dma = DMAControllerImpl::new();
@@ -256,6 +297,8 @@ mod tests {
         cpu.expect_enable().never();
         cpu.expect_disable().never();
         dma.write(MemoryAddress::DMA, 0xC0);
+        dma.tick(&mut memory, &mut cpu, &lcd, false);
+        dma.tick(&mut memory, &mut cpu, &lcd, false);
         for (index, address) in (0xFE00u16..=0xFE9Fu16).enumerate() {
             assert_eq_hex!(memory.read(address), 0x0000);
             dma.tick(&mut memory, &mut cpu, &mut lcd, false);
@@ -266,6 +309,50 @@ mod tests {
         assert_eq_hex!(memory.read(0x8190), 0x0000);
     }
 
+    #[test]
+    fn legacy_dma_transfer_has_startup_delay() {
+        let mut dma = DMAControllerImpl::new();
+        let mut memory = create_memory();
+        let mut cpu = MockCPU::new();
+        let lcd = MockLCDController::new();
+        memory.write(0xFE00, 0xAB);
+        dma.write(MemoryAddress::DMA, 0xC0);
+        dma.tick(&mut memory, &mut cpu, &lcd, false); // Machine cycle in which DMA was written
+        assert_eq_hex!(memory.read(0xFE00), 0xAB);
+        dma.tick(&mut memory, &mut cpu, &lcd, false); // Startup delay
+        assert_eq_hex!(memory.read(0xFE00), 0xAB);
+        dma.tick(&mut memory, &mut cpu, &lcd, false);
+        assert_eq_hex!(memory.read(0xFE00), 0x00);
+        assert_eq_hex!(memory.read(0xFE01), 0x00);
+        dma.tick(&mut memory, &mut cpu, &lcd, false);
+        assert_eq_hex!(memory.read(0xFE01), 0x01);
+    }
+
+    #[test]
+    fn writing_dma_during_legacy_transfer_restarts_from_new_source() {
+        let mut dma = DMAControllerImpl::new();
+        let mut memory = create_memory();
+        let mut cpu = MockCPU::new();
+        let lcd = MockLCDController::new();
+        for address in 0xD000u16..0xD0A0u16 {
+            memory.write(address, 0xFF - address as u8);
+        }
+        dma.write(MemoryAddress::DMA, 0xC0);
+        for _ in 0..(LEGACY_DMA_STARTUP_DELAY as u16 + 80) {
+            dma.tick(&mut memory, &mut cpu, &lcd, false);
+        }
+        assert_eq_hex!(memory.read(0xFE4F), 0x4F);
+        dma.write(MemoryAddress::DMA, 0xD0);
+        for _ in 0..(LEGACY_DMA_STARTUP_DELAY as u16 + 160) {
+            dma.tick(&mut memory, &mut cpu, &lcd, false);
+        }
+        for (index, address) in (0xFE00u16..=0xFE9Fu16).enumerate() {
+            assert_eq_hex!(memory.read(address), 0xFF - index as u8);
+        }
+        cpu.expect_enable().once().return_const(());
+        dma.tick(&mut memory, &mut cpu, &lcd, false);
+    }
+
     #[test]
     fn start_general_purpose_dma_transfer() {
         let mut dma = DMAControllerImpl::new();
@@ -290,6 +377,59 @@ mod tests {
         assert_eq_hex!(memory.read(0x8190), 0x0000);
     }
 
+    #[test]
+    fn general_purpose_dma_transfer_wraps_source_and_destination_addresses() {
+        let mut dma = DMAControllerImpl::new();
+        let mut memory = MockMemory::new();
+        let mut cpu = MockCPU::new();
+        let lcd = MockLCDController::new();
+        for offset in 0..0x20u16 {
+            memory.write(0xFFF0u16.wrapping_add(offset), 0x40 + offset as u8);
+        }
+        dma.write(MemoryAddress::HDMA1, 0xFF);
+        dma.write(MemoryAddress::HDMA2, 0xF0);
+        dma.write(MemoryAddress::HDMA3, 0x1F);
+        dma.write(MemoryAddress::HDMA4, 0xF0);
+        dma.write(MemoryAddress::HDMA5, 0x01); // Transfer 2 lines = 32 bytes
+        cpu.expect_disable().times(0x20).return_const(());
+        cpu.expect_enable().once().return_const(());
+        for _ in 0..0x20 {
+            dma.tick(&mut memory, &mut cpu, &lcd, false);
+        }
+        assert_eq_hex!(dma.read(MemoryAddress::HDMA5), 0xFF);
+        for offset in 0..0x10u16 {
+            assert_eq_hex!(memory.read(0x9FF0 + offset), 0xFF); // The first line is read from beyond echo RAM
+            assert_eq_hex!(memory.read(0x8000 + offset), 0x50 + offset as u8);
+        }
+    }
+
+    #[test]
+    fn general_purpose_dma_transfer_from_vram_or_echo_ram_reads_ff() {
+        let mut dma = DMAControllerImpl::new();
+        let mut memory = MockMemory::new();
+        let mut cpu = MockCPU::new();
+        let lcd = MockLCDController::new();
+        for offset in 0..0x10u16 {
+            memory.write(0x8800 + offset, 0x40 + offset as u8);
+            memory.write(0xE000 + offset, 0x50 + offset as u8);
+        }
+        cpu.expect_disable().times(0x20).return_const(());
+        cpu.expect_enable().times(2).return_const(());
+        for (high_source_address, destination_offset) in [(0x88, 0x00), (0xE0, 0x10)] {
+            dma.write(MemoryAddress::HDMA1, high_source_address);
+            dma.write(MemoryAddress::HDMA2, 0x00);
+            dma.write(MemoryAddress::HDMA3, 0x10);
+            dma.write(MemoryAddress::HDMA4, destination_offset);
+            dma.write(MemoryAddress::HDMA5, 0x00); // Transfer 1 line = 16 bytes
+            for _ in 0..0x10 {
+                dma.tick(&mut memory, &mut cpu, &lcd, false);
+            }
+        }
+        for offset in 0..0x20u16 {
+            assert_eq_hex!(memory.read(0x9000 + offset), 0xFF);
+        }
+    }
+
     #[test]
     fn start_hblank_dma_transfer() {
         let mut dma = DMAControllerImpl::new();
@@ -338,6 +478,33 @@ mod tests {
         assert_eq_hex!(memory.read(0x8190), 0x0000);
     }
 
+    #[test]
+    fn hblank_dma_transfers_bytes_while_ppu_is_in_hblank() {
+        let mut dma = DMAControllerImpl::new();
+        let mut memory = create_memory();
+        let mut cpu = MockCPU::new();
+        let mut lcd = LCDControllerImpl::new();
+        lcd.force_mode(LCDMode::HBlank, 0);
+        dma.write(MemoryAddress::HDMA1, 0xC0);
+        dma.write(MemoryAddress::HDMA2, 0x00);
+        dma.write(MemoryAddress::HDMA3, 0x01);
+        dma.write(MemoryAddress::HDMA4, 0x00);
+        dma.write(MemoryAddress::HDMA5, 0x81); // Transfer 2 lines = 32 bytes
+
+        cpu.expect_enabled().return_const(false);
+        cpu.expect_disable().times(0x10).return_const(());
+        for (index, address) in (0x8100u16..0x8110u16).enumerate() {
+            dma.tick(&mut memory, &mut cpu, &lcd, false);
+            assert_eq_hex!(memory.read(address), index as u8);
+        }
+        assert_eq_hex!(dma.read(MemoryAddress::HDMA5), 0x00); // One line remaining
+
+        lcd.force_mode(LCDMode::Mode2, 1);
+        cpu.expect_enable().once().return_const(());
+        dma.tick(&mut memory, &mut cpu, &lcd, false);
+        assert_eq_hex!(memory.read(0x8110), 0x00);
+    }
+
     #[test]
     fn cancel_hblank_dma_transfer() {
         let mut dma = DMAControllerImpl::new();
@@ -389,4 +556,87 @@ mod tests {
         assert_eq_hex!(memory.read(0x813F), 0x1F);
         assert_eq_hex!(memory.read(0x8140), 0x00);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hdma5_reports_remaining_lines_during_hblank_transfer() {
+        let mut dma = DMAControllerImpl::new();
+        let mut memory = create_memory();
+        let mut cpu = MockCPU::new();
+        let mut lcd = MockLCDController::new();
+        dma.write(MemoryAddress::HDMA1, 0xC0);
+        dma.write(MemoryAddress::HDMA2, 0x00);
+        dma.write(MemoryAddress::HDMA3, 0x01);
+        dma.write(MemoryAddress::HDMA4, 0x00);
+        dma.write(MemoryAddress::HDMA5, 0x82); // Transfer 3 lines
+        assert_eq_hex!(dma.read(MemoryAddress::HDMA5), 0x02);
+
+        lcd.expect_get_mode().return_const(LCDMode::HBlank);
+        cpu.expect_disable().return_const(());
+        cpu.expect_enabled().return_const(false);
+        cpu.expect_enable().once().return_const(());
+        for line in 0..3u8 {
+            for byte in 0..0x10 {
+                dma.tick(&mut memory, &mut cpu, &lcd, false);
+                let expected_hdma5 = if byte < 0x0F { 2 - line } else if line < 2 { 1 - line } else { 0xFF };
+                assert_eq_hex!(dma.read(MemoryAddress::HDMA5), expected_hdma5);
+            }
+        }
+    }
+
+    #[test]
+    fn hblank_dma_transfer_resumes_after_serialization() {
+        let mut dma = DMAControllerImpl::new();
+        let mut memory = create_memory();
+        let mut cpu = MockCPU::new();
+        let mut lcd = MockLCDController::new();
+        dma.write(MemoryAddress::HDMA1, 0xC0);
+        dma.write(MemoryAddress::HDMA2, 0x00);
+        dma.write(MemoryAddress::HDMA3, 0x01);
+        dma.write(MemoryAddress::HDMA4, 0x00);
+        dma.write(MemoryAddress::HDMA5, 0x81); // Transfer 2 lines = 2 x 16 byte = 32 byte
+
+        lcd.expect_get_mode()
+            .times(0x10)
+            .return_const(LCDMode::HBlank);
+        cpu.expect_disable()
+            .times(0x10)
+            .return_const(());
+        cpu.expect_enabled()
+            .times(0x10)
+            .return_const(false);
+        for _ in 0usize..0x10 {
+            dma.tick(&mut memory, &mut cpu, &lcd, false);
+        }
+        let hdma5 = dma.read(MemoryAddress::HDMA5);
+
+        // Save the controller halfway through the transfer and continue with a freshly loaded one
+        let state = bincode::serialize(&dma).unwrap();
+        let mut dma: DMAControllerImpl = bincode::deserialize(&state).unwrap();
+        assert_eq_hex!(dma.read(MemoryAddress::HDMA5), hdma5);
+        let mut cpu = MockCPU::new();
+        let mut lcd = MockLCDController::new();
+        lcd.expect_get_mode()
+            .times(0x10)
+            .return_const(LCDMode::HBlank);
+        cpu.expect_disable()
+            .times(0x10)
+            .return_const(());
+        cpu.expect_enabled()
+            .times(0x10)
+            .return_const(false);
+        for (index, address) in (0x8110u16..0x8120u16).enumerate() {
+            if index == 0x0F {
+                cpu.expect_enable()
+                    .once()
+                    .return_const(());
+            }
+            assert_eq_hex!(memory.read(address), 0x00);
+            dma.tick(&mut memory, &mut cpu, &lcd, false);
+            assert_eq_hex!(memory.read(address), (0x10 + index) as u8);
+        }
+        assert_eq_hex!(dma.read(MemoryAddress::HDMA5), 0xFF);
+        for address in 0x8100u16..0x8110u16 {
+            assert_eq_hex!(memory.read(address), address as u8);
+        }
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::memory::OAMObject;
 use crate::internal::cpu::interrupts::{Interrupt, InterruptController};
-use crate::internal::memory::cram::CRAM;
+use crate::internal::memory::cram::{ColorReference, CRAM};
 use crate::internal::memory::memory::{Memory, MemoryAddress};
 use crate::internal::memory::oam::{OAM, ObjectReference};
 use crate::internal::memory::vram::{BackgroundParams, ObjectParams, Point, TileAddressingMode, TileMapIndex, VRAM, WindowParams};
@@ -13,6 +13,11 @@ use crate::renderer::{Color, Renderer, RenderTarget};
 use crate::internal::util::bit_util::BitUtil;
 
 const DOTS_PER_FRAME: u32 = 70224;
+const DOTS_PER_LINE: u32 = 456;
+const MODE_3_START_COLUMN: u16 = 80;
+const MODE_3_MINIMUM_END_COLUMN: u16 = 248;
+const WINDOW_START_PENALTY: u16 = 6;
+const VBLANK_START_LINE: u32 = 144;
 
 #[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LCDMode {
@@ -98,6 +103,7 @@ impl LCDC {
 #[automock]
 pub trait LCDController {
   fn get_mode(&self) -> LCDMode;
+  fn lcd_enabled(&self) -> bool;
 }
 
 #[derive(Serialize, Deserialize)]
@@ -109,10 +115,20 @@ pub struct LCDControllerImpl {
   line_rendered: bool,
   column: u16,
   mode: LCDMode,
+  mode_3_end_column: u16,
   lcdc: LCDC,
   stat: Stat,
+  // Right after the LCD is turned on, line 0 has no OAM scan. It stays in HBlank until Mode 3 starts.
+  first_line_after_enable: bool,
   interrupt_line: bool,  // The STAT interrupt is triggered on the rising edge of this line (which is the OR'ed combination of the various sources that can trigger the input)
   opri: u8,
+  // Set through KEY0 by the boot ROM. The PPU then uses the monochrome palettes and DMG meaning of LCDC bit 0.
+  dmg_compatibility: bool,
+  // KEY0 and OPRI can only be written by the boot ROM
+  boot_registers_locked: bool,
+  // In headless mode the LCD keeps its timing and interrupts, but never draws or touches the renderer
+  #[serde(skip)]
+  headless: bool,
   scy: u8,
   scx: u8,
   lyc: u8,
@@ -124,6 +140,10 @@ impl LCDController for LCDControllerImpl {
   fn get_mode(&self) -> LCDMode {
     self.mode
   }
+
+  fn lcd_enabled(&self) -> bool {
+    self.lcdc.lcd_enabled()
+  }
 }
 
 impl LCDControllerImpl {
@@ -136,10 +156,15 @@ impl LCDControllerImpl {
       line_rendered: false,
       column: 0,
       mode: LCDMode::Mode2,
+      mode_3_end_column: MODE_3_MINIMUM_END_COLUMN,
       lcdc: LCDC(0x91),
       stat: Stat(0x02), // TODO: Implement writing these registers correctly
+      first_line_after_enable: false,
       interrupt_line: false,
       opri: 0,
+      dmg_compatibility: false,
+      boot_registers_locked: false,
+      headless: false,
       scy: 0,
       scx: 0,
       lyc: 0,
@@ -148,28 +173,122 @@ impl LCDControllerImpl {
     }
   }
 
+  pub fn headless(&self) -> bool {
+    self.headless
+  }
+
+  pub fn set_headless(&mut self, headless: bool) {
+    self.headless = headless;
+  }
+
   pub fn use_8_x_16_tiles(&self) -> bool {
     self.lcdc.use_8_x_16_tiles()
   }
 
-  fn find_intersecting_objects(&mut self, oam: &dyn OAM) {
-    let use_8_x_16_tiles = self.lcdc.use_8_x_16_tiles();
-    if self.intersecting_object_references.len() < 10 && self.column % 4 == 0 {
-      let object_index = (self.column / 2) as u8;
-      if let Some(object_reference) = oam.get_object_reference_if_intersects(object_index, self.line, use_8_x_16_tiles) {
-        self.intersecting_object_references.push(object_reference);
+  // The objects selected for the current line. These are only known once the OAM scan completes, so the list is
+  // empty outside of Mode 3.
+  pub fn current_line_objects(&self) -> &[ObjectReference] {
+    if self.mode == LCDMode::Mode3 {
+      &self.intersecting_object_references
+    } else {
+      &[]
+    }
+  }
+
+  // The color of a line that hasn't been drawn yet: color 0 of background palette 0
+  pub fn blank_color(&self, cram: &dyn CRAM) -> Color {
+    let color_ref = ColorReference { color_index: 0, palette_index: 0, foreground: false };
+    if self.dmg_compatibility { cram.monochrome_background_color(color_ref) } else { cram.background_color(color_ref) }
+  }
+
+  // The number of lines of the current frame that have been drawn to the main render target so far
+  pub fn lines_drawn(&self) -> u8 {
+    if !self.lcdc.lcd_enabled() {
+      0
+    } else if self.mode == LCDMode::VBlank {
+      144
+    } else if self.line_rendered {
+      self.line + 1
+    } else {
+      self.line
+    }
+  }
+
+  // Returns the number of dots until the next point at which the LCD could request a STAT or VBlank interrupt, or
+  // None while the LCD is off and can't request either
+  pub fn dots_until_next_stat_event(&self) -> Option<u32> {
+    if !self.lcdc.lcd_enabled() {
+      return None;
+    }
+    let line = self.dot / DOTS_PER_LINE;
+    let column = self.dot % DOTS_PER_LINE;
+    // The visible line after the current one, which is line 0 of the next frame from line 143 onwards
+    let next_line = if line + 1 < VBLANK_START_LINE { line + 1 } else { 0 };
+    let mode_2_dot = self.stat.interrupt_enabled_for_mode(LCDMode::Mode2)
+      .then(|| next_line * DOTS_PER_LINE);
+    let hblank_dot = self.stat.interrupt_enabled_for_mode(LCDMode::HBlank).then(|| {
+      let hblank_start_column = if self.mode == LCDMode::Mode3 {
+        self.mode_3_end_column
+      } else {
+        self.calculate_mode_3_end_column()
+      } as u32;
+      if line < VBLANK_START_LINE && column < hblank_start_column {
+        line * DOTS_PER_LINE + hblank_start_column
+      } else {
+        next_line * DOTS_PER_LINE + self.calculate_mode_3_end_column() as u32
       }
+    });
+    let lyc_dot = (self.stat.lyc_interrupt_enabled() && (self.lyc as u32) < DOTS_PER_FRAME / DOTS_PER_LINE)
+      .then(|| self.lyc as u32 * DOTS_PER_LINE);
+    [Some(VBLANK_START_LINE * DOTS_PER_LINE), mode_2_dot, hblank_dot, lyc_dot].into_iter()
+      .flatten()
+      .map(|event_dot| (event_dot + DOTS_PER_FRAME - self.dot - 1) % DOTS_PER_FRAME + 1)
+      .min()
+  }
+
+  // Moves the PPU straight to the start of the given mode on the given line, so tests don't have to tick their way
+  // there. The STAT line is updated without requesting an interrupt.
+  #[cfg(test)]
+  pub fn force_mode(&mut self, mode: LCDMode, line: u8) {
+    assert_eq!(mode == LCDMode::VBlank, (VBLANK_START_LINE..154).contains(&(line as u32)),
+               "Line {} can't be in the requested mode", line);
+    self.line = line;
+    self.mode_3_end_column = self.calculate_mode_3_end_column();
+    self.column = match mode {
+      LCDMode::HBlank => self.mode_3_end_column,
+      LCDMode::Mode3 => MODE_3_START_COLUMN,
+      LCDMode::Mode2 | LCDMode::VBlank => 0,
+    };
+    self.dot = line as u32 * DOTS_PER_LINE + self.column as u32;
+    self.mode = mode;
+    self.stat.set_mode(mode);
+    self.stat.set_lyc_equals_line(self.line == self.lyc);
+    self.line_rendered = matches!(mode, LCDMode::HBlank | LCDMode::VBlank);
+    self.first_line_after_enable = false;
+    self.intersecting_object_references.clear();
+    self.current_object_index = 0;
+    self.interrupt_line =
+      self.stat.interrupt_enabled_for_mode(self.mode) ||
+        (self.stat.lyc_equals_line() && self.stat.lyc_interrupt_enabled());
+  }
+
+  // The OAM scan checks 2 objects every 4 dots. This scans every object up to the given index that hasn't been
+  // scanned yet, so it catches up on dots that were skipped.
+  fn find_intersecting_objects(&mut self, oam: &dyn OAM, end_object_index: u8) {
+    let use_8_x_16_tiles = self.lcdc.use_8_x_16_tiles();
+    while self.current_object_index < end_object_index {
       if self.intersecting_object_references.len() < 10 {
-        if let Some(object_reference) = oam.get_object_reference_if_intersects(object_index + 1, self.line, use_8_x_16_tiles) {
+        if let Some(object_reference) = oam.get_object_reference_if_intersects(self.current_object_index, self.line, use_8_x_16_tiles) {
           self.intersecting_object_references.push(object_reference);
         }
       }
+      self.current_object_index += 1;
     }
   }
 
   fn draw_background_line(&self, vram: &dyn VRAM, cram: &dyn CRAM, renderer: &mut dyn Renderer) {
     // Don't draw the background line if we're in monochrome mode and bg_priority bit is cleared
-    if self.opri == 1 && !self.lcdc.bg_priority() {
+    if self.dmg_compatibility && !self.lcdc.bg_priority() {
       return;
     }
     let color_references = vram.background_line_colors(BackgroundParams {
@@ -182,7 +301,7 @@ impl LCDControllerImpl {
       },
     });
     color_references.into_iter()
-      .map(|color_ref| (color_ref, if self.opri == 1 { cram.monochrome_background_color(color_ref) } else { cram.background_color(color_ref) }))
+      .map(|color_ref| (color_ref, if self.dmg_compatibility { cram.monochrome_background_color(color_ref) } else { cram.background_color(color_ref) }))
       .enumerate()
       .for_each(|(x, (color_ref, color))| {
         let background_draw_depth = if color_ref.color_index == 0 || !self.lcdc.bg_priority() {
@@ -197,12 +316,28 @@ impl LCDControllerImpl {
   }
 
   fn should_draw_window_line(&self) -> bool {
-    (self.opri == 0 || self.lcdc.bg_priority()) &&
+    (!self.dmg_compatibility || self.lcdc.bg_priority()) &&
       self.wy <= self.line &&
       self.wy <= 143 &&
       self.wx <= 166
   }
 
+  // Mode 3 takes longer when the PPU has to discard pixels for fine scrolling, or has to restart fetching for the window
+  fn calculate_mode_3_end_column(&self) -> u16 {
+    let window_penalty = if self.lcdc.windowing_enabled() && self.should_draw_window_line() { WINDOW_START_PENALTY } else { 0 };
+    MODE_3_MINIMUM_END_COLUMN + (self.scx % 8) as u16 + window_penalty
+  }
+
+  // The window starts at WX - 7, so for WX < 7 its leftmost pixels fall off the screen. With WX = 0, the window
+  // already starts while the PPU is discarding pixels for the fine scroll, so SCX % 8 more of its pixels are lost.
+  fn discarded_window_pixels(&self) -> u8 {
+    match self.wx {
+      0 => 7 + self.scx % 8,
+      1..=6 => 7 - self.wx,
+      _ => 0,
+    }
+  }
+
   fn draw_window_line(&self, vram: &dyn VRAM, cram: &dyn CRAM, renderer: &mut dyn Renderer) {
     if self.lcdc.windowing_enabled() && self.should_draw_window_line() {
       let color_references = vram.window_line_colors(WindowParams {
@@ -213,10 +348,11 @@ impl LCDControllerImpl {
           x: self.wx,
           y: self.wy,
         },
+        discarded_pixels: self.discarded_window_pixels(),
       });
       color_references.into_iter()
         .map(|color_ref| {
-          let color = if self.opri == 1 {
+          let color = if self.dmg_compatibility {
             cram.monochrome_background_color(color_ref)
           } else {
             cram.background_color(color_ref)
@@ -224,9 +360,8 @@ impl LCDControllerImpl {
           color
         })
         .enumerate()
-        .skip(if self.wx < 7 { 7 - self.wx as usize } else { 0 })
         .for_each(|(x, color)| {
-          renderer.draw_pixel(x + self.wx as usize - 7, self.line as usize, 0xFF, color, RenderTarget::Main);
+          renderer.draw_pixel(x + self.wx.saturating_sub(7) as usize, self.line as usize, 0xFF, color, RenderTarget::Main);
         });
     }
   }
@@ -250,62 +385,76 @@ impl LCDControllerImpl {
       });
     }
 
+    // The objects are in priority order: by OAM index, or by X coordinate when OPRI is set. The first opaque object
+    // pixel at a position wins, so pixels of lower priority objects underneath it aren't drawn.
+    let mut covered = [false; 160];
     objects.into_iter()
       .filter(|object| object.lcd_x != 0 && object.lcd_x <= 168)
       .for_each(|object| {
         let params = ObjectParams {
           object,
           row: self.line + 16 - object.lcd_y,
-          monochrome: self.opri == 1,
+          monochrome: self.dmg_compatibility,
         };
         let colors = vram.object_line_colors(params);
         colors.into_iter()
-          .map(|color_ref| (color_ref, if self.opri == 1 { cram.monochrome_object_color(color_ref) } else { cram.object_color(color_ref) }))
+          .map(|color_ref| (color_ref, if self.dmg_compatibility { cram.monochrome_object_color(color_ref) } else { cram.object_color(color_ref) }))
           .enumerate()
           .skip(if object.lcd_x < 8 { 8 - object.lcd_x } else { 0 } as usize)
           .take(if object.lcd_x > 160 { 168 - object.lcd_x } else { 8 } as usize)
           .for_each(|(pixel_offset, (color_ref, color))| {
+            let x = object.lcd_x as usize + pixel_offset - 8;
+            if covered[x] {
+              return;
+            }
+            covered[x] = !color.transparent;
             let obj_draw_depth = if color_ref.foreground {
               5
             } else {
               2
             };
-            renderer.draw_pixel(object.lcd_x as usize + pixel_offset - 8, self.line as usize, obj_draw_depth, color, RenderTarget::Main);
+            renderer.draw_pixel(x, self.line as usize, obj_draw_depth, color, RenderTarget::Main);
           });
       });
   }
 
+  // The atlas shows the 40 objects in two rows of 20. Objects that are hidden because they're positioned
+  // off-screen are drawn as transparent cells, so they're easy to tell apart from visible ones.
   fn draw_obj_atlas_line(&self, vram: &dyn VRAM, cram: &dyn CRAM, oam: &dyn OAM, renderer: &mut dyn Renderer) {
-    if self.line < 32 {
-      let row = self.line % 8;
-      let object_range = if self.line < 8 || (self.line < 16 && self.lcdc.use_8_x_16_tiles()) {
-        0..20u8
-      } else if self.line < 24 || (self.line < 32 && self.lcdc.use_8_x_16_tiles()) {
-        20..40u8
-      } else {
-        0..0u8
-      };
-      object_range.into_iter()
-        .for_each(|object_index| {
-          let object = oam.get_object(ObjectReference {
-            object_index,
-            use_bottom_tile: (self.line % 16) > 7,
-          }, self.lcdc.use_8_x_16_tiles());
-          let params = ObjectParams {
-            object,
-            row,
-            monochrome: self.opri == 1,
-          };
-          let column_offset = (object_index % 20) * 8;
-          let colors = vram.object_line_colors(params);
-          colors.into_iter()
-            .map(|color_ref| if self.opri == 1 { cram.monochrome_object_color(color_ref) } else { cram.object_color(color_ref) })
-            .enumerate()
-            .for_each(|(pixel_offset, color)| {
-              renderer.draw_pixel(column_offset as usize + pixel_offset, self.line as usize, 5, color, RenderTarget::ObjectAtlas);
-            });
-        })
+    let use_8_x_16_tiles = self.lcdc.use_8_x_16_tiles();
+    let object_height = if use_8_x_16_tiles { 16u8 } else { 8u8 };
+    let atlas_row = self.line / object_height;
+    if atlas_row > 1 {
+      return;
     }
+    let object_row = self.line % object_height;
+    (20 * atlas_row..20 * (atlas_row + 1)).for_each(|object_index| {
+      let top_object = oam.get_object(ObjectReference { object_index, use_bottom_tile: false }, use_8_x_16_tiles);
+      let hidden = top_object.lcd_y <= 16 - object_height || top_object.lcd_y >= 160 ||
+        top_object.lcd_x == 0 || top_object.lcd_x >= 168;
+      let column_offset = (object_index % 20) as usize * 8;
+      if hidden {
+        (0..8).for_each(|pixel_offset| {
+          renderer.draw_pixel(column_offset + pixel_offset, self.line as usize, 5, Color::transparent(), RenderTarget::ObjectAtlas);
+        });
+        return;
+      }
+      let object = oam.get_object(ObjectReference {
+        object_index,
+        use_bottom_tile: object_row > 7,
+      }, use_8_x_16_tiles);
+      let params = ObjectParams {
+        object,
+        row: object_row % 8,
+        monochrome: self.dmg_compatibility,
+      };
+      vram.object_line_colors(params).into_iter()
+        .map(|color_ref| if self.dmg_compatibility { cram.monochrome_object_color(color_ref) } else { cram.object_color(color_ref) })
+        .enumerate()
+        .for_each(|(pixel_offset, color)| {
+          renderer.draw_pixel(column_offset + pixel_offset, self.line as usize, 5, color, RenderTarget::ObjectAtlas);
+        });
+    })
   }
 
   fn draw_tile_atlas_line(&self, vram: &dyn VRAM, renderer: &mut dyn Renderer) {
@@ -329,6 +478,9 @@ impl LCDControllerImpl {
   }
 
   fn draw_line(&self, vram: &dyn VRAM, cram: &dyn CRAM, oam: &dyn OAM, renderer: &mut dyn Renderer) {
+    if self.headless {
+      return;
+    }
     if renderer.render_target_is_enabled(RenderTarget::Main) {
       self.draw_background_line(vram, cram, renderer);
       self.draw_window_line(vram, cram, renderer);
@@ -342,19 +494,45 @@ impl LCDControllerImpl {
     }
   }
 
+  // The LCD shows a blank screen while the system is halted in STOP mode
+  pub fn draw_blank_screen(&self, renderer: &mut dyn Renderer) {
+    if !self.headless && renderer.render_target_is_enabled(RenderTarget::Main) {
+      for y in 0..144 {
+        for x in 0..160 {
+          renderer.draw_pixel(x, y, 0xFF, Color::white(), RenderTarget::Main);
+        }
+      }
+      renderer.flush();
+    }
+  }
+
   fn update_mode(&mut self) {
+    let previous_mode = self.mode;
     self.mode = if self.line >= 144 {
       LCDMode::VBlank
+    } else if self.column < MODE_3_START_COLUMN {
+      if self.first_line_after_enable { LCDMode::HBlank } else { LCDMode::Mode2 }
     } else {
-      match self.column {
-        0..=79 => LCDMode::Mode2,
-        80..=247 => LCDMode::Mode3,
-        _ => LCDMode::HBlank
+      if previous_mode == LCDMode::Mode2 || self.first_line_after_enable {
+        self.mode_3_end_column = self.calculate_mode_3_end_column();
+        self.first_line_after_enable = false;
       }
+      if self.column < self.mode_3_end_column { LCDMode::Mode3 } else { LCDMode::HBlank }
     };
     self.stat.set_mode(self.mode);
   }
 
+  // The mode and coincidence bits are derived from the live PPU state, so writes to LYC or LCDC are reflected
+  // right away instead of after the next tick. While the LCD is off, the mode bits read as HBlank.
+  fn read_stat(&self) -> u8 {
+    let mut stat = Stat(self.stat.0);
+    stat.set_lyc_equals_line(self.line == self.lyc);
+    stat.set_mode(if self.lcdc.lcd_enabled() { self.mode } else { LCDMode::HBlank });
+    0x80 | stat.0
+  }
+
+  // Mode 3 has no STAT source of its own, so entering it can only lower the STAT line. The line can still rise
+  // during Mode 3 through the LY=LYC source, but only when LYC is written to match LY while the line is low.
   fn maybe_request_interrupt(&mut self, interrupt_controller: &mut dyn InterruptController) {
     let new_interrupt_line =
       self.stat.interrupt_enabled_for_mode(self.mode) ||
@@ -365,6 +543,20 @@ impl LCDControllerImpl {
     self.interrupt_line = new_interrupt_line;
   }
 
+  // Turning the LCD on restarts the frame at line 0. That first line skips the OAM scan, so it has no objects and
+  // no Mode 2 STAT interrupt.
+  fn enable(&mut self) {
+    self.dot = 0;
+    self.line = 0;
+    self.column = 0;
+    self.mode = LCDMode::HBlank;
+    self.stat.set_mode(LCDMode::HBlank);
+    self.line_rendered = false;
+    self.first_line_after_enable = true;
+    self.intersecting_object_references.clear();
+    self.current_object_index = 0;
+  }
+
   pub fn tick(&mut self, vram: &dyn VRAM, cram: &dyn CRAM, oam: &dyn OAM,
               renderer: &mut dyn Renderer,
               interrupt_controller: &mut dyn InterruptController,
@@ -377,7 +569,13 @@ impl LCDControllerImpl {
      * 168-291 dots spent in mode 3 (rendering the image), and the remaining dots spent in HBlank
      */
     let number_of_dots_for_tick = if double_speed { 2u32 } else { 4u32 };
-    self.dot = (self.dot + number_of_dots_for_tick) % DOTS_PER_FRAME;
+    self.advance(number_of_dots_for_tick, vram, cram, oam, renderer, interrupt_controller);
+  }
+
+  fn advance(&mut self, dots: u32, vram: &dyn VRAM, cram: &dyn CRAM, oam: &dyn OAM,
+             renderer: &mut dyn Renderer,
+             interrupt_controller: &mut dyn InterruptController) {
+    self.dot = (self.dot + dots) % DOTS_PER_FRAME;
     if !self.lcdc.lcd_enabled() {
       return;
     }
@@ -388,13 +586,14 @@ impl LCDControllerImpl {
     self.stat.set_lyc_equals_line(self.line == self.lyc);
 
 
+    let previous_mode = self.mode;
     self.update_mode();
     self.maybe_request_interrupt(interrupt_controller);
 
 
     match self.mode {
       LCDMode::HBlank => {
-        if self.column == 248 {
+        if previous_mode != LCDMode::HBlank {
           self.intersecting_object_references.clear();
           self.current_object_index = 0;
         }
@@ -402,14 +601,19 @@ impl LCDControllerImpl {
       LCDMode::VBlank => {
         if self.column == 0 && self.line == 144 {
           interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
-          renderer.flush();
+          if !self.headless {
+            renderer.flush();
+          }
         }
       }
       LCDMode::Mode2 => {
         self.line_rendered = false;
-        self.find_intersecting_objects(oam);
+        self.find_intersecting_objects(oam, ((self.column / 4) * 2 + 2).min(40) as u8);
       }
       LCDMode::Mode3 => {
+        if previous_mode == LCDMode::Mode2 {
+          self.find_intersecting_objects(oam, 40);
+        }
         if !self.line_rendered {
           self.draw_line(vram, cram, oam, renderer);
           self.line_rendered = true;
@@ -423,28 +627,45 @@ impl Memory for LCDControllerImpl {
   fn read(&self, address: u16) -> u8 {
     match address {
       MemoryAddress::LCDC => self.lcdc.0,
-      MemoryAddress::STAT => 0x80 | self.stat.0,
+      MemoryAddress::STAT => self.read_stat(),
       MemoryAddress::SCY => self.scy,
       MemoryAddress::SCX => self.scx,
       MemoryAddress::LY => self.line,
       MemoryAddress::LYC => self.lyc,
       MemoryAddress::WY => self.wy,
       MemoryAddress::WX => self.wx,
-      MemoryAddress::OPRI => self.opri,
+      // Only bit 0 of OPRI is implemented, the unused bits read as 1
+      MemoryAddress::OPRI => 0xFE | self.opri,
       _ => panic!("Unable to read address {:#x} from LCD Controller", address)
     }
   }
 
   fn write(&mut self, address: u16, value: u8) {
     match address {
-      MemoryAddress::LCDC => self.lcdc.0 = value,
+      MemoryAddress::LCDC => {
+        let was_enabled = self.lcdc.lcd_enabled();
+        self.lcdc.0 = value;
+        if !was_enabled && self.lcdc.lcd_enabled() {
+          self.enable();
+        }
+      }
       MemoryAddress::STAT => self.stat.0 = (self.stat.0 & 0x7) | (value & 0xF8),
       MemoryAddress::SCY => self.scy = value,
       MemoryAddress::SCX => self.scx = value,
       MemoryAddress::LYC => self.lyc = value,
       MemoryAddress::WY => self.wy = value,
       MemoryAddress::WX => self.wx = value,
-      MemoryAddress::OPRI => self.opri = value,
+      // The object priority mode and DMG compatibility can only be changed by the boot ROM, they're locked once the
+      // boot ROM hands off control
+      MemoryAddress::KEY0 => if !self.boot_registers_locked {
+        self.dmg_compatibility = value.get_bit(2)
+      },
+      MemoryAddress::OPRI => if !self.boot_registers_locked {
+        self.opri = value & 0x01
+      },
+      MemoryAddress::BANK => if value != 0 {
+        self.boot_registers_locked = true
+      },
       _ => panic!("Unable to write to address {:#x} in LCD Controller", address)
     }
   }
@@ -452,16 +673,588 @@ impl Memory for LCDControllerImpl {
 
 #[cfg(test)]
 pub mod tests {
-  use mockall::predicate::eq;
+  use std::sync::{Arc, Mutex};
+
+  use assert_hex::assert_eq_hex;
+  use mockall::predicate::{always, eq};
 
-  use crate::internal::cpu::interrupts::MockInterruptController;
-  use crate::internal::memory::cram::{ColorReference, MockCRAM};
-  use crate::internal::memory::oam::MockOAM;
-  use crate::internal::memory::vram::MockVRAM;
+  use crate::internal::cpu::interrupts::{InterruptControllerImpl, MockInterruptController};
+  use crate::internal::memory::cram::{ColorReference, CRAMImpl, MockCRAM};
+  use crate::internal::memory::oam::{MockOAM, OAMImpl};
+  use crate::internal::memory::vram::{MockVRAM, VRAMImpl};
   use crate::renderer::MockRenderer;
 
   use super::*;
 
+  fn window_pixel_columns(wx: u8) -> Vec<usize> {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::LCDC, 0xB1);
+    controller.write(MemoryAddress::WX, wx);
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut renderer = MockRenderer::new();
+    let columns = Arc::new(Mutex::new(vec![]));
+    let drawn_columns = columns.clone();
+    renderer.expect_draw_pixel().returning(move |x, _, _, _, _| drawn_columns.lock().unwrap().push(x));
+    controller.draw_window_line(&vram, &cram, &mut renderer);
+    let columns = columns.lock().unwrap().clone();
+    columns
+  }
+
+  // Window tile column 2 is filled with color 1, so window pixels 16 to 23 are the only ones that aren't black
+  fn highlighted_window_columns(wx: u8, scx: u8) -> Vec<usize> {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::LCDC, 0xB1);
+    controller.write(MemoryAddress::WX, wx);
+    controller.write(MemoryAddress::SCX, scx);
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    cram.write_raw_palette(false, 0, [0x0000, 0x7FFF, 0x0000, 0x0000]);
+    vram.write(0x9802, 1);
+    vram.write(0x8010, 0xFF);
+    let mut renderer = MockRenderer::new();
+    let pixels = Arc::new(Mutex::new(vec![]));
+    let drawn_pixels = pixels.clone();
+    renderer.expect_draw_pixel().returning(move |x, _, _, color, _| drawn_pixels.lock().unwrap().push((x, color)));
+    controller.draw_window_line(&vram, &cram, &mut renderer);
+    let pixels = pixels.lock().unwrap().clone();
+    assert_eq!(pixels.iter().map(|(x, _)| *x).collect::<Vec<usize>>(), (wx.saturating_sub(7) as usize..160).collect::<Vec<usize>>());
+    pixels.into_iter()
+      .filter(|(_, color)| color.red != 0)
+      .map(|(x, _)| x)
+      .collect()
+  }
+
+  fn object_line_pixels(lcdc: u8, line: u8, oam: &OAMImpl, vram: &VRAMImpl, cram: &CRAMImpl) -> Vec<(usize, Color)> {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::LCDC, lcdc);
+    controller.line = line;
+    controller.intersecting_object_references = (0..40u8)
+      .filter_map(|object_index| oam.get_object_reference_if_intersects(object_index, line, controller.use_8_x_16_tiles()))
+      .collect();
+    let mut renderer = MockRenderer::new();
+    let pixels = Arc::new(Mutex::new(vec![]));
+    let drawn_pixels = pixels.clone();
+    renderer.expect_draw_pixel().returning(move |x, _, _, color, _| drawn_pixels.lock().unwrap().push((x, color)));
+    controller.draw_obj_line(vram, cram, oam, &mut renderer);
+    let pixels = pixels.lock().unwrap().clone();
+    pixels
+  }
+
+  fn write_object_palette(cram: &mut CRAMImpl, palette_index: u8, color_words: [u16; 4]) {
+    cram.write_raw_palette(true, palette_index, color_words);
+  }
+
+  fn write_object(oam: &mut OAMImpl, object_index: u8, bytes: [u8; 4]) {
+    oam.write_raw(4 * object_index as usize, &bytes);
+  }
+
+  #[test]
+  fn background_line_uses_seeded_tile_attributes_and_palette() {
+    let mut controller = LCDControllerImpl::new();
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    controller.write(MemoryAddress::LCDC, 0x91);
+    cram.write_raw_palette(false, 0, [0x7FFF, 0x0000, 0x0000, 0x0000]);
+    cram.write_raw_palette(false, 2, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    vram.write_raw(0, 0x9800, &[1]); // Tile 1 in the top left corner of the tile map
+    vram.write_raw(1, 0x9800, &[0x0A]); // Using palette 2 and VRAM bank 1
+    vram.write_raw(1, 0x8010, &[0xA0, 0x60]); // First row of tile 1 has color indices 1, 2, 3, 0, 0, 0, 0, 0
+    let mut renderer = MockRenderer::new();
+    let pixels = Arc::new(Mutex::new(vec![]));
+    let drawn_pixels = pixels.clone();
+    renderer.expect_draw_pixel().returning(move |x, _, _, color, _| drawn_pixels.lock().unwrap().push((x, color)));
+    controller.draw_background_line(&vram, &cram, &mut renderer);
+    let pixels = pixels.lock().unwrap().clone();
+    let expected_colors = [0x001F, 0x03E0, 0x7C00, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x7FFF];
+    assert_eq!(pixels.len(), 160);
+    expected_colors.into_iter().enumerate().for_each(|(x, color_word)| {
+      assert_eq!(pixels[x], (x, Color::from_rgb555(color_word)), "Pixel {}", x);
+    });
+  }
+
+  #[test]
+  fn x_flipped_object_uses_its_vram_bank_and_palette() {
+    let mut oam = OAMImpl::new();
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    write_object_palette(&mut cram, 3, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    vram.write(MemoryAddress::VBK, 1);
+    // First row of tile 1 has color indices 1, 2, 3, 0, 0, 0, 0, 0
+    vram.write(0x8010, 0xA0);
+    vram.write(0x8011, 0x60);
+    // Object at the top left corner using tile 1 from bank 1, palette 3, flipped horizontally
+    write_object(&mut oam, 0, [16, 8, 1, 0x2B]);
+    let pixels = object_line_pixels(0x83, 0, &oam, &vram, &cram);
+    let expected_pixels: Vec<(usize, Color)> = [0u16, 0, 0, 0, 0, 0x7C00, 0x03E0, 0x001F].into_iter()
+      .enumerate()
+      .map(|(x, color_word)| (x, if color_word == 0 { Color::transparent() } else { Color::from_rgb555(color_word) }))
+      .collect();
+    assert_eq!(pixels, expected_pixels);
+  }
+
+  #[test]
+  fn x_flipped_object_partially_off_screen_left_shows_its_flipped_right_end() {
+    let mut oam = OAMImpl::new();
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    write_object_palette(&mut cram, 0, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    // First row of tile 1 has color indices 1, 2, 3, 0, 0, 0, 0, 0
+    vram.write(0x8010, 0xA0);
+    vram.write(0x8011, 0x60);
+    // Only the right half of the object is on screen
+    write_object(&mut oam, 0, [16, 4, 1, 0x20]);
+    let pixels = object_line_pixels(0x83, 0, &oam, &vram, &cram);
+    let expected_pixels: Vec<(usize, Color)> = [0u16, 0x7C00, 0x03E0, 0x001F].into_iter()
+      .enumerate()
+      .map(|(x, color_word)| (x, if color_word == 0 { Color::transparent() } else { Color::from_rgb555(color_word) }))
+      .collect();
+    assert_eq!(pixels, expected_pixels);
+
+    // Without flipping, the same columns show the transparent right end of the tile
+    write_object(&mut oam, 0, [16, 4, 1, 0x00]);
+    let pixels = object_line_pixels(0x83, 0, &oam, &vram, &cram);
+    assert_eq!(pixels.iter().map(|(x, _)| *x).collect::<Vec<usize>>(), vec![0, 1, 2, 3]);
+    assert!(pixels.iter().all(|(_, color)| color.transparent));
+  }
+
+  #[test]
+  fn lower_oam_index_wins_overlapping_objects_in_cgb_priority_mode() {
+    let mut oam = OAMImpl::new();
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    write_object_palette(&mut cram, 0, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    write_object_palette(&mut cram, 1, [0x0000, 0x7FFF, 0x7FFF, 0x7FFF]);
+    // First row of tile 1 has color indices 1, 2, 3, 0, 0, 0, 0, 0, tile 2 is fully opaque
+    vram.write(0x8010, 0xA0);
+    vram.write(0x8011, 0x60);
+    vram.write(0x8020, 0xFF);
+    // Both objects are at the same position, object 1 is listed after object 0 in OAM
+    write_object(&mut oam, 1, [16, 8, 2, 0x01]);
+    write_object(&mut oam, 0, [16, 8, 1, 0x00]);
+    let pixels = object_line_pixels(0x83, 0, &oam, &vram, &cram);
+    let opaque_pixels: Vec<(usize, Color)> = pixels.into_iter()
+      .filter(|(_, color)| !color.transparent)
+      .collect();
+    let expected_pixels: Vec<(usize, Color)> = [0x001F, 0x03E0, 0x7C00, 0x7FFF, 0x7FFF, 0x7FFF, 0x7FFF, 0x7FFF].into_iter()
+      .enumerate()
+      .map(|(x, color_word)| (x, Color::from_rgb555(color_word)))
+      .collect();
+    assert_eq!(opaque_pixels, expected_pixels);
+  }
+
+  #[test]
+  fn y_flipped_8_x_16_object_swaps_tiles_and_rows() {
+    let mut oam = OAMImpl::new();
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    write_object_palette(&mut cram, 2, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    // First row of tile 2 starts with color index 1, last row of tile 3 starts with color index 3
+    vram.write(0x8020, 0x80);
+    vram.write(0x803E, 0x80);
+    vram.write(0x803F, 0x80);
+    // The lower bit of the tile index is ignored for 8x16 objects
+    write_object(&mut oam, 0, [16, 8, 3, 0x42]);
+    let top_row_pixels = object_line_pixels(0x87, 0, &oam, &vram, &cram);
+    assert_eq!(top_row_pixels[0], (0, Color::from_rgb555(0x7C00)));
+    assert!(top_row_pixels[1..].iter().all(|(_, color)| color.transparent));
+    let bottom_row_pixels = object_line_pixels(0x87, 15, &oam, &vram, &cram);
+    assert_eq!(bottom_row_pixels[0], (0, Color::from_rgb555(0x001F)));
+    assert!(bottom_row_pixels[1..].iter().all(|(_, color)| color.transparent));
+  }
+
+  #[test]
+  fn window_with_wx_7_starts_at_left_edge() {
+    assert_eq!(window_pixel_columns(7), (0..160).collect::<Vec<usize>>());
+  }
+
+  #[test]
+  fn window_with_wx_0_covers_entire_line() {
+    assert_eq!(window_pixel_columns(0), (0..160).collect::<Vec<usize>>());
+  }
+
+  #[test]
+  fn window_with_wx_0_discards_fine_scroll_pixels() {
+    assert_eq!(highlighted_window_columns(0, 0), (9..17).collect::<Vec<usize>>());
+    assert_eq!(highlighted_window_columns(0, 5), (4..12).collect::<Vec<usize>>());
+  }
+
+  #[test]
+  fn window_with_wx_3_ignores_fine_scroll() {
+    assert_eq!(highlighted_window_columns(3, 0), (12..20).collect::<Vec<usize>>());
+    assert_eq!(highlighted_window_columns(3, 5), (12..20).collect::<Vec<usize>>());
+  }
+
+  #[test]
+  fn window_with_wx_166_only_covers_last_pixel() {
+    assert_eq!(window_pixel_columns(166), vec![159]);
+  }
+
+  #[test]
+  fn window_with_wx_above_166_is_disabled() {
+    assert!(window_pixel_columns(167).is_empty());
+  }
+
+  #[test]
+  fn fine_scroll_and_window_extend_mode_3() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut oam = MockOAM::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    oam.expect_get_object_reference_if_intersects().return_const(None);
+    renderer.expect_render_target_is_enabled().return_const(false);
+    controller.write(MemoryAddress::LCDC, 0xB1);
+    controller.write(MemoryAddress::WX, 7);
+    controller.write(MemoryAddress::SCX, 3);
+    // Mode 3 is extended by 3 dots for the fine scroll and 6 dots for the window, so HBlank starts at dot 257
+    for _ in 0..64 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+      assert!(controller.get_mode() != LCDMode::HBlank);
+    }
+    controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    assert!(controller.get_mode() == LCDMode::HBlank);
+  }
+
+  #[test]
+  fn stat_reflects_live_mode_and_coincidence() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut oam = MockOAM::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    oam.expect_get_object_reference_if_intersects().return_const(None);
+    renderer.expect_render_target_is_enabled().return_const(false);
+    controller.write(MemoryAddress::STAT, 0x48);
+    assert_eq_hex!(controller.read(MemoryAddress::STAT), 0xCE); // Mode 2, LY = LYC
+    controller.write(MemoryAddress::LYC, 1);
+    assert_eq_hex!(controller.read(MemoryAddress::STAT), 0xCA);
+    interrupt_controller.expect_request_interrupt().return_const(());
+    for _ in 0..20 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    assert_eq_hex!(controller.read(MemoryAddress::STAT), 0xCB); // Mode 3 at dot 80
+    for _ in 20..62 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    assert_eq_hex!(controller.read(MemoryAddress::STAT), 0xC8); // HBlank at dot 248
+    for _ in 62..114 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    assert_eq_hex!(controller.read(MemoryAddress::STAT), 0xCE); // Mode 2 on line 1, LY = LYC
+    controller.write(MemoryAddress::LCDC, 0x11);
+    assert_eq_hex!(controller.read(MemoryAddress::STAT), 0xCC); // LCD off
+  }
+
+  #[test]
+  fn first_line_after_enabling_lcd_skips_oam_scan() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut oam = OAMImpl::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    renderer.expect_render_target_is_enabled().return_const(false);
+    renderer.expect_flush().return_const(());
+    interrupt_controller.expect_request_interrupt().return_const(());
+    oam.write_raw(0, &[16, 8, 0, 0]); // Object on line 0
+    controller.write(MemoryAddress::STAT, 0x20); // Mode 2 STAT source
+    controller.write(MemoryAddress::LCDC, 0x13);
+    controller.write(MemoryAddress::LCDC, 0x93);
+    assert_eq_hex!(controller.read(MemoryAddress::LY), 0);
+    let mut tick = |controller: &mut LCDControllerImpl, interrupt_controller: &mut MockInterruptController| {
+      controller.tick(&vram, &cram, &oam, &mut renderer, interrupt_controller, false);
+    };
+    // Line 0 starts out in HBlank instead of Mode 2, so the Mode 2 STAT source doesn't fire
+    interrupt_controller.checkpoint();
+    interrupt_controller.expect_request_interrupt().never();
+    for _ in 0..19 {
+      tick(&mut controller, &mut interrupt_controller);
+      assert_eq_hex!(controller.read(MemoryAddress::STAT) & 0x03, 0x00);
+    }
+    tick(&mut controller, &mut interrupt_controller);
+    assert!(controller.get_mode() == LCDMode::Mode3);
+    assert!(controller.current_line_objects().is_empty());
+
+    // Line 0 of the next frame is scanned as usual
+    interrupt_controller.checkpoint();
+    interrupt_controller.expect_request_interrupt().return_const(());
+    while controller.read(MemoryAddress::LY) != 0 || controller.get_mode() != LCDMode::Mode2 {
+      tick(&mut controller, &mut interrupt_controller);
+    }
+    while controller.get_mode() == LCDMode::Mode2 {
+      tick(&mut controller, &mut interrupt_controller);
+    }
+    assert_eq!(controller.current_line_objects().len(), 1);
+  }
+
+  #[test]
+  fn forced_mode_is_reflected_in_ly_and_stat_and_ticks_on_from_there() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut oam = MockOAM::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    oam.expect_get_object_reference_if_intersects().return_const(None);
+    controller.write(MemoryAddress::STAT, 0x08); // HBlank STAT source
+    controller.force_mode(LCDMode::HBlank, 10);
+    assert_eq_hex!(controller.read(MemoryAddress::LY), 10);
+    assert_eq_hex!(controller.read(MemoryAddress::STAT), 0x88);
+    interrupt_controller.expect_request_interrupt().never();
+    for _ in 0..52 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    assert_eq_hex!(controller.read(MemoryAddress::LY), 11);
+    assert!(controller.get_mode() == LCDMode::Mode2);
+  }
+
+  #[test]
+  fn entering_mode_3_never_requests_stat_interrupt() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut oam = MockOAM::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    oam.expect_get_object_reference_if_intersects().return_const(None);
+    renderer.expect_render_target_is_enabled().return_const(false);
+    controller.write(MemoryAddress::STAT, 0x78);
+    controller.write(MemoryAddress::LYC, 1); // Line 1 enters Mode 3 with the LY=LYC source high
+    let mut mode_3_entries = 0;
+    for _ in 0..3 * 114 {
+      let previous_mode = controller.get_mode();
+      interrupt_controller.clear_interrupt(Interrupt::Stat);
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+      if previous_mode == LCDMode::Mode2 && controller.get_mode() == LCDMode::Mode3 {
+        mode_3_entries += 1;
+        assert!(!interrupt_controller.read(MemoryAddress::IF).get_bit(Interrupt::Stat.get_bit()));
+      }
+    }
+    assert_eq!(mode_3_entries, 3);
+  }
+
+  #[test]
+  fn headless_lcd_keeps_timing_without_rendering() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = MockVRAM::new();
+    let cram = MockCRAM::new();
+    let mut oam = MockOAM::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    oam.expect_get_object_reference_if_intersects().return_const(None);
+    controller.set_headless(true);
+    controller.write(MemoryAddress::STAT, 0x08); // HBlank STAT source
+    interrupt_controller.write(MemoryAddress::IF, 0x00);
+    for _ in 0..144 * 114 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    assert_eq!(controller.read(MemoryAddress::LY), 144);
+    assert_eq!(interrupt_controller.read(MemoryAddress::IF) & 0x03, 0x03);
+  }
+
+  #[test]
+  fn lyc_match_written_during_mode_3_requests_stat_interrupt() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut oam = MockOAM::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = InterruptControllerImpl::new();
+    oam.expect_get_object_reference_if_intersects().return_const(None);
+    renderer.expect_render_target_is_enabled().return_const(false);
+    controller.write(MemoryAddress::STAT, 0x78);
+    controller.write(MemoryAddress::LYC, 1);
+    while controller.get_mode() != LCDMode::Mode3 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    interrupt_controller.clear_interrupt(Interrupt::Stat);
+    controller.write(MemoryAddress::LYC, 0);
+    controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    assert!(controller.get_mode() == LCDMode::Mode3);
+    assert!(interrupt_controller.read(MemoryAddress::IF).get_bit(Interrupt::Stat.get_bit()));
+  }
+
+  #[test]
+  fn next_stat_event_is_start_of_vblank_if_no_stat_sources_are_enabled() {
+    let controller = LCDControllerImpl::new();
+    assert_eq!(controller.dots_until_next_stat_event(), Some(144 * 456));
+  }
+
+  #[test]
+  fn next_stat_event_is_start_of_hblank() {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::STAT, 0x08);
+    assert_eq!(controller.dots_until_next_stat_event(), Some(248));
+    controller.dot = 248;
+    assert_eq!(controller.dots_until_next_stat_event(), Some(456));
+  }
+
+  #[test]
+  fn next_stat_event_is_lyc_coincidence() {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::STAT, 0x40);
+    controller.write(MemoryAddress::LYC, 2);
+    controller.dot = 100;
+    assert_eq!(controller.dots_until_next_stat_event(), Some(2 * 456 - 100));
+    controller.dot = 3 * 456;
+    assert_eq!(controller.dots_until_next_stat_event(), Some(141 * 456));
+  }
+
+  #[test]
+  fn next_stat_event_wraps_around_to_next_frame() {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::STAT, 0x20);
+    controller.dot = 150 * 456;
+    assert_eq!(controller.dots_until_next_stat_event(), Some(4 * 456));
+  }
+
+  #[test]
+  fn no_stat_event_while_lcd_is_off() {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::STAT, 0x78);
+    controller.write(MemoryAddress::LCDC, 0x11);
+    assert_eq!(controller.dots_until_next_stat_event(), None);
+  }
+
+  fn object_atlas_pixels(lcdc: u8, oam: &OAMImpl, vram: &VRAMImpl, cram: &CRAMImpl) -> Vec<(usize, usize, Color)> {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::LCDC, lcdc);
+    let mut renderer = MockRenderer::new();
+    let pixels = Arc::new(Mutex::new(vec![]));
+    let drawn_pixels = pixels.clone();
+    renderer.expect_draw_pixel()
+      .with(always(), always(), always(), always(), eq(RenderTarget::ObjectAtlas))
+      .returning(move |x, y, _, color, _| drawn_pixels.lock().unwrap().push((x, y, color)));
+    for line in 0..144u8 {
+      controller.line = line;
+      controller.draw_obj_atlas_line(vram, cram, oam, &mut renderer);
+    }
+    let pixels = pixels.lock().unwrap().clone();
+    pixels
+  }
+
+  #[test]
+  fn object_atlas_draws_objects_in_their_grid_cells() {
+    let mut oam = OAMImpl::new();
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    write_object_palette(&mut cram, 0, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    (0..8u16).for_each(|row| vram.write(0x8010 + 2 * row, 0xFF)); // Tile 1 only has color index 1
+    write_object(&mut oam, 0, [16, 8, 1, 0x00]);
+    write_object(&mut oam, 23, [100, 50, 1, 0x00]);
+    let pixels = object_atlas_pixels(0x83, &oam, &vram, &cram);
+    assert_eq!(pixels.len(), 16 * 160);
+    let object_color = Color::from_rgb555(0x001F);
+    pixels.into_iter().for_each(|(x, y, color)| {
+      let in_object_0 = x < 8 && y < 8;
+      let in_object_23 = (24..32).contains(&x) && (8..16).contains(&y);
+      if in_object_0 || in_object_23 {
+        assert_eq!(color, object_color, "Expected object pixel at ({}, {})", x, y);
+      } else {
+        assert!(color.transparent, "Expected empty cell at ({}, {})", x, y);
+      }
+    });
+  }
+
+  #[test]
+  fn object_atlas_draws_8_x_16_objects_over_two_tiles() {
+    let mut oam = OAMImpl::new();
+    let mut vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    write_object_palette(&mut cram, 0, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    (0..8u16).for_each(|row| vram.write(0x8020 + 2 * row, 0xFF)); // Tile 2 only has color index 1
+    (0..8u16).for_each(|row| vram.write(0x8031 + 2 * row, 0xFF)); // Tile 3 only has color index 2
+    write_object(&mut oam, 20, [16, 8, 2, 0x00]);
+    let pixels = object_atlas_pixels(0x87, &oam, &vram, &cram);
+    assert_eq!(pixels.len(), 32 * 160);
+    assert!(pixels.iter().filter(|(x, y, _)| *x < 8 && (16..24).contains(y)).all(|(_, _, color)| *color == Color::from_rgb555(0x001F)));
+    assert!(pixels.iter().filter(|(x, y, _)| *x < 8 && (24..32).contains(y)).all(|(_, _, color)| *color == Color::from_rgb555(0x03E0)));
+    assert!(pixels.iter().filter(|(x, y, _)| *x >= 8 || *y < 16).all(|(_, _, color)| color.transparent));
+  }
+
+  #[test]
+  fn current_line_objects_are_reported_during_mode_3() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let cram = CRAMImpl::new();
+    let mut oam = OAMImpl::new();
+    let mut renderer = MockRenderer::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    renderer.expect_render_target_is_enabled().return_const(false);
+    interrupt_controller.expect_request_interrupt().return_const(());
+    write_object(&mut oam, 3, [20, 8, 0, 0]); // Covers lines 4-11
+    write_object(&mut oam, 7, [26, 16, 0, 0]); // Covers lines 10-17
+    write_object(&mut oam, 12, [24, 24, 0, 0]); // Covers lines 8-15
+    write_object(&mut oam, 20, [40, 32, 0, 0]); // Covers lines 24-31
+    controller.write(MemoryAddress::LCDC, 0x83);
+    while controller.line < 10 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    assert!(controller.get_mode() == LCDMode::Mode2);
+    assert!(controller.current_line_objects().is_empty());
+    while controller.get_mode() != LCDMode::Mode3 {
+      controller.tick(&vram, &cram, &oam, &mut renderer, &mut interrupt_controller, false);
+    }
+    let object_indices: Vec<u8> = controller.current_line_objects().iter()
+      .map(|object_reference| object_reference.object_index)
+      .collect();
+    assert_eq!(object_indices, vec![3, 7, 12]);
+  }
+
+  #[test]
+  fn opri_is_locked_after_boot_rom_hand_off() {
+    let mut controller = LCDControllerImpl::new();
+    controller.write(MemoryAddress::OPRI, 0x01);
+    assert_eq_hex!(controller.read(MemoryAddress::OPRI), 0xFF);
+    controller.write(MemoryAddress::BANK, 0x00);
+    controller.write(MemoryAddress::OPRI, 0x00);
+    assert_eq_hex!(controller.read(MemoryAddress::OPRI), 0xFE);
+    controller.write(MemoryAddress::OPRI, 0x01);
+    controller.write(MemoryAddress::BANK, 0x11);
+    controller.write(MemoryAddress::OPRI, 0x00);
+    assert_eq_hex!(controller.read(MemoryAddress::OPRI), 0xFF);
+  }
+
+  #[test]
+  fn key0_selects_monochrome_palettes_until_boot_rom_hand_off() {
+    let mut controller = LCDControllerImpl::new();
+    let vram = VRAMImpl::new();
+    let mut cram = CRAMImpl::new();
+    cram.write_raw_palette(false, 0, [0x0000, 0x001F, 0x03E0, 0x7C00]);
+    cram.write(MemoryAddress::BGP, 0xE7); // Color index 0 maps to color 3 of the monochrome palette
+    controller.write(MemoryAddress::LCDC, 0x91);
+    let first_pixel_color = |controller: &LCDControllerImpl| {
+      let mut renderer = MockRenderer::new();
+      let pixels = Arc::new(Mutex::new(vec![]));
+      let drawn_pixels = pixels.clone();
+      renderer.expect_draw_pixel().returning(move |_, _, _, color, _| drawn_pixels.lock().unwrap().push(color));
+      controller.draw_background_line(&vram, &cram, &mut renderer);
+      let color = pixels.lock().unwrap()[0];
+      color
+    };
+    assert_eq!(first_pixel_color(&controller), Color::from_rgb555(0x0000));
+    controller.write(MemoryAddress::KEY0, 0x04);
+    assert_eq!(first_pixel_color(&controller), Color::from_rgb555(0x7C00));
+    controller.write(MemoryAddress::BANK, 0x11);
+    controller.write(MemoryAddress::KEY0, 0x80);
+    assert_eq!(first_pixel_color(&controller), Color::from_rgb555(0x7C00));
+  }
+
+  #[test]
+  fn opri_unused_bits_read_as_ones() {
+    let mut controller = LCDControllerImpl::new();
+    assert_eq_hex!(controller.read(MemoryAddress::OPRI), 0xFE);
+    controller.write(MemoryAddress::OPRI, 0x02); // Only bit 0 selects the priority mode
+    assert_eq_hex!(controller.read(MemoryAddress::OPRI), 0xFE);
+    assert_eq!(controller.opri, 0);
+    controller.write(MemoryAddress::OPRI, 0xFF);
+    assert_eq_hex!(controller.read(MemoryAddress::OPRI), 0xFF);
+    assert_eq!(controller.opri, 1);
+  }
+
   #[test]
   fn stat_blocking() {
     let mut controller = LCDControllerImpl::new();
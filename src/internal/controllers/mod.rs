@@ -4,3 +4,4 @@ pub mod dma;
 pub mod audio;
 pub mod lcd;
 pub mod speed;
+pub mod serial;
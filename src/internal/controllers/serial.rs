@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::internal::cpu::interrupts::{Interrupt, InterruptController};
+use crate::internal::memory::memory::{Memory, MemoryAddress};
+use crate::internal::util::bit_util::BitUtil;
+use crate::serial::SerialPeer;
+
+pub trait SerialController {
+  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerialControllerImpl {
+  data: u8,
+  control: u8,
+  bits_remaining: u8,
+  cycles_until_next_bit: u16,
+  // Set when a peer clocked in the last bit of a transfer, until the interrupt for it has been requested
+  completed_by_peer: bool,
+  #[serde(skip)]
+  sent_bytes: VecDeque<u8>,
+  #[serde(skip)]
+  peer: Option<Box<dyn SerialPeer>>,
+}
+
+impl SerialControllerImpl {
+  // The internal clock runs at 8192 Hz, or at 262144 Hz when the CGB high speed clock is selected.
+  // Both scale with the CPU in double speed mode, so they're expressed in machine cycles.
+  const MACHINE_CYCLES_PER_BIT: u16 = 128;
+  const HIGH_SPEED_MACHINE_CYCLES_PER_BIT: u16 = 4;
+  // Games that keep polling the link port send bytes forever, so only the most recent ones are kept around until
+  // they're taken
+  const MAX_SENT_BYTES: usize = 4096;
+
+  pub fn new() -> SerialControllerImpl {
+    SerialControllerImpl {
+      data: 0,
+      control: 0,
+      bits_remaining: 0,
+      cycles_until_next_bit: 0,
+      completed_by_peer: false,
+      sent_bytes: VecDeque::new(),
+      peer: None,
+    }
+  }
+
+  pub fn connect_peer(&mut self, peer: Box<dyn SerialPeer>) {
+    self.peer = Some(peer);
+  }
+
+  pub fn disconnect_peer(&mut self) {
+    self.peer = None;
+  }
+
+  // Requests the interrupt for a transfer a peer has completed. Owners that can reach an interrupt controller call
+  // this right after exchanging a bit, so the interrupt isn't held back until the next tick, e.g. while STOPped.
+  pub fn request_peer_completion_interrupt(&mut self, interrupt_controller: &mut dyn InterruptController) {
+    if self.completed_by_peer {
+      self.completed_by_peer = false;
+      interrupt_controller.request_interrupt(Interrupt::SerialIOComplete);
+    }
+  }
+
+  pub fn take_peer(&mut self) -> Option<Box<dyn SerialPeer>> {
+    self.peer.take()
+  }
+
+  // Returns the bytes that were sent since the last call, in the order they were sent. Only the last 4096 bytes are
+  // returned if more were sent.
+  pub fn take_sent_bytes(&mut self) -> Vec<u8> {
+    self.sent_bytes.drain(..).collect()
+  }
+
+  fn transfer_requested(&self) -> bool {
+    self.control.get_bit(7)
+  }
+
+  fn uses_internal_clock(&self) -> bool {
+    self.control.get_bit(0)
+  }
+
+  fn machine_cycles_per_bit(&self) -> u16 {
+    if self.control.get_bit(1) {
+      SerialControllerImpl::HIGH_SPEED_MACHINE_CYCLES_PER_BIT
+    } else {
+      SerialControllerImpl::MACHINE_CYCLES_PER_BIT
+    }
+  }
+}
+
+impl SerialController for SerialControllerImpl {
+  // Without a connected peer, every bit that's shifted in is a 1. Transfers on the external clock only complete when
+  // a peer clocks them.
+  fn tick(&mut self, interrupt_controller: &mut dyn InterruptController) {
+    self.request_peer_completion_interrupt(interrupt_controller);
+    if !self.transfer_requested() || !self.uses_internal_clock() {
+      return;
+    }
+    self.cycles_until_next_bit -= 1;
+    if self.cycles_until_next_bit == 0 {
+      let outgoing_bit = self.data.get_bit(7);
+      let incoming_bit = self.peer.as_mut().map_or(true, |peer| peer.exchange_bit(outgoing_bit));
+      self.data = (self.data << 1) | incoming_bit as u8;
+      self.bits_remaining -= 1;
+      if self.bits_remaining == 0 {
+        self.control = self.control.reset_bit(7);
+        interrupt_controller.request_interrupt(Interrupt::SerialIOComplete);
+      } else {
+        self.cycles_until_next_bit = self.machine_cycles_per_bit();
+      }
+    }
+  }
+}
+
+impl SerialPeer for SerialControllerImpl {
+  // Only shifts while a transfer on the external clock is pending, otherwise the line stays high
+  fn exchange_bit(&mut self, bit: bool) -> bool {
+    if !self.transfer_requested() || self.uses_internal_clock() {
+      return true;
+    }
+    let outgoing_bit = self.data.get_bit(7);
+    self.data = (self.data << 1) | bit as u8;
+    self.bits_remaining -= 1;
+    if self.bits_remaining == 0 {
+      self.control = self.control.reset_bit(7);
+      self.completed_by_peer = true;
+    }
+    outgoing_bit
+  }
+}
+
+impl Memory for SerialControllerImpl {
+  fn read(&self, address: u16) -> u8 {
+    match address {
+      MemoryAddress::SB => self.data,
+      MemoryAddress::SC => 0x7C | self.control,
+      _ => panic!("Can't read address {} on serial controller", address)
+    }
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address {
+      MemoryAddress::SB => self.data = value,
+      MemoryAddress::SC => {
+        let transfer_started = value.get_bit(7) && !self.transfer_requested();
+        self.control = value & 0x83;
+        if transfer_started {
+          self.bits_remaining = 8;
+          self.cycles_until_next_bit = self.machine_cycles_per_bit();
+          if self.sent_bytes.len() == SerialControllerImpl::MAX_SENT_BYTES {
+            self.sent_bytes.pop_front();
+          }
+          self.sent_bytes.push_back(self.data);
+        }
+      }
+      _ => panic!("Can't write to address {} on serial controller", address)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::internal::cpu::interrupts::MockInterruptController;
+  use mockall::predicate::eq;
+
+  use super::*;
+
+  #[test]
+  fn internal_clock_transfer_completes_after_8_bits() {
+    let mut serial = SerialControllerImpl::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    serial.write(MemoryAddress::SB, 0x42);
+    serial.write(MemoryAddress::SC, 0x81);
+    assert_eq!(serial.take_sent_bytes(), vec![0x42]);
+    interrupt_controller.expect_request_interrupt().never();
+    for _ in 0..(8 * 128 - 1) {
+      serial.tick(&mut interrupt_controller);
+    }
+    assert_eq!(serial.read(MemoryAddress::SC), 0xFD);
+    interrupt_controller.checkpoint();
+    interrupt_controller.expect_request_interrupt().with(eq(Interrupt::SerialIOComplete)).once().return_const(());
+    serial.tick(&mut interrupt_controller);
+    assert_eq!(serial.read(MemoryAddress::SC), 0x7D);
+    assert_eq!(serial.read(MemoryAddress::SB), 0xFF);
+  }
+
+  #[test]
+  fn only_the_most_recent_sent_bytes_are_kept() {
+    let mut serial = SerialControllerImpl::new();
+    (0..5000u32).for_each(|index| {
+      serial.write(MemoryAddress::SB, index as u8);
+      serial.write(MemoryAddress::SC, 0x80);
+      serial.write(MemoryAddress::SC, 0x00);
+    });
+    let sent_bytes = serial.take_sent_bytes();
+    assert_eq!(sent_bytes.len(), 4096);
+    assert_eq!(sent_bytes[0], (5000u32 - 4096) as u8);
+    assert_eq!(sent_bytes[4095], 4999u32 as u8);
+    assert!(serial.take_sent_bytes().is_empty());
+  }
+
+  #[test]
+  fn external_clock_transfer_waits_for_peer() {
+    let mut serial = SerialControllerImpl::new();
+    let mut interrupt_controller = MockInterruptController::new();
+    interrupt_controller.expect_request_interrupt().never();
+    serial.write(MemoryAddress::SB, 0x42);
+    serial.write(MemoryAddress::SC, 0x80);
+    for _ in 0..10000 {
+      serial.tick(&mut interrupt_controller);
+    }
+    assert_eq!(serial.read(MemoryAddress::SC), 0xFC);
+    assert_eq!(serial.read(MemoryAddress::SB), 0x42);
+  }
+
+  fn transfer(master: &Rc<RefCell<SerialControllerImpl>>, slave: &Rc<RefCell<SerialControllerImpl>>) {
+    let mut interrupt_controller = MockInterruptController::new();
+    interrupt_controller.expect_request_interrupt().with(eq(Interrupt::SerialIOComplete)).times(2).return_const(());
+    RefCell::borrow_mut(slave).write(MemoryAddress::SC, 0x80);
+    RefCell::borrow_mut(master).write(MemoryAddress::SC, 0x81);
+    for _ in 0..(8 * 128) {
+      RefCell::borrow_mut(master).tick(&mut interrupt_controller);
+    }
+    RefCell::borrow_mut(slave).tick(&mut interrupt_controller);
+    assert_eq!(master.borrow().read(MemoryAddress::SC), 0x7D);
+    assert_eq!(slave.borrow().read(MemoryAddress::SC), 0x7C);
+  }
+
+  #[test]
+  fn linked_controllers_exchange_bytes_in_both_directions() {
+    let first = Rc::new(RefCell::new(SerialControllerImpl::new()));
+    let second = Rc::new(RefCell::new(SerialControllerImpl::new()));
+    RefCell::borrow_mut(&first).connect_peer(Box::new(Rc::downgrade(&second)));
+    RefCell::borrow_mut(&second).connect_peer(Box::new(Rc::downgrade(&first)));
+
+    RefCell::borrow_mut(&first).write(MemoryAddress::SB, 0x42);
+    RefCell::borrow_mut(&second).write(MemoryAddress::SB, 0x99);
+    transfer(&first, &second);
+    assert_eq!(first.borrow().read(MemoryAddress::SB), 0x99);
+    assert_eq!(second.borrow().read(MemoryAddress::SB), 0x42);
+
+    RefCell::borrow_mut(&first).write(MemoryAddress::SB, 0x12);
+    RefCell::borrow_mut(&second).write(MemoryAddress::SB, 0x34);
+    transfer(&second, &first);
+    assert_eq!(first.borrow().read(MemoryAddress::SB), 0x34);
+    assert_eq!(second.borrow().read(MemoryAddress::SB), 0x12);
+  }
+
+  #[test]
+  fn dropped_peer_acts_as_disconnected() {
+    let mut serial = SerialControllerImpl::new();
+    let peer = Rc::new(RefCell::new(SerialControllerImpl::new()));
+    serial.connect_peer(Box::new(Rc::downgrade(&peer)));
+    drop(peer);
+    let mut interrupt_controller = MockInterruptController::new();
+    interrupt_controller.expect_request_interrupt().with(eq(Interrupt::SerialIOComplete)).once().return_const(());
+    serial.write(MemoryAddress::SB, 0x42);
+    serial.write(MemoryAddress::SC, 0x81);
+    for _ in 0..(8 * 128) {
+      serial.tick(&mut interrupt_controller);
+    }
+    assert_eq!(serial.read(MemoryAddress::SB), 0xFF);
+  }
+}
@@ -5,46 +5,66 @@ use crate::internal::cpu::cpu::CPU;
 use crate::internal::memory::memory::{Memory, MemoryAddress};
 use crate::internal::util::bit_util::BitUtil;
 
+// After STOP is executed with a speed switch armed, the CPU stays stopped for this many machine cycles
+// while the rest of the system keeps running
+const SPEED_SWITCH_MACHINE_CYCLES: u16 = 2050;
+
 #[automock]
 pub trait SpeedController {
   fn double_speed(&self) -> bool;
+  fn switching_speed(&self) -> bool;
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct SpeedControllerImpl(u8);
+pub struct SpeedControllerImpl {
+  key1: u8,
+  switch_cycles_remaining: u16,
+}
 
 impl SpeedControllerImpl {
   pub fn new() -> Self {
-    SpeedControllerImpl(0x00)
+    SpeedControllerImpl {
+      key1: 0x00,
+      switch_cycles_remaining: 0,
+    }
   }
 
   pub fn tick(&mut self, cpu: &mut dyn CPU) {
-    if cpu.stopped() & self.0.get_bit(0) {
-      self.0 = self.0.toggle_bit(7);
-      self.0 = self.0.reset_bit(0);
-      cpu.resume();
+    if self.switch_cycles_remaining > 0 {
+      self.switch_cycles_remaining -= 1;
+      if self.switch_cycles_remaining == 0 {
+        self.key1 = self.key1.toggle_bit(7);
+        cpu.resume();
+      }
+    } else if cpu.stopped() & self.key1.get_bit(0) {
+      self.key1 = self.key1.reset_bit(0);
+      self.switch_cycles_remaining = SPEED_SWITCH_MACHINE_CYCLES;
     }
   }
 }
 
 impl SpeedController for SpeedControllerImpl {
   fn double_speed(&self) -> bool {
-    self.0.get_bit(7)
+    self.key1.get_bit(7)
+  }
+
+  fn switching_speed(&self) -> bool {
+    self.switch_cycles_remaining > 0
   }
 }
 
 impl Memory for SpeedControllerImpl {
   fn read(&self, address: u16) -> u8 {
     match address {
-      MemoryAddress::KEY1 => self.0,
+      MemoryAddress::KEY1 => self.key1,
       _ => panic!("SpeedController can't read value at address {}", address)
     }
   }
 
   fn write(&mut self, address: u16, value: u8) {
     match address {
-      MemoryAddress::KEY1 => self.0 = if value.get_bit(0) { self.0.set_bit(0) } else { self.0.reset_bit(0) },
+      MemoryAddress::KEY1 => self.key1 = if value.get_bit(0) { self.key1.set_bit(0) } else { self.key1.reset_bit(0) },
       _ => panic!("SpeedController can't write to address {}", address)
     }
   }
-}
\ No newline at end of file
+}
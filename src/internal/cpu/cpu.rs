@@ -4,8 +4,8 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use mockall::automock;
 use serde::{Deserialize, Serialize};
 
-use crate::cpu::CPUInfo;
-use crate::internal::cpu::decoder::{InstructionDecoder, InstructionScheduler};
+use crate::cpu::{CPUInfo, GbModel};
+use crate::internal::cpu::decoder::{InstructionDecoder, InstructionScheduler, MAX_SCHEDULED_INSTRUCTIONS};
 use crate::internal::cpu::instruction::{ByteArithmeticParams, ByteCastingParams, ByteLocation, ByteLogicParams, ByteOperationParams, ByteRotationParams, ByteShiftParams, Instruction, WordArithmeticParams, WordLocation, WordOperationParams};
 use crate::internal::cpu::interrupts::Interrupt;
 use crate::internal::cpu::opcode::Opcode;
@@ -31,14 +31,28 @@ struct InstructionContext {
     address_buffer: u16,
 }
 
+// A memory access made by the CPU, either as part of the instruction stream or to read data
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemoryAccess {
+    Fetch(u16),
+    Read(u16),
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CPUImpl {
     enabled: bool,
     halted: bool,
     stopped: bool,
+    // EI only sets IME after the instruction following it has been executed
+    interrupt_enable_pending: bool,
+    // Mirrors IME in the interrupt controller, which the CPU only ever changes through EI, DI, RETI and interrupt dispatch
+    interrupt_master_enable: bool,
     context: InstructionContext,
     instructions: VecDeque<Instruction>,
     registers: Registers,
+    instructions_executed: u64,
+    #[serde(skip)]
+    memory_accesses: Option<Vec<MemoryAccess>>,
 }
 
 impl CPU for CPUImpl {
@@ -50,13 +64,24 @@ impl CPU for CPUImpl {
                 InstructionDecoder::schedule_call_interrupt_routine(self, Interrupt::ButtonPressed);
             }
         } else if self.halted {
-            if (memory.read(MemoryAddress::IF) & 0x1F) != 0x00 {
+            if (memory.read(MemoryAddress::IE) & memory.read(MemoryAddress::IF) & 0x1F) != 0x00 {
                 self.unhalt();
+                // If IME is set, the interrupt is serviced right away. Waking up takes an extra machine cycle,
+                // so we only schedule the interrupt routine here.
+                if let Some(interrupt) = Interrupt::from_bit(memory.read(MemoryAddress::RI)) {
+                    InstructionDecoder::schedule_call_interrupt_routine(self, interrupt);
+                }
             }
         } else if !self.instructions.is_empty() {
             self.execute_machine_cycle(memory);
         } else if self.enabled {
             let optional_interrupt = Interrupt::from_bit(memory.read(MemoryAddress::RI));
+            // Interrupts are only checked at instruction boundaries, so setting IME while the instruction after EI
+            // executes is indistinguishable from setting it once it's done.
+            if self.interrupt_enable_pending {
+                self.interrupt_enable_pending = false;
+                self.set_interrupt_master_enable(memory, true);
+            }
             if let Some(interrupt) = optional_interrupt {
                 if let Interrupt::ButtonPressed = interrupt {
                     self.resume();
@@ -99,12 +124,16 @@ impl CPU for CPUImpl {
             pc: self.registers.read_word(WordRegister::PC),
             stopped: self.stopped,
             enabled: self.enabled,
+            ime: self.interrupt_master_enable,
+            ime_pending: self.interrupt_enable_pending,
+            instructions_executed: self.instructions_executed,
         }
     }
 }
 
 impl InstructionScheduler for CPUImpl {
     fn schedule(&mut self, instruction: Instruction) {
+        debug_assert!(self.instructions.len() < MAX_SCHEDULED_INSTRUCTIONS, "Instruction queue exceeds {} micro-ops", MAX_SCHEDULED_INSTRUCTIONS);
         self.instructions.push_back(instruction);
     }
 }
@@ -115,17 +144,64 @@ impl CPUImpl {
             enabled: true,
             halted: false,
             stopped: false,
+            interrupt_enable_pending: false,
+            interrupt_master_enable: false,
             context: InstructionContext {
                 byte_buffer: 0u8,
                 word_buffer: 0u16,
                 address_buffer: 0u16,
             },
-            instructions: VecDeque::with_capacity(20),
+            instructions: VecDeque::with_capacity(MAX_SCHEDULED_INSTRUCTIONS),
             registers: Registers::new(),
+            instructions_executed: 0,
+            memory_accesses: None,
+        }
+    }
+
+    // Whether the last machine cycle completed an instruction, or the interrupt routine dispatch
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    // IME as last set by EI, DI, RETI or interrupt dispatch. While EI is pending, this is still false.
+    pub fn ime_enabled(&self) -> bool {
+        self.interrupt_master_enable
+    }
+
+    fn set_interrupt_master_enable(&mut self, memory: &mut dyn Memory, enabled: bool) {
+        self.interrupt_master_enable = enabled;
+        memory.write(MemoryAddress::IME, if enabled { 0x01 } else { 0x00 });
+    }
+
+    pub fn set_memory_access_tracking_enabled(&mut self, enabled: bool) {
+        self.memory_accesses = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn take_memory_accesses(&mut self) -> Vec<MemoryAccess> {
+        self.memory_accesses.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn track_memory_access(&mut self, access: MemoryAccess) {
+        if let Some(memory_accesses) = self.memory_accesses.as_mut() {
+            memory_accesses.push(access);
         }
     }
 
-    pub fn init(&mut self) {
+    // Loads the register values the boot ROM of the given model leaves behind when it hands control to the cartridge.
+    // Header dependent values are those of a cartridge with a non-zero header checksum that isn't licensed by Nintendo.
+    pub fn init_for_model(&mut self, model: GbModel) {
+        let (af, bc, de, hl) = match model {
+            GbModel::Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            GbModel::MgbPocket => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            GbModel::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            GbModel::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+            GbModel::CgbInDmgCompat => (0x1180, 0x0000, 0x0008, 0x007C),
+        };
+        self.registers.write_word(WordRegister::AF, af);
+        self.registers.write_word(WordRegister::BC, bc);
+        self.registers.write_word(WordRegister::DE, de);
+        self.registers.write_word(WordRegister::HL, hl);
+        self.registers.write_word(WordRegister::SP, 0xFFFE);
         self.registers.write_word(WordRegister::PC, 0x0100);
     }
 
@@ -189,15 +265,20 @@ impl CPUImpl {
                 let interrupt_request = memory.read(MemoryAddress::IF);
                 memory.write(MemoryAddress::IF, interrupt_request.reset_bit(interrupt.get_bit()));
             }
-            Instruction::EnableInterrupts => { memory.write(MemoryAddress::IME, 0x01); }
-            Instruction::DisableInterrupts => { memory.write(MemoryAddress::IME, 0x00); }
+            Instruction::EnableInterrupts => { self.interrupt_enable_pending = true; }
+            Instruction::EnableInterruptsImmediately => self.set_interrupt_master_enable(memory, true),
+            Instruction::DisableInterrupts => self.set_interrupt_master_enable(memory, false),
             Instruction::FlipCarry => { self.flip_carry_flag(); }
             Instruction::SetCarry => { self.set_carry_flag(); }
             Instruction::Halt => { self.halt(); }
-            Instruction::Stop => { self.stop(); }
+            Instruction::Stop => {
+                self.stop();
+                memory.write(MemoryAddress::DIV, 0x00);
+            }
             Instruction::DecodeCBInstruction => {
                 let opcode = Opcode(self.read_next_byte(memory));
                 InstructionDecoder::decode_cb(self, opcode);
+                debug_assert_eq!(InstructionDecoder::validate_schedule(&self.instructions), Ok(()), "Invalid schedule for CB opcode {:#04x}", opcode.value());
             }
         }
     }
@@ -213,12 +294,15 @@ impl CPUImpl {
 
     fn decode_instruction(&mut self, memory: &mut dyn Memory) {
         let opcode = Opcode(self.read_next_byte(memory));
+        self.instructions_executed += 1;
         InstructionDecoder::decode(self, opcode);
+        debug_assert_eq!(InstructionDecoder::validate_schedule(&self.instructions), Ok(()), "Invalid schedule for opcode {:#04x}", opcode.value());
     }
 
     fn read_next_byte(&mut self, memory: &dyn Memory) -> u8 {
         let address = self.registers.read_word(WordRegister::PC);
-        self.registers.write_word(WordRegister::PC, address + 1);
+        self.registers.write_word(WordRegister::PC, address.wrapping_add(1));
+        self.track_memory_access(MemoryAccess::Fetch(address));
         memory.read(address)
     }
 
@@ -231,8 +315,15 @@ impl CPUImpl {
             ByteLocation::UpperAddressBuffer => (self.context.address_buffer >> 8) as u8,
             ByteLocation::LowerWordBuffer => self.context.word_buffer as u8,
             ByteLocation::UpperWordBuffer => (self.context.word_buffer >> 8) as u8,
-            ByteLocation::MemoryReferencedByAddressBuffer => memory.read(self.context.address_buffer),
-            ByteLocation::MemoryReferencedByRegister(register) => memory.read(self.registers.read_word(register)),
+            ByteLocation::MemoryReferencedByAddressBuffer => {
+                self.track_memory_access(MemoryAccess::Read(self.context.address_buffer));
+                memory.read(self.context.address_buffer)
+            }
+            ByteLocation::MemoryReferencedByRegister(register) => {
+                let address = self.registers.read_word(register);
+                self.track_memory_access(MemoryAccess::Read(address));
+                memory.read(address)
+            }
             ByteLocation::NextMemoryByte => self.read_next_byte(memory),
         }
     }
@@ -313,7 +404,13 @@ impl CPUImpl {
         let carry_result2 = (le_bytes1[1] as u16) ^ (le_bytes2[1] as u16) ^ result2;
         let result = (&[result1, result2 as u8][..]).read_u16::<LittleEndian>().unwrap();
         if params.set_flag {
-            let flag = ((carry_result2.get_bit(4) as u8) << 5) | ((carry_result2.get_bit(8) as u8) << 4);
+            // SP + e derives its flags from the low byte, ADD HL,rr from the high byte
+            let (half_carry, carry) = if params.reset_zero_flag {
+                ((le_bytes1[0] & 0x0F) + (le_bytes2[0] & 0x0F) > 0x0F, carry1)
+            } else {
+                (carry_result2.get_bit(4), carry_result2.get_bit(8))
+            };
+            let flag = ((half_carry as u8) << 5) | ((carry as u8) << 4);
             self.registers.write_byte_masked(ByteRegister::F, flag, if params.reset_zero_flag { 0xF0 } else { 0x70 });
         }
         self.write_word(params.destination, result);
@@ -524,6 +621,7 @@ pub mod test {
     use assert_hex::assert_eq_hex;
     use test_case::test_case;
 
+    use crate::internal::cpu::interrupts::{InterruptController, InterruptControllerImpl};
     use crate::internal::memory::memory::test::MockMemory;
 
     use super::*;
@@ -534,6 +632,39 @@ pub mod test {
         }
     }
 
+    // Memory that routes the interrupt registers to an actual interrupt controller
+    struct MemoryWithInterrupts {
+        memory: MockMemory,
+        interrupt_controller: InterruptControllerImpl,
+    }
+
+    impl MemoryWithInterrupts {
+        fn new() -> MemoryWithInterrupts {
+            let mut interrupt_controller = InterruptControllerImpl::new();
+            interrupt_controller.write(MemoryAddress::IF, 0x00);
+            MemoryWithInterrupts {
+                memory: MockMemory::new(),
+                interrupt_controller,
+            }
+        }
+    }
+
+    impl Memory for MemoryWithInterrupts {
+        fn read(&self, address: u16) -> u8 {
+            match address {
+                MemoryAddress::IF | MemoryAddress::IE | MemoryAddress::IME | MemoryAddress::RI => self.interrupt_controller.read(address),
+                _ => self.memory.read(address)
+            }
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            match address {
+                MemoryAddress::IF | MemoryAddress::IE | MemoryAddress::IME | MemoryAddress::RI => self.interrupt_controller.write(address, value),
+                _ => self.memory.write(address, value)
+            }
+        }
+    }
+
     #[test]
     fn reg_to_reg_ld() {
         let mut cpu = CPUImpl::new();
@@ -833,9 +964,29 @@ pub mod test {
         assert_eq!(cpu.registers.read_word(WordRegister::HL), 0x0002);
     }
 
+    #[test_case(GbModel::Dmg, 0x01B0, 0x0013, 0x00D8, 0x014D; "DMG")]
+    #[test_case(GbModel::MgbPocket, 0xFFB0, 0x0013, 0x00D8, 0x014D; "MGB")]
+    #[test_case(GbModel::Sgb, 0x0100, 0x0014, 0x0000, 0xC060; "SGB")]
+    #[test_case(GbModel::Cgb, 0x1180, 0x0000, 0xFF56, 0x000D; "CGB")]
+    #[test_case(GbModel::CgbInDmgCompat, 0x1180, 0x0000, 0x0008, 0x007C; "CGB in DMG compatibility mode")]
+    fn init_for_model(model: GbModel, af: u16, bc: u16, de: u16, hl: u16) {
+        let mut cpu = CPUImpl::new();
+        cpu.init_for_model(model);
+        let cpu_info = cpu.cpu_info();
+        assert_eq_hex!(cpu_info.af, af);
+        assert_eq_hex!(cpu_info.bc, bc);
+        assert_eq_hex!(cpu_info.de, de);
+        assert_eq_hex!(cpu_info.hl, hl);
+        assert_eq_hex!(cpu_info.sp, 0xFFFE);
+        assert_eq_hex!(cpu_info.pc, 0x0100);
+    }
+
     #[test_case(0x0FF8, 0x07, 0x00; "no flags")]
-    #[test_case(0x0FF8, 0x08, 0x20; "only half carry")]
+    #[test_case(0x0FF8, 0x08, 0x30; "carries out of bits 3 and 7")]
     #[test_case(0xFFF8, 0x08, 0x30; "both carry flags")]
+    #[test_case(0x0F0F, 0x01, 0x20; "only half carry")]
+    #[test_case(0x00F0, 0x10, 0x10; "only carry")]
+    #[test_case(0x0FFF, 0x00, 0x00; "carry out of upper byte ignored")]
     fn reg_sp_plus_signed_immediate_to_hl_ld_writes_correct_flags(sp: u16, e: u8, f: u8) {
         let mut cpu = CPUImpl::new();
         let mut memory = MockMemory::new();
@@ -1004,6 +1155,31 @@ pub mod test {
         assert_eq!(cpu.registers.read_byte(ByteRegister::F), f);
     }
 
+    #[test]
+    fn subtract_with_carry_sets_flags_for_all_operands() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MockMemory::new();
+        memory.write(MemoryAddress::RI, 0xFF); // Return no interrupts
+        memory.write(0x0000, 0x9A);
+        for a in 0x00..=0xFFu8 {
+            for value in 0x00..=0xFFu8 {
+                for borrow in [0u8, 1] {
+                    cpu.registers.write_word(WordRegister::PC, 0x0000);
+                    cpu.registers.write_byte(ByteRegister::F, borrow << 4);
+                    cpu.registers.write_byte(ByteRegister::A, a);
+                    cpu.registers.write_byte(ByteRegister::D, value);
+                    cpu.tick(&mut memory);
+                    let result = a.wrapping_sub(value).wrapping_sub(borrow);
+                    let half_carry = (a & 0x0F) < (value & 0x0F) + borrow;
+                    let carry = (a as u16) < (value as u16) + (borrow as u16);
+                    let f = ((result == 0) as u8) << 7 | 0x40 | (half_carry as u8) << 5 | (carry as u8) << 4;
+                    assert_eq!(cpu.registers.read_byte(ByteRegister::A), result, "{:#04x} - {:#04x} - {}", a, value, borrow);
+                    assert_eq!(cpu.registers.read_byte(ByteRegister::F), f, "{:#04x} - {:#04x} - {}", a, value, borrow);
+                }
+            }
+        }
+    }
+
     #[test_case(0xFC, 0xFB, 0x00, 0xC0; "zero flag set correctly")]
     #[test_case(0x1F, 0x3E, 0xE0, 0x50; "carry set correctly")]
     #[test_case(0xF1, 0xE2, 0x0E, 0x60; "half carry set correctly")]
@@ -1284,6 +1460,10 @@ pub mod test {
 
     #[test_case(0x01, 0x00, 0x10, 0xD0; "zero flag set correctly and carry not affected")]
     #[test_case(0x10, 0x0F, 0x00, 0x60; "half carry set correctly")]
+    #[test_case(0x10, 0x0F, 0x90, 0x70; "half carry set correctly and carry preserved")]
+    #[test_case(0x00, 0xFF, 0x80, 0x60; "borrow out of bit 7 does not set carry")]
+    #[test_case(0x00, 0xFF, 0x10, 0x70; "borrow out of bit 7 preserves carry")]
+    #[test_case(0x11, 0x10, 0x70, 0x50; "half carry cleared without borrow from bit 4")]
     fn decrement_indirect_hl(value: u8, result: u8, f_old: u8, f_new: u8) {
         let mut cpu = CPUImpl::new();
         let mut memory = MockMemory::new();
@@ -1299,6 +1479,8 @@ pub mod test {
 
     #[test_case(0xF01E, 0xF028, 0xE046, 0x80, 0x90; "carry set correctly and zero flag not affected")]
     #[test_case(0x1E1E, 0x2828, 0x4646, 0x80, 0xA0; "half carry set correctly")]
+    #[test_case(0x00FF, 0x0001, 0x0100, 0x00, 0x00; "carry out of low byte ignored")]
+    #[test_case(0x0FFF, 0x0001, 0x1000, 0x00, 0x20; "half carry from bit 11")]
     fn add_reg_pair_to_reg_hl(hl: u16, value: u16, result: u16, f_old: u8, f_new: u8) {
         let mut cpu = CPUImpl::new();
         let mut memory = MockMemory::new();
@@ -1313,7 +1495,9 @@ pub mod test {
     }
 
     #[test_case(0xFFDA, 0x26, 0x0000, 0x30; "carry set correctly and zero flag set to zero")]
-    #[test_case(0x0FDA, 0x26, 0x1000, 0x20; "half carry set correctly")]
+    #[test_case(0x0FDA, 0x26, 0x1000, 0x30; "carry and half carry from low byte")]
+    #[test_case(0x00FF, 0x01, 0x0100, 0x30; "carry out of low byte sets both flags")]
+    #[test_case(0x0FFF, 0x00, 0x0FFF, 0x00; "upper byte does not affect flags")]
     fn add_immediate_to_reg_sp(sp: u16, value: u8, result: u16, f: u8) {
         let mut cpu = CPUImpl::new();
         let mut memory = MockMemory::new();
@@ -1791,6 +1975,36 @@ pub mod test {
         assert_eq!(cpu.registers.read_word(WordRegister::PC), 0x0008);
     }
 
+    #[test_case(0x0000, 0xFC, 0xFFFE; "backward jump below 0x0000 wraps")]
+    #[test_case(0xFFFD, 0x02, 0x0001; "forward jump above 0xFFFF wraps")]
+    #[test_case(0xFFFE, 0x04, 0x0004; "operand fetched from 0xFFFF wraps program counter")]
+    fn jump_relative_wraps_around_address_space(address: u16, offset: u8, expected_pc: u16) {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MockMemory::new();
+        memory.write(MemoryAddress::RI, 0xFF); // Return no interrupts
+        cpu.registers.write_word(WordRegister::PC, address);
+        memory.write(address, 0x18);
+        memory.write(address.wrapping_add(1), offset);
+        perform_ticks(&mut cpu, &mut memory, 3);
+
+        assert_eq!(cpu.registers.read_word(WordRegister::PC), expected_pc);
+    }
+
+    #[test_case(0x0000, 0xFC, 0xFFFE; "backward jump below 0x0000 wraps")]
+    #[test_case(0xFFFD, 0x02, 0x0001; "forward jump above 0xFFFF wraps")]
+    fn jump_conditional_relative_wraps_around_address_space(address: u16, offset: u8, expected_pc: u16) {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MockMemory::new();
+        memory.write(MemoryAddress::RI, 0xFF); // Return no interrupts
+        cpu.registers.write_byte(ByteRegister::F, 0x00);
+        cpu.registers.write_word(WordRegister::PC, address);
+        memory.write(address, 0x20); // JR NZ
+        memory.write(address.wrapping_add(1), offset);
+        perform_ticks(&mut cpu, &mut memory, 3);
+
+        assert_eq!(cpu.registers.read_word(WordRegister::PC), expected_pc);
+    }
+
     #[test_case(0x00, 0x70; "jumps when zero flag not set")]
     #[test_case(0x01, 0x80; "jumps when zero flag set")]
     #[test_case(0x02, 0xE0; "jumps when carry not set")]
@@ -1979,10 +2193,6 @@ pub mod test {
                 let a = (x % 10) | ((x / 10) << 4);
                 let d = (y % 10) | ((y / 10) << 4);
                 let f = u8::compose(&[(sum % 100 == 0, 7), (sum >= 100, 4)]);
-                if a == 0x1 && d == 0x9 {
-                    println!("Invalid result. A: {:#x}, D: {:#x}, F: {:#x}", a, d, f);
-                }
-
                 cpu.registers.write_byte(ByteRegister::A, a);
                 cpu.registers.write_byte(ByteRegister::D, d);
                 memory.write(instruction_index, 0x82);
@@ -2013,6 +2223,48 @@ pub mod test {
         })
     }
 
+    fn reference_decimal_adjust(a: u8, f: u8) -> (u8, u8) {
+        let (n, half_carry, carry) = (f.get_bit(6), f.get_bit(5), f.get_bit(4));
+        let mut result = a;
+        let mut result_carry = carry;
+        if n {
+            if carry {
+                result = result.wrapping_sub(0x60);
+            }
+            if half_carry {
+                result = result.wrapping_sub(0x06);
+            }
+        } else {
+            if carry || a > 0x99 {
+                result = result.wrapping_add(0x60);
+                result_carry = true;
+            }
+            if half_carry || (a & 0x0F) > 0x09 {
+                result = result.wrapping_add(0x06);
+            }
+        }
+        (result, u8::compose(&[(result == 0, 7), (n, 6), (result_carry, 4)]))
+    }
+
+    #[test]
+    fn decimal_adjust_reg_a_matches_reference_for_all_inputs() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MockMemory::new();
+        memory.write(MemoryAddress::RI, 0xFF); // Return no interrupts
+        memory.write(0x0000, 0x27);
+        for a in 0x00..=0xFFu8 {
+            for f in [0x00u8, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70] {
+                cpu.registers.write_word(WordRegister::PC, 0x0000);
+                cpu.registers.write_byte(ByteRegister::A, a);
+                cpu.registers.write_byte(ByteRegister::F, f);
+                cpu.tick(&mut memory);
+                let (expected_a, expected_f) = reference_decimal_adjust(a, f);
+                assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), expected_a, "A: {:#04x}, F: {:#04x}", a, f);
+                assert_eq_hex!(cpu.registers.read_byte(ByteRegister::F), expected_f, "A: {:#04x}, F: {:#04x}", a, f);
+            }
+        }
+    }
+
     #[test]
     fn ones_complement_reg_a() {
         let mut cpu = CPUImpl::new();
@@ -2062,7 +2314,198 @@ pub mod test {
         memory.write(0x0001, 0xFB);
         cpu.tick(&mut memory);
         assert_eq!(memory.read(MemoryAddress::IME), 0x00);
+        assert!(!cpu.ime_enabled());
         cpu.tick(&mut memory);
+        assert_eq!(memory.read(MemoryAddress::IME), 0x00);
+        assert!(!cpu.ime_enabled());
+        cpu.tick(&mut memory); // IME is set once the instruction following EI executes
         assert_eq!(memory.read(MemoryAddress::IME), 0x01);
+        assert!(cpu.ime_enabled());
+    }
+
+    #[test]
+    fn cpu_info_reflects_ime_transitions() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        cpu.registers.write_word(WordRegister::SP, 0xCFFE);
+        memory.write(0xCFFE, 0x04);
+        memory.write(0xCFFF, 0x00); // Return address 0x0004
+        memory.write(0x0000, 0xFB); // EI
+        memory.write(0x0001, 0x00); // NOP
+        memory.write(0x0002, 0xF3); // DI
+        memory.write(0x0003, 0xD9); // RETI
+        memory.write(0x0004, 0x00); // NOP
+        let ime_state = |cpu: &CPUImpl, memory: &MemoryWithInterrupts| {
+            let cpu_info = cpu.cpu_info();
+            assert_eq!(cpu.ime_enabled(), memory.interrupt_controller.interrupts_enabled());
+            assert_eq!(cpu_info.ime, cpu.ime_enabled());
+            (cpu.ime_enabled(), cpu_info.ime_pending)
+        };
+        assert_eq!(ime_state(&cpu, &memory), (false, false));
+        perform_ticks(&mut cpu, &mut memory, 1); // EI
+        assert_eq!(ime_state(&cpu, &memory), (false, true));
+        perform_ticks(&mut cpu, &mut memory, 1); // NOP
+        assert_eq!(ime_state(&cpu, &memory), (true, false));
+        perform_ticks(&mut cpu, &mut memory, 1); // DI
+        assert_eq!(ime_state(&cpu, &memory), (false, false));
+        perform_ticks(&mut cpu, &mut memory, 4); // RETI
+        assert_eq!(ime_state(&cpu, &memory), (true, false));
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0004);
+    }
+
+    #[test]
+    fn instructions_executed_counts_decoded_instructions() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        memory.write(0x0000, 0x00); // NOP
+        memory.write(0x0001, 0x3E); // LD A, 0x05
+        memory.write(0x0002, 0x05);
+        memory.write(0x0003, 0xCB); // SWAP A
+        memory.write(0x0004, 0x37);
+        memory.write(0x0005, 0x3C); // INC A
+        perform_ticks(&mut cpu, &mut memory, 1);
+        assert_eq!(cpu.cpu_info().instructions_executed, 1);
+        perform_ticks(&mut cpu, &mut memory, 1); // Halfway through LD A, 0x05
+        assert_eq!(cpu.cpu_info().instructions_executed, 2);
+        perform_ticks(&mut cpu, &mut memory, 4);
+        assert_eq!(cpu.cpu_info().instructions_executed, 4);
+        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), 0x51);
+    }
+
+    #[test]
+    fn ei_delays_interrupt_dispatch_by_one_instruction() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        cpu.registers.write_word(WordRegister::SP, 0xD000);
+        memory.write(MemoryAddress::IE, 0x01);
+        memory.write(0x0000, 0xFB); // EI
+        memory.write(0x0001, 0x3C); // INC A
+        memory.write(0x0002, 0x3C); // INC A
+        memory.interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+        let a = cpu.registers.read_byte(ByteRegister::A);
+        perform_ticks(&mut cpu, &mut memory, 2);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0002);
+        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), a.wrapping_add(1));
+        perform_ticks(&mut cpu, &mut memory, 5);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+        assert_eq_hex!(memory.read(0xCFFE), 0x02);
+    }
+
+    #[test]
+    fn interrupt_raised_mid_instruction_is_serviced_after_it_completes() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        cpu.registers.write_word(WordRegister::SP, 0xD000);
+        memory.write(MemoryAddress::IME, 0x01);
+        memory.write(MemoryAddress::IE, 0x01);
+        memory.write(0x0000, 0xCD); // CALL 0x1234
+        memory.write(0x0001, 0x34);
+        memory.write(0x0002, 0x12);
+        perform_ticks(&mut cpu, &mut memory, 2);
+        memory.interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+        perform_ticks(&mut cpu, &mut memory, 3);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0003); // Still pushing the return address
+        perform_ticks(&mut cpu, &mut memory, 1);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x1234);
+        assert_eq_hex!(memory.read(0xCFFE), 0x03);
+        assert_eq_hex!(memory.read(0xCFFF), 0x00);
+        perform_ticks(&mut cpu, &mut memory, 5);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+        assert_eq_hex!(memory.read(0xCFFC), 0x34);
+        assert_eq_hex!(memory.read(0xCFFD), 0x12);
+    }
+
+    #[test]
+    fn reti_services_pending_interrupt_before_next_instruction() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        cpu.registers.write_word(WordRegister::SP, 0xCFFE);
+        memory.write(0xCFFE, 0x00);
+        memory.write(0xCFFF, 0x12); // Return address 0x1200
+        memory.write(MemoryAddress::IE, 0x01);
+        memory.write(0x0000, 0xD9); // RETI
+        memory.write(0x1200, 0x3C); // INC A
+        memory.interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+        let a = cpu.registers.read_byte(ByteRegister::A);
+        perform_ticks(&mut cpu, &mut memory, 4);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x1200);
+        assert_eq!(memory.read(MemoryAddress::IME), 0x01);
+        perform_ticks(&mut cpu, &mut memory, 5);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), a);
+        assert_eq_hex!(memory.read(0xCFFE), 0x00);
+        assert_eq_hex!(memory.read(0xCFFF), 0x12);
+    }
+
+    #[test]
+    fn halt_with_ime_set_dispatches_pending_interrupt() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        cpu.registers.write_word(WordRegister::SP, 0xD000);
+        memory.write(MemoryAddress::IME, 0x01);
+        memory.write(MemoryAddress::IE, 0x01);
+        memory.write(0x0000, 0x76); // HALT
+        memory.write(0x0001, 0x3C); // INC A
+        memory.write(0x0040, 0xD9); // RETI
+        perform_ticks(&mut cpu, &mut memory, 3);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0001);
+
+        memory.interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+        cpu.tick(&mut memory); // Wake up
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0001);
+        perform_ticks(&mut cpu, &mut memory, 5);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0040);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::SP), 0xCFFE);
+        assert_eq_hex!(memory.read(0xCFFE), 0x01);
+        assert_eq_hex!(memory.read(0xCFFF), 0x00);
+        assert_eq_hex!(memory.read(MemoryAddress::IF) & 0x1F, 0x00);
+        assert_eq!(memory.read(MemoryAddress::IME), 0x00);
+
+        perform_ticks(&mut cpu, &mut memory, 4);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0001);
+        assert_eq!(memory.read(MemoryAddress::IME), 0x01);
+        let a = cpu.registers.read_byte(ByteRegister::A);
+        cpu.tick(&mut memory);
+        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), a.wrapping_add(1));
+    }
+
+    #[test]
+    fn interrupt_dispatch_takes_five_machine_cycles() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        cpu.registers.write_word(WordRegister::SP, 0xD000);
+        memory.write(MemoryAddress::IME, 0x01);
+        memory.write(MemoryAddress::IE, 0x04);
+        memory.write(0x0000, 0x3C); // INC A
+        memory.write(0x0050, 0x3C); // INC A
+        memory.interrupt_controller.request_interrupt(Interrupt::TimerOverflow);
+        let a = cpu.registers.read_byte(ByteRegister::A);
+        for _ in 0..4 {
+            cpu.tick(&mut memory);
+            assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0000);
+        }
+        cpu.tick(&mut memory);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0050);
+        assert!(cpu.instructions.is_empty());
+        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), a); // No instruction was executed during dispatch
+        cpu.tick(&mut memory);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0051);
+        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), a.wrapping_add(1));
+    }
+
+    #[test]
+    fn halt_with_ime_cleared_resumes_without_dispatch() {
+        let mut cpu = CPUImpl::new();
+        let mut memory = MemoryWithInterrupts::new();
+        memory.write(MemoryAddress::IE, 0x01);
+        memory.write(0x0000, 0x76); // HALT
+        memory.write(0x0001, 0x3C); // INC A
+        perform_ticks(&mut cpu, &mut memory, 3);
+        memory.interrupt_controller.request_interrupt(Interrupt::VerticalBlank);
+        let a = cpu.registers.read_byte(ByteRegister::A);
+        perform_ticks(&mut cpu, &mut memory, 2);
+        assert_eq_hex!(cpu.registers.read_word(WordRegister::PC), 0x0002);
+        assert_eq_hex!(cpu.registers.read_byte(ByteRegister::A), a.wrapping_add(1));
+        assert_eq_hex!(memory.read(MemoryAddress::IF) & 0x1F, 0x01);
     }
 }
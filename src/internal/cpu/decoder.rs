@@ -1,9 +1,13 @@
+use crate::cpu::{Condition, DecodedInstruction, Mnemonic, Operand, Register, RegisterPair};
 use crate::internal::cpu::instruction::{ByteArithmeticParams, ByteCastingParams, ByteLocation, ByteLogicParams, ByteOperationParams, ByteRotationParams, ByteShiftParams, Instruction, WordArithmeticParams, WordLocation, WordOperationParams};
-use crate::internal::cpu::instruction::Instruction::{AddBytes, AddWords, AndBytes, BranchIfCarry, BranchIfNotCarry, BranchIfNotZero, BranchIfZero, CastByteToSignedWord, ClearInterrupt, DecimalAdjust, DecodeCBInstruction, DecrementWord, Defer, DisableInterrupts, EnableInterrupts, EndBranch, FlipCarry, GetBitFromByte, Halt, IncrementWord, MoveByte, MoveWord, Noop, OnesComplementByte, OrBytes, ResetBitOnByte, RotateByteLeft, RotateByteLeftThroughCarry, RotateByteRight, RotateByteRightThroughCarry, SetBitOnByte, SetCarry, ShiftByteLeft, ShiftByteRight, Stop, SubtractBytes, SwapByte, XorBytes};
+use crate::internal::cpu::instruction::Instruction::{AddBytes, AddWords, AndBytes, BranchIfCarry, BranchIfNotCarry, BranchIfNotZero, BranchIfZero, CastByteToSignedWord, ClearInterrupt, DecimalAdjust, DecodeCBInstruction, DecrementWord, Defer, DisableInterrupts, EnableInterrupts, EnableInterruptsImmediately, EndBranch, FlipCarry, GetBitFromByte, Halt, IncrementWord, MoveByte, MoveWord, Noop, OnesComplementByte, OrBytes, ResetBitOnByte, RotateByteLeft, RotateByteLeftThroughCarry, RotateByteRight, RotateByteRightThroughCarry, SetBitOnByte, SetCarry, ShiftByteLeft, ShiftByteRight, Stop, SubtractBytes, SwapByte, XorBytes};
 use crate::internal::cpu::interrupts::Interrupt;
 use crate::internal::cpu::opcode::Opcode;
 use crate::internal::cpu::register::{ByteRegister, WordRegister};
 
+// The longest schedule is produced by a conditional CALL
+pub const MAX_SCHEDULED_INSTRUCTIONS: usize = 14;
+
 pub trait InstructionScheduler {
     fn schedule(&mut self, instruction: Instruction);
 }
@@ -262,6 +266,160 @@ impl InstructionDecoder {
         };
     }
 
+    // Describes the instruction at the start of the bytes, following the opcode's bit structure instead of the
+    // micro-instructions the CPU executes for it
+    pub fn decode_to_descriptor(bytes: &[u8]) -> DecodedInstruction {
+        let byte_at = |index: usize| bytes.get(index).copied().unwrap_or(0);
+        let immediate_8 = Operand::Immediate8(byte_at(1));
+        let immediate_16 = (byte_at(1) as u16) | ((byte_at(2) as u16) << 8);
+        let opcode = Opcode(byte_at(0));
+        let y = opcode.y_bits();
+        let z = opcode.z_bits();
+        let q = y & 1;
+        let p = opcode.dd_bits();
+        let register_operand = |bits: u8| InstructionDecoder::register_operand(bits);
+        let condition = Some(InstructionDecoder::condition(opcode.cc_bits()));
+        let (mnemonic, condition, operands, length) = match (opcode.value() >> 6, z) {
+            (0, 0) => match y {
+                0 => (Mnemonic::Nop, None, vec![], 1),
+                1 => (Mnemonic::Ld, None, vec![Operand::IndirectAddress(immediate_16), Operand::RegisterPair(RegisterPair::SP)], 3),
+                2 => (Mnemonic::Stop, None, vec![], 1),
+                3 => (Mnemonic::Jr, None, vec![Operand::Offset(byte_at(1) as i8)], 2),
+                _ => (Mnemonic::Jr, condition, vec![Operand::Offset(byte_at(1) as i8)], 2),
+            },
+            (0, 1) if q == 0 => (Mnemonic::Ld, None, vec![Operand::RegisterPair(InstructionDecoder::register_pair(p, false)), Operand::Immediate16(immediate_16)], 3),
+            (0, 1) => (Mnemonic::Add, None, vec![Operand::RegisterPair(RegisterPair::HL), Operand::RegisterPair(InstructionDecoder::register_pair(p, false))], 1),
+            (0, 2) => {
+                let memory_operand = match p {
+                    0 => Operand::Indirect(RegisterPair::BC),
+                    1 => Operand::Indirect(RegisterPair::DE),
+                    2 => Operand::IndirectHLIncrement,
+                    _ => Operand::IndirectHLDecrement,
+                };
+                let accumulator = Operand::Register(Register::A);
+                (Mnemonic::Ld, None, if q == 0 { vec![memory_operand, accumulator] } else { vec![accumulator, memory_operand] }, 1)
+            }
+            (0, 3) => (if q == 0 { Mnemonic::Inc } else { Mnemonic::Dec }, None, vec![Operand::RegisterPair(InstructionDecoder::register_pair(p, false))], 1),
+            (0, 4) => (Mnemonic::Inc, None, vec![register_operand(y)], 1),
+            (0, 5) => (Mnemonic::Dec, None, vec![register_operand(y)], 1),
+            (0, 6) => (Mnemonic::Ld, None, vec![register_operand(y), immediate_8], 2),
+            (0, _) => ([Mnemonic::Rlca, Mnemonic::Rrca, Mnemonic::Rla, Mnemonic::Rra, Mnemonic::Daa, Mnemonic::Cpl, Mnemonic::Scf, Mnemonic::Ccf][y as usize], None, vec![], 1),
+            (1, _) if opcode.value() == 0x76 => (Mnemonic::Halt, None, vec![], 1),
+            (1, _) => (Mnemonic::Ld, None, vec![register_operand(y), register_operand(z)], 1),
+            (2, _) => (InstructionDecoder::alu_mnemonic(y), None, vec![Operand::Register(Register::A), register_operand(z)], 1),
+            (_, 0) => match y {
+                0..=3 => (Mnemonic::Ret, condition, vec![], 1),
+                4 => (Mnemonic::Ldh, None, vec![Operand::HighIndirect(byte_at(1)), Operand::Register(Register::A)], 2),
+                5 => (Mnemonic::Add, None, vec![Operand::RegisterPair(RegisterPair::SP), Operand::Offset(byte_at(1) as i8)], 2),
+                6 => (Mnemonic::Ldh, None, vec![Operand::Register(Register::A), Operand::HighIndirect(byte_at(1))], 2),
+                _ => (Mnemonic::Ld, None, vec![Operand::RegisterPair(RegisterPair::HL), Operand::RegisterPair(RegisterPair::SP), Operand::Offset(byte_at(1) as i8)], 2),
+            },
+            (_, 1) if q == 0 => (Mnemonic::Pop, None, vec![Operand::RegisterPair(InstructionDecoder::register_pair(p, true))], 1),
+            (_, 1) => match p {
+                0 => (Mnemonic::Ret, None, vec![], 1),
+                1 => (Mnemonic::Reti, None, vec![], 1),
+                2 => (Mnemonic::Jp, None, vec![Operand::RegisterPair(RegisterPair::HL)], 1),
+                _ => (Mnemonic::Ld, None, vec![Operand::RegisterPair(RegisterPair::SP), Operand::RegisterPair(RegisterPair::HL)], 1),
+            },
+            (_, 2) => match y {
+                0..=3 => (Mnemonic::Jp, condition, vec![Operand::Immediate16(immediate_16)], 3),
+                4 => (Mnemonic::Ld, None, vec![Operand::HighIndirectC, Operand::Register(Register::A)], 1),
+                5 => (Mnemonic::Ld, None, vec![Operand::IndirectAddress(immediate_16), Operand::Register(Register::A)], 3),
+                6 => (Mnemonic::Ld, None, vec![Operand::Register(Register::A), Operand::HighIndirectC], 1),
+                _ => (Mnemonic::Ld, None, vec![Operand::Register(Register::A), Operand::IndirectAddress(immediate_16)], 3),
+            },
+            (_, 3) => match y {
+                0 => (Mnemonic::Jp, None, vec![Operand::Immediate16(immediate_16)], 3),
+                1 => InstructionDecoder::cb_descriptor(Opcode(byte_at(1))),
+                6 => (Mnemonic::Di, None, vec![], 1),
+                7 => (Mnemonic::Ei, None, vec![], 1),
+                _ => (Mnemonic::Invalid, None, vec![], 1),
+            },
+            (_, 4) if y < 4 => (Mnemonic::Call, condition, vec![Operand::Immediate16(immediate_16)], 3),
+            (_, 5) if q == 0 => (Mnemonic::Push, None, vec![Operand::RegisterPair(InstructionDecoder::register_pair(p, true))], 1),
+            (_, 5) if p == 0 => (Mnemonic::Call, None, vec![Operand::Immediate16(immediate_16)], 3),
+            (_, 6) => (InstructionDecoder::alu_mnemonic(y), None, vec![Operand::Register(Register::A), immediate_8], 2),
+            (_, 7) => (Mnemonic::Rst, None, vec![Operand::Vector(y * 8)], 1),
+            _ => (Mnemonic::Invalid, None, vec![], 1),
+        };
+        DecodedInstruction { mnemonic, condition, operands, length }
+    }
+
+    fn cb_descriptor(opcode: Opcode) -> (Mnemonic, Option<Condition>, Vec<Operand>, u8) {
+        let y = opcode.y_bits();
+        let register = InstructionDecoder::register_operand(opcode.z_bits());
+        match opcode.value() >> 6 {
+            0 => ([Mnemonic::Rlc, Mnemonic::Rrc, Mnemonic::Rl, Mnemonic::Rr, Mnemonic::Sla, Mnemonic::Sra, Mnemonic::Swap, Mnemonic::Srl][y as usize], None, vec![register], 2),
+            1 => (Mnemonic::Bit, None, vec![Operand::Bit(y), register], 2),
+            2 => (Mnemonic::Res, None, vec![Operand::Bit(y), register], 2),
+            _ => (Mnemonic::Set, None, vec![Operand::Bit(y), register], 2),
+        }
+    }
+
+    fn register_operand(bits: u8) -> Operand {
+        match bits {
+            0 => Operand::Register(Register::B),
+            1 => Operand::Register(Register::C),
+            2 => Operand::Register(Register::D),
+            3 => Operand::Register(Register::E),
+            4 => Operand::Register(Register::H),
+            5 => Operand::Register(Register::L),
+            6 => Operand::Indirect(RegisterPair::HL),
+            _ => Operand::Register(Register::A),
+        }
+    }
+
+    // PUSH and POP use AF where the other instructions use SP
+    fn register_pair(bits: u8, use_af: bool) -> RegisterPair {
+        match bits {
+            0 => RegisterPair::BC,
+            1 => RegisterPair::DE,
+            2 => RegisterPair::HL,
+            _ => if use_af { RegisterPair::AF } else { RegisterPair::SP },
+        }
+    }
+
+    fn condition(bits: u8) -> Condition {
+        match bits {
+            0 => Condition::NotZero,
+            1 => Condition::Zero,
+            2 => Condition::NotCarry,
+            _ => Condition::Carry,
+        }
+    }
+
+    fn alu_mnemonic(bits: u8) -> Mnemonic {
+        [Mnemonic::Add, Mnemonic::Adc, Mnemonic::Sub, Mnemonic::Sbc, Mnemonic::And, Mnemonic::Xor, Mnemonic::Or, Mnemonic::Cp][bits as usize]
+    }
+
+    // Checks that a schedule can be executed by the CPU. Branches must be closed by an EndBranch before the next one
+    // starts, and a CB instruction can only be decoded after the machine cycle that fetched its prefix.
+    pub fn validate_schedule<'a>(instructions: impl IntoIterator<Item=&'a Instruction>) -> Result<(), String> {
+        let mut branch_open = false;
+        let mut previous_instruction: Option<&Instruction> = None;
+        for (index, instruction) in instructions.into_iter().enumerate() {
+            match instruction {
+                BranchIfZero | BranchIfNotZero | BranchIfCarry | BranchIfNotCarry if branch_open => {
+                    return Err(format!("Branch at position {} starts before the previous branch has ended", index));
+                }
+                BranchIfZero | BranchIfNotZero | BranchIfCarry | BranchIfNotCarry => branch_open = true,
+                EndBranch if !branch_open => {
+                    return Err(format!("EndBranch at position {} doesn't close a branch", index));
+                }
+                EndBranch => branch_open = false,
+                DecodeCBInstruction if !matches!(previous_instruction, Some(Defer)) => {
+                    return Err(format!("DecodeCBInstruction at position {} isn't preceded by a Defer", index));
+                }
+                _ => {}
+            }
+            previous_instruction = Some(instruction);
+        }
+        if branch_open {
+            return Err(String::from("Branch is never closed by an EndBranch"));
+        }
+        Ok(())
+    }
+
     fn reg_to_reg_ld(scheduler: &mut dyn InstructionScheduler, opcode: Opcode) {
         scheduler.schedule(
             MoveByte(ByteOperationParams {
@@ -1359,11 +1517,13 @@ impl InstructionDecoder {
         }));
     }
 
+    // Dispatching an interrupt takes 5 machine cycles: 2 wait states, 2 cycles to push PC and 1 to jump to the vector
     pub fn schedule_call_interrupt_routine(scheduler: &mut dyn InstructionScheduler, interrupt: Interrupt) {
         scheduler.schedule(ClearInterrupt(interrupt));
         scheduler.schedule(DisableInterrupts);
         scheduler.schedule(Defer);
         scheduler.schedule(Noop);
+        scheduler.schedule(Defer);
         scheduler.schedule(DecrementWord(WordLocation::Register(WordRegister::SP)));
         scheduler.schedule(MoveByte(ByteOperationParams {
             source: ByteLocation::Register(ByteRegister::UpperPC),
@@ -1377,7 +1537,7 @@ impl InstructionDecoder {
         }));
         scheduler.schedule(Defer);
         scheduler.schedule(MoveWord(WordOperationParams {
-            source: WordLocation::Value(interrupt.get_routine_address()),
+            source: WordLocation::Value(interrupt.routine_address()),
             destination: WordLocation::Register(WordRegister::PC),
         }));
     }
@@ -1466,7 +1626,8 @@ impl InstructionDecoder {
 
     fn return_from_interrupt(scheduler: &mut dyn InstructionScheduler) {
         InstructionDecoder::return_from_call(scheduler);
-        scheduler.schedule(EnableInterrupts);
+        // Unlike EI, RETI sets IME right away, so a pending interrupt is serviced before the next instruction
+        scheduler.schedule(EnableInterruptsImmediately);
     }
 
     fn return_conditionally(scheduler: &mut dyn InstructionScheduler, opcode: Opcode) {
@@ -1541,4 +1702,99 @@ impl InstructionDecoder {
     fn stop(scheduler: &mut dyn InstructionScheduler) {
         scheduler.schedule(Stop)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const UNKNOWN_OPCODES: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+    impl InstructionScheduler for VecDeque<Instruction> {
+        fn schedule(&mut self, instruction: Instruction) {
+            self.push_back(instruction);
+        }
+    }
+
+    #[test]
+    fn all_opcodes_produce_valid_schedules() {
+        for opcode in (0x00..=0xFFu8).filter(|opcode| !UNKNOWN_OPCODES.contains(opcode)) {
+            let mut instructions: VecDeque<Instruction> = VecDeque::new();
+            InstructionDecoder::decode(&mut instructions, Opcode(opcode));
+            assert_eq!(InstructionDecoder::validate_schedule(&instructions), Ok(()), "Opcode {:#04x}", opcode);
+        }
+        for opcode in 0x00..=0xFFu8 {
+            let mut instructions: VecDeque<Instruction> = VecDeque::new();
+            InstructionDecoder::decode_cb(&mut instructions, Opcode(opcode));
+            assert_eq!(InstructionDecoder::validate_schedule(&instructions), Ok(()), "CB opcode {:#04x}", opcode);
+        }
+    }
+
+    #[test]
+    fn schedules_never_exceed_maximum_length() {
+        for opcode in (0x00..=0xFFu8).filter(|opcode| !UNKNOWN_OPCODES.contains(opcode)) {
+            let mut instructions: VecDeque<Instruction> = VecDeque::new();
+            InstructionDecoder::decode(&mut instructions, Opcode(opcode));
+            assert!(instructions.len() <= MAX_SCHEDULED_INSTRUCTIONS, "Opcode {:#04x}", opcode);
+        }
+        for opcode in 0x00..=0xFFu8 {
+            let mut instructions: VecDeque<Instruction> = VecDeque::new();
+            InstructionDecoder::decode_cb(&mut instructions, Opcode(opcode));
+            assert!(instructions.len() <= MAX_SCHEDULED_INSTRUCTIONS, "CB opcode {:#04x}", opcode);
+        }
+        let mut instructions: VecDeque<Instruction> = VecDeque::new();
+        InstructionDecoder::schedule_call_interrupt_routine(&mut instructions, Interrupt::VerticalBlank);
+        assert!(instructions.len() <= MAX_SCHEDULED_INSTRUCTIONS);
+    }
+
+    #[test]
+    fn conditional_jump_is_decoded_to_descriptor() {
+        let descriptor = InstructionDecoder::decode_to_descriptor(&[0xCA, 0x34, 0x12]);
+        assert_eq!(descriptor, DecodedInstruction {
+            mnemonic: Mnemonic::Jp,
+            condition: Some(Condition::Zero),
+            operands: vec![Operand::Immediate16(0x1234)],
+            length: 3,
+        });
+    }
+
+    #[test]
+    fn register_load_is_decoded_to_descriptor() {
+        let descriptor = InstructionDecoder::decode_to_descriptor(&[0x43]);
+        assert_eq!(descriptor, DecodedInstruction {
+            mnemonic: Mnemonic::Ld,
+            condition: None,
+            operands: vec![Operand::Register(Register::B), Operand::Register(Register::E)],
+            length: 1,
+        });
+        let descriptor = InstructionDecoder::decode_to_descriptor(&[0x7E]);
+        assert_eq!(descriptor.operands, vec![Operand::Register(Register::A), Operand::Indirect(RegisterPair::HL)]);
+    }
+
+    #[test]
+    fn only_unknown_opcodes_are_decoded_as_invalid() {
+        for opcode in 0x00..=0xFFu8 {
+            let descriptor = InstructionDecoder::decode_to_descriptor(&[opcode, 0x00, 0x00]);
+            assert_eq!(descriptor.mnemonic == Mnemonic::Invalid, UNKNOWN_OPCODES.contains(&opcode), "Opcode {:#04x}", opcode);
+        }
+        let descriptor = InstructionDecoder::decode_to_descriptor(&[0xCB, 0x7C]);
+        assert_eq!(descriptor, DecodedInstruction {
+            mnemonic: Mnemonic::Bit,
+            condition: None,
+            operands: vec![Operand::Bit(7), Operand::Register(Register::H)],
+            length: 2,
+        });
+    }
+
+    #[test]
+    fn malformed_schedules_are_flagged() {
+        assert!(InstructionDecoder::validate_schedule(&[EndBranch, Noop]).is_err());
+        assert!(InstructionDecoder::validate_schedule(&[BranchIfZero, Defer, Noop]).is_err());
+        assert!(InstructionDecoder::validate_schedule(&[BranchIfZero, BranchIfCarry, EndBranch, EndBranch]).is_err());
+        assert!(InstructionDecoder::validate_schedule(&[Noop, DecodeCBInstruction]).is_err());
+        assert!(InstructionDecoder::validate_schedule(&[Defer, DecodeCBInstruction]).is_ok());
+        assert!(InstructionDecoder::validate_schedule(&[BranchIfNotCarry, Defer, Noop, EndBranch]).is_ok());
+    }
+}
@@ -117,6 +117,7 @@ pub enum Instruction {
   ResetBitOnByte(ByteOperationParams, u8),
   ClearInterrupt(Interrupt),
   EnableInterrupts,
+  EnableInterruptsImmediately,
   DisableInterrupts,
   FlipCarry,
   SetCarry,
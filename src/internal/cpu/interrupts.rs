@@ -14,6 +14,16 @@ pub enum Interrupt {
 }
 
 impl Interrupt {
+  // In order of priority, highest first
+  pub const ALL: [Interrupt; 5] = [
+    Interrupt::VerticalBlank,
+    Interrupt::Stat,
+    Interrupt::TimerOverflow,
+    Interrupt::SerialIOComplete,
+    Interrupt::ButtonPressed,
+  ];
+
+  // The position of the interrupt's bit in the IF and IE registers
   pub fn get_bit(&self) -> u8 {
     match self {
       Interrupt::VerticalBlank => 0,
@@ -24,7 +34,7 @@ impl Interrupt {
     }
   }
 
-  pub fn get_routine_address(&self) -> u16 {
+  pub fn routine_address(&self) -> u16 {
     match self {
       Interrupt::VerticalBlank => 0x40,
       Interrupt::Stat => 0x48,
@@ -139,6 +149,15 @@ impl Memory for InterruptControllerImpl {
 mod tests {
   use super::*;
 
+  #[test]
+  fn interrupts_match_hardware_vectors_and_bits() {
+    let vectors_and_bits: Vec<(u16, u8)> = Interrupt::ALL.iter()
+      .map(|interrupt| (interrupt.routine_address(), interrupt.get_bit()))
+      .collect();
+    assert_eq!(vectors_and_bits, vec![(0x40, 0), (0x48, 1), (0x50, 2), (0x58, 3), (0x60, 4)]);
+    Interrupt::ALL.iter().for_each(|interrupt| assert_eq!(Interrupt::from_bit(interrupt.get_bit()), Some(*interrupt)));
+  }
+
   #[test]
   fn get_requested_interrupt_returns_highest_priority() {
     let mut interrupt_controller = InterruptControllerImpl::new();
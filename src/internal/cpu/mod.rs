@@ -3,4 +3,4 @@ pub mod interrupts;
 mod opcode;
 mod register;
 mod instruction;
-mod decoder;
\ No newline at end of file
+pub mod decoder;
\ No newline at end of file
@@ -0,0 +1,42 @@
+use crate::debug::Coverage;
+use crate::internal::cpu::cpu::MemoryAccess;
+use crate::internal::memory::mbc::MBC;
+
+// Only accesses to the cartridge ROM are tracked, so the coverage can be mapped back onto the ROM file
+pub struct CoverageTracker {
+  enabled: bool,
+  coverage: Coverage,
+}
+
+impl CoverageTracker {
+  pub fn new() -> CoverageTracker {
+    CoverageTracker {
+      enabled: false,
+      coverage: Coverage::default(),
+    }
+  }
+
+  pub fn enabled(&self) -> bool {
+    self.enabled
+  }
+
+  pub fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+  }
+
+  pub fn coverage(&self) -> &Coverage {
+    &self.coverage
+  }
+
+  pub fn reset(&mut self) {
+    self.coverage = Coverage::default();
+  }
+
+  pub fn track(&mut self, accesses: Vec<MemoryAccess>, rom: &dyn MBC) {
+    accesses.into_iter().for_each(|access| match access {
+      MemoryAccess::Fetch(address) if address < 0x8000 => self.coverage.mark_executed(rom.rom_bank(address), address),
+      MemoryAccess::Read(address) if address < 0x8000 => self.coverage.mark_read(rom.rom_bank(address), address),
+      _ => {}
+    });
+  }
+}
@@ -0,0 +1,79 @@
+use crate::renderer::{AccessibilityFilter, Color, Renderer, RenderTarget};
+
+// A filter together with its daltonization matrix, which is only computed once when the filter is picked
+#[derive(Copy, Clone)]
+pub struct PreparedFilter {
+  filter: AccessibilityFilter,
+  daltonization_matrix: Option<[[f32; 3]; 3]>,
+}
+
+impl PreparedFilter {
+  pub fn new(filter: AccessibilityFilter) -> Self {
+    PreparedFilter {
+      filter,
+      daltonization_matrix: filter.daltonization_matrix(),
+    }
+  }
+
+  pub fn filter(&self) -> AccessibilityFilter {
+    self.filter
+  }
+
+  pub fn apply(&self, color: Color) -> Color {
+    self.filter.apply_with_matrix(self.daltonization_matrix.as_ref(), color)
+  }
+}
+
+// Passes everything on to the wrapped renderer, but maps the colors drawn to the main render target through a filter
+pub struct FilteredRenderer<'a> {
+  renderer: &'a mut dyn Renderer,
+  filter: &'a PreparedFilter,
+}
+
+impl<'a> FilteredRenderer<'a> {
+  pub fn new(renderer: &'a mut dyn Renderer, filter: &'a PreparedFilter) -> Self {
+    FilteredRenderer {
+      renderer,
+      filter,
+    }
+  }
+}
+
+impl<'a> Renderer for FilteredRenderer<'a> {
+  fn render_target_is_enabled(&self, target: RenderTarget) -> bool {
+    self.renderer.render_target_is_enabled(target)
+  }
+
+  fn set_render_target_enabled(&mut self, target: RenderTarget, enabled: bool) {
+    self.renderer.set_render_target_enabled(target, enabled)
+  }
+
+  fn draw_pixel(&mut self, x: usize, y: usize, z: u8, color: Color, target: RenderTarget) {
+    let color = if target == RenderTarget::Main { self.filter.apply(color) } else { color };
+    self.renderer.draw_pixel(x, y, z, color, target)
+  }
+
+  fn flush(&mut self) {
+    self.renderer.flush()
+  }
+
+  fn read_partial_frame(&self) -> Vec<u8> {
+    self.renderer.read_partial_frame()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn prepared_filter_maps_colors_like_the_filter_itself() {
+    let colors = [Color::from_rgb(0x1F, 0, 0), Color::from_rgb(0x04, 0x0C, 0x14), Color::white(), Color::transparent()];
+    [AccessibilityFilter::Protanopia, AccessibilityFilter::Deuteranopia, AccessibilityFilter::Tritanopia, AccessibilityFilter::HighContrast]
+      .into_iter()
+      .for_each(|filter| {
+        let prepared_filter = PreparedFilter::new(filter);
+        colors.iter().for_each(|color| assert_eq!(prepared_filter.apply(*color), filter.apply(*color), "{:?}", filter));
+      });
+  }
+}
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+use crate::input::{Button, InputEvent, InputLog};
+
+pub struct InputRecorder {
+  start_frame: u64,
+  log: InputLog,
+}
+
+impl InputRecorder {
+  pub fn new(start_frame: u64) -> InputRecorder {
+    InputRecorder {
+      start_frame,
+      log: InputLog::default(),
+    }
+  }
+
+  pub fn record(&mut self, frame: u64, button: Button, pressed: bool) {
+    self.log.events.push(InputEvent {
+      frame: frame - self.start_frame,
+      button,
+      pressed,
+    });
+  }
+
+  pub fn finish(self) -> InputLog {
+    self.log
+  }
+}
+
+pub struct InputPlayer {
+  start_frame: u64,
+  events: VecDeque<InputEvent>,
+}
+
+impl InputPlayer {
+  pub fn new(start_frame: u64, log: InputLog) -> InputPlayer {
+    let mut events = log.events;
+    events.sort_by_key(|event| event.frame);
+    InputPlayer {
+      start_frame,
+      events: events.into(),
+    }
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.events.is_empty()
+  }
+
+  // Removes and returns the events that should be applied by the given frame, in the order they were recorded
+  pub fn take_due_events(&mut self, frame: u64) -> Vec<InputEvent> {
+    let relative_frame = frame - self.start_frame;
+    let due_event_count = self.events.iter()
+      .take_while(|event| event.frame <= relative_frame)
+      .count();
+    self.events.drain(..due_event_count).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn recorded_events_are_relative_to_start_frame() {
+    let mut recorder = InputRecorder::new(10);
+    recorder.record(10, Button::A, true);
+    recorder.record(12, Button::A, false);
+    assert_eq!(recorder.finish().events, vec![
+      InputEvent { frame: 0, button: Button::A, pressed: true },
+      InputEvent { frame: 2, button: Button::A, pressed: false },
+    ]);
+  }
+
+  #[test]
+  fn player_returns_events_once_their_frame_is_reached() {
+    let log = InputLog {
+      events: vec![
+        InputEvent { frame: 0, button: Button::START, pressed: true },
+        InputEvent { frame: 2, button: Button::START, pressed: false },
+        InputEvent { frame: 2, button: Button::UP, pressed: true },
+      ]
+    };
+    let mut player = InputPlayer::new(5, log);
+    assert_eq!(player.take_due_events(5), vec![InputEvent { frame: 0, button: Button::START, pressed: true }]);
+    assert!(player.take_due_events(6).is_empty());
+    assert_eq!(player.take_due_events(7).len(), 2);
+    assert!(player.is_finished());
+  }
+}
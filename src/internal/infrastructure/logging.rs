@@ -0,0 +1,137 @@
+use std::cell::RefCell;
+use std::fmt::Arguments;
+
+use crate::logger::{EmulatorLogger, LogLevel};
+
+#[cfg(feature = "log")]
+fn default_logger() -> Box<dyn EmulatorLogger> {
+  Box::new(crate::logger::LogCrateLogger)
+}
+
+#[cfg(not(feature = "log"))]
+fn default_logger() -> Box<dyn EmulatorLogger> {
+  Box::new(crate::logger::NullLogger)
+}
+
+thread_local! {
+  static LOGGER: RefCell<Box<dyn EmulatorLogger>> = RefCell::new(default_logger());
+}
+
+pub fn set_logger(logger: Box<dyn EmulatorLogger>) {
+  LOGGER.with(|current_logger| *current_logger.borrow_mut() = logger);
+}
+
+// Messages are passed as format_args! so they're only formatted when the logger wants the level, which matters for
+// the ones that are logged on every bank switch
+fn log(level: LogLevel, message: Arguments) {
+  LOGGER.with(|logger| {
+    let logger = logger.borrow();
+    if !logger.enabled(level) {
+      return;
+    }
+    let formatted_message;
+    let message = match message.as_str() {
+      Some(message) => message,
+      None => {
+        formatted_message = message.to_string();
+        &formatted_message
+      }
+    };
+    match level {
+      LogLevel::Trace => logger.trace(message),
+      LogLevel::Debug => logger.debug(message),
+      LogLevel::Info => logger.info(message),
+      LogLevel::Warn => logger.warn(message),
+    }
+  });
+}
+
+pub fn trace(message: Arguments) {
+  log(LogLevel::Trace, message);
+}
+
+pub fn debug(message: Arguments) {
+  log(LogLevel::Debug, message);
+}
+
+pub fn info(message: Arguments) {
+  log(LogLevel::Info, message);
+}
+
+pub fn warn(message: Arguments) {
+  log(LogLevel::Warn, message);
+}
+
+#[cfg(test)]
+pub mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::logger::EmulatorLogger;
+
+  #[derive(Clone, Default)]
+  pub struct CapturingLogger {
+    pub messages: Rc<RefCell<Vec<(&'static str, String)>>>,
+  }
+
+  impl CapturingLogger {
+    fn capture(&self, level: &'static str, message: &str) {
+      self.messages.borrow_mut().push((level, String::from(message)));
+    }
+  }
+
+  impl EmulatorLogger for CapturingLogger {
+    fn trace(&self, message: &str) {
+      self.capture("trace", message);
+    }
+
+    fn debug(&self, message: &str) {
+      self.capture("debug", message);
+    }
+
+    fn info(&self, message: &str) {
+      self.capture("info", message);
+    }
+
+    fn warn(&self, message: &str) {
+      self.capture("warn", message);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fmt::{Display, Formatter};
+
+  use crate::logger::{EmulatorLogger, LogLevel};
+
+  use super::*;
+
+  struct PanicsWhenFormatted;
+
+  impl Display for PanicsWhenFormatted {
+    fn fmt(&self, _formatter: &mut Formatter<'_>) -> std::fmt::Result {
+      panic!("Message was formatted for a disabled level")
+    }
+  }
+
+  struct WarningsOnlyLogger;
+
+  impl EmulatorLogger for WarningsOnlyLogger {
+    fn trace(&self, _message: &str) {}
+    fn debug(&self, _message: &str) {}
+    fn info(&self, _message: &str) {}
+    fn warn(&self, _message: &str) {}
+
+    fn enabled(&self, level: LogLevel) -> bool {
+      level == LogLevel::Warn
+    }
+  }
+
+  #[test]
+  fn messages_for_disabled_levels_are_not_formatted() {
+    set_logger(Box::new(WarningsOnlyLogger));
+    trace(format_args!("{}", PanicsWhenFormatted));
+    info(format_args!("{}", PanicsWhenFormatted));
+  }
+}
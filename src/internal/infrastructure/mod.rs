@@ -1 +1,7 @@
 pub mod toggle;
+pub mod rewind_buffer;
+pub mod logging;
+pub mod input_recorder;
+pub mod coverage_tracker;
+pub mod filtered_renderer;
+pub mod signal_tracer;
@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+pub struct RewindBuffer {
+  capacity: usize,
+  interval: u32,
+  frames_since_snapshot: u32,
+  // The most recent snapshot is kept as is. Every older snapshot is stored as a compressed
+  // delta that turns its successor back into it, so we can walk back from the most recent one.
+  latest_snapshot: Option<Vec<u8>>,
+  deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+  pub fn new(frames_back: usize, interval: u32) -> RewindBuffer {
+    let interval = interval.max(1);
+    RewindBuffer {
+      capacity: (frames_back / interval as usize).max(1),
+      interval,
+      frames_since_snapshot: 0,
+      latest_snapshot: None,
+      deltas: VecDeque::new(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.deltas.len() + if self.latest_snapshot.is_some() { 1 } else { 0 }
+  }
+
+  pub fn clear(&mut self) {
+    self.frames_since_snapshot = 0;
+    self.latest_snapshot = None;
+    self.deltas.clear();
+  }
+
+  pub fn frame_completed(&mut self) -> bool {
+    self.frames_since_snapshot += 1;
+    if self.frames_since_snapshot >= self.interval {
+      self.frames_since_snapshot = 0;
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn push(&mut self, snapshot: Vec<u8>) {
+    if let Some(previous_snapshot) = self.latest_snapshot.take() {
+      self.deltas.push_back(RewindBuffer::compress(&RewindBuffer::xor(&previous_snapshot, &snapshot)));
+      if self.deltas.len() >= self.capacity {
+        self.deltas.pop_front();
+      }
+    }
+    self.latest_snapshot = Some(snapshot);
+  }
+
+  pub fn pop(&mut self) -> Option<Vec<u8>> {
+    let snapshot = self.latest_snapshot.take()?;
+    self.latest_snapshot = self.deltas.pop_back()
+      .map(|delta| RewindBuffer::xor(&RewindBuffer::decompress(&delta), &snapshot));
+    self.frames_since_snapshot = 0;
+    Some(snapshot)
+  }
+
+  // The result has the length of the first operand, the second one is padded with zeroes if needed
+  fn xor(first: &[u8], second: &[u8]) -> Vec<u8> {
+    first.iter()
+      .enumerate()
+      .map(|(index, byte)| byte ^ second.get(index).copied().unwrap_or(0))
+      .collect()
+  }
+
+  fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  fn decompress(bytes: &[u8]) -> Vec<u8> {
+    let mut decompressed_bytes: Vec<u8> = Vec::new();
+    ZlibDecoder::new(bytes).read_to_end(&mut decompressed_bytes).unwrap();
+    decompressed_bytes
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pops_snapshots_in_reverse_order() {
+    let mut buffer = RewindBuffer::new(10, 1);
+    buffer.push(vec![1, 2, 3, 4]);
+    buffer.push(vec![1, 2, 5]);
+    buffer.push(vec![7, 2, 5, 4, 9]);
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.pop(), Some(vec![7, 2, 5, 4, 9]));
+    assert_eq!(buffer.pop(), Some(vec![1, 2, 5]));
+    assert_eq!(buffer.pop(), Some(vec![1, 2, 3, 4]));
+    assert_eq!(buffer.pop(), None);
+    assert_eq!(buffer.len(), 0);
+  }
+
+  #[test]
+  fn drops_oldest_snapshot_when_full() {
+    let mut buffer = RewindBuffer::new(6, 2);
+    (0..5u8).for_each(|value| buffer.push(vec![value; 16]));
+    assert_eq!(buffer.len(), 3);
+    assert_eq!(buffer.pop(), Some(vec![4; 16]));
+    assert_eq!(buffer.pop(), Some(vec![3; 16]));
+    assert_eq!(buffer.pop(), Some(vec![2; 16]));
+    assert_eq!(buffer.pop(), None);
+  }
+
+  #[test]
+  fn requests_snapshot_every_interval() {
+    let mut buffer = RewindBuffer::new(6, 3);
+    let requests: Vec<bool> = (0..6).map(|_| buffer.frame_completed()).collect();
+    assert_eq!(requests, vec![false, false, true, false, false, true]);
+  }
+}
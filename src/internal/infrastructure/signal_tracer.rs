@@ -0,0 +1,84 @@
+use crate::debug::SignalEvent;
+use crate::internal::memory::memory::Memory;
+
+struct Probe {
+  address: u16,
+  bit_mask: u8,
+  value: Option<u8>,
+}
+
+// Samples the probed register bits after every machine cycle and logs each change
+#[derive(Default)]
+pub struct SignalTracer {
+  probes: Vec<Probe>,
+  events: Vec<SignalEvent>,
+}
+
+impl SignalTracer {
+  pub fn new() -> SignalTracer {
+    SignalTracer::default()
+  }
+
+  pub fn tracing(&self) -> bool {
+    !self.probes.is_empty()
+  }
+
+  pub fn trace(&mut self, address: u16, bit_mask: u8) {
+    self.probes.push(Probe { address, bit_mask, value: None });
+  }
+
+  pub fn clear(&mut self) {
+    self.probes.clear();
+    self.events.clear();
+  }
+
+  pub fn drain_events(&mut self) -> Vec<SignalEvent> {
+    std::mem::take(&mut self.events)
+  }
+
+  // The first sample of a probe only establishes its starting value
+  pub fn sample(&mut self, machine_cycle: u64, memory: &dyn Memory) {
+    for probe in self.probes.iter_mut() {
+      let value = memory.read(probe.address) & probe.bit_mask;
+      if let Some(previous_value) = probe.value {
+        if previous_value != value {
+          self.events.push(SignalEvent {
+            machine_cycle,
+            address: probe.address,
+            bit_mask: probe.bit_mask,
+            previous_value,
+            value,
+          });
+        }
+      }
+      probe.value = Some(value);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::internal::memory::memory::test::MockMemory;
+
+  use super::*;
+
+  #[test]
+  fn logs_changes_of_masked_bits_only() {
+    let mut tracer = SignalTracer::new();
+    let mut memory = MockMemory::new();
+    tracer.trace(0xFF0F, 0x01);
+    memory.write(0xFF0F, 0xE0);
+    tracer.sample(0, &memory);
+    memory.write(0xFF0F, 0xE2);
+    tracer.sample(1, &memory);
+    memory.write(0xFF0F, 0xE3);
+    tracer.sample(2, &memory);
+    memory.write(0xFF0F, 0xE0);
+    tracer.sample(3, &memory);
+    assert_eq!(tracer.drain_events(), vec![
+      SignalEvent { machine_cycle: 2, address: 0xFF0F, bit_mask: 0x01, previous_value: 0x00, value: 0x01 },
+      SignalEvent { machine_cycle: 3, address: 0xFF0F, bit_mask: 0x01, previous_value: 0x01, value: 0x00 },
+    ]);
+    assert!(tracer.drain_events().is_empty());
+  }
+}
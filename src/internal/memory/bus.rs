@@ -1,3 +1,5 @@
+use crate::internal::infrastructure::logging;
+use crate::internal::memory::control::ControlRegisters;
 use crate::internal::memory::mbc::MBC;
 use crate::internal::memory::memory::Memory;
 
@@ -5,10 +7,10 @@ pub struct MemoryBus<'a> {
   pub rom: &'a mut Box<dyn MBC>,
   pub vram: &'a mut dyn Memory,
   pub wram: &'a mut dyn Memory,
-  pub reserved_area_1: &'a mut dyn Memory,
   pub oam: &'a mut dyn Memory,
   pub reserved_area_2: &'a mut dyn Memory,
   pub button_controller: &'a mut dyn Memory,
+  pub serial: &'a mut dyn Memory,
   pub timer: &'a mut dyn Memory,
   pub interrupt_controller: &'a mut dyn Memory,
   pub speed_controller: &'a mut dyn Memory,
@@ -16,7 +18,7 @@ pub struct MemoryBus<'a> {
   pub lcd: &'a mut dyn Memory,
   pub dma: &'a mut dyn Memory,
   pub cram: &'a mut dyn Memory,
-  pub control_registers: &'a mut dyn Memory,
+  pub control_registers: &'a mut ControlRegisters,
   pub stack: &'a mut dyn Memory,
   pub unmapped_memory: &'a mut dyn Memory
 }
@@ -24,16 +26,17 @@ pub struct MemoryBus<'a> {
 impl<'a> Memory for MemoryBus<'a> {
   fn read(&self, address: u16) -> u8 {
     match address {
-      0x0000..=0x7FFF => self.rom.read(address),
+      0x0000..=0x7FFF => self.control_registers.read_boot_rom(address).unwrap_or_else(|| self.rom.read(address)),
       0x8000..=0x9FFF => self.vram.read(address),
       0xA000..=0xBFFF => self.rom.read(address),
       0xC000..=0xDFFF => self.wram.read(address),
-      0xE000..=0xFDFF => self.reserved_area_1.read(address),
+      // Echo RAM mirrors 0xC000-0xDDFF
+      0xE000..=0xFDFF => self.wram.read(address - 0x2000),
       0xFE00..=0xFE9F => self.oam.read(address),
       0xFEA0..=0xFEA1 => self.interrupt_controller.read(address),
       0xFEA2..=0xFEFF => self.reserved_area_2.read(address),
       0xFF00 => self.button_controller.read(address),
-      0xFF01..=0xFF02 => 0xFF, // TODO: implement serial transfer
+      0xFF01..=0xFF02 => self.serial.read(address),
       0xFF03 => self.unmapped_memory.read(address),
       0xFF04..=0xFF07 => self.timer.read(address),
       0xFF08..=0xFF0E => self.unmapped_memory.read(address),
@@ -73,12 +76,12 @@ impl<'a> Memory for MemoryBus<'a> {
       0x8000..=0x9FFF => self.vram.write(address, value),
       0xA000..=0xBFFF => self.rom.write(address, value),
       0xC000..=0xDFFF => self.wram.write(address, value),
-      0xE000..=0xFDFF => self.reserved_area_1.write(address, value),
+      0xE000..=0xFDFF => self.wram.write(address - 0x2000, value),
       0xFE00..=0xFE9F => self.oam.write(address, value),
       0xFEA0 => self.interrupt_controller.write(address, value),
       0xFEA1..=0xFEFF => self.reserved_area_2.write(address, value),
       0xFF00 => self.button_controller.write(address, value),
-      0xFF01..=0xFF02 => {}, // Serial communication not implemented (yet)
+      0xFF01..=0xFF02 => self.serial.write(address, value),
       0xFF03 => self.unmapped_memory.write(address, value),
       0xFF04..=0xFF07 => self.timer.write(address, value),
       0xFF08..=0xFF0E => self.unmapped_memory.write(address, value),
@@ -94,13 +97,19 @@ impl<'a> Memory for MemoryBus<'a> {
       0xFF46 => self.dma.write(address, value),
       0xFF47..=0xFF49 => self.cram.write(address, value),
       0xFF4A..=0xFF4B => self.lcd.write(address, value),
-      0xFF4C => self.control_registers.write(address, value),
+      0xFF4C => {
+        self.control_registers.write(address, value);
+        self.lcd.write(address, value);
+      }
       0xFF4D => self.speed_controller.write(address, value),
       0xFF4E => self.unmapped_memory.write(address, value),
       0xFF4F => self.vram.write(address, value),
-      0xFF50 => self.control_registers.write(address, value),
+      0xFF50 => {
+        self.control_registers.write(address, value);
+        self.lcd.write(address, value);
+      }
       0xFF51..=0xFF55 => self.dma.write(address, value),
-      0xFF56 => {}, // Infrared communication not implemented (yet)
+      0xFF56 => logging::debug(format_args!("Ignoring write to infrared port, infrared communication is not supported")),
       0xFF57..=0xFF67 => self.unmapped_memory.write(address, value),
       0xFF68..=0xFF6B => self.cram.write(address, value),
       0xFF6C => self.lcd.write(address, value),
@@ -6,6 +6,9 @@ use crate::internal::memory::memory::Memory;
 pub struct ControlRegisters {
   key0: u8,
   bank: u8,
+  // Save states don't include the boot ROM, the emulator loads it again after restoring one
+  #[serde(skip)]
+  boot_rom: Vec<u8>,
 }
 
 impl ControlRegisters {
@@ -13,8 +16,22 @@ impl ControlRegisters {
     ControlRegisters {
       key0: 0,
       bank: 0,
+      boot_rom: Vec::new(),
     }
   }
+
+  pub fn load_boot_rom(&mut self, boot_rom: &[u8]) {
+    self.boot_rom = boot_rom.to_vec();
+  }
+
+  // Until BANK is written, the boot ROM is mapped over 0x0000-0x00FF and, for the 2KB CGB boot ROM, 0x0200-0x08FF.
+  // The cartridge header at 0x0100-0x01FF always stays visible.
+  pub fn read_boot_rom(&self, address: u16) -> Option<u8> {
+    if self.bank != 0 || (0x0100..0x0200).contains(&address) || address > 0x08FF {
+      return None;
+    }
+    self.boot_rom.get(address as usize).copied()
+  }
 }
 
 impl Memory for ControlRegisters {
@@ -28,9 +45,62 @@ impl Memory for ControlRegisters {
 
   fn write(&mut self, address: u16, value: u8) {
     match address {
-      0xFF4C => self.key0 = value,
-      0xFF50 => self.bank = value,
+      // Only the boot ROM can select the CGB or DMG compatibility mode
+      0xFF4C => if self.bank == 0 {
+        self.key0 = value
+      },
+      0xFF50 => {
+        // Once the boot ROM has been unmapped, it can't be mapped back in
+        if self.bank == 0 {
+          self.bank = value;
+        }
+      }
       _ => panic!("Can't write to control register at address {}", address)
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::internal::memory::memory::MemoryAddress;
+
+  use super::*;
+
+  #[test]
+  fn bank_register_is_latched_after_unmapping_boot_rom() {
+    let mut control_registers = ControlRegisters::new();
+    control_registers.write(MemoryAddress::BANK, 0x00);
+    assert_eq!(control_registers.read(MemoryAddress::BANK), 0x00);
+    control_registers.write(MemoryAddress::BANK, 0x11);
+    assert_eq!(control_registers.read(MemoryAddress::BANK), 0x11);
+    control_registers.write(MemoryAddress::BANK, 0x00);
+    assert_eq!(control_registers.read(MemoryAddress::BANK), 0x11);
+  }
+
+  #[test]
+  fn boot_rom_is_mapped_until_bank_is_written() {
+    let mut control_registers = ControlRegisters::new();
+    assert_eq!(control_registers.read_boot_rom(0x0000), None);
+    control_registers.load_boot_rom(&[0x31; 0x100]);
+    assert_eq!(control_registers.read_boot_rom(0x0000), Some(0x31));
+    assert_eq!(control_registers.read_boot_rom(0x00FF), Some(0x31));
+    // A DMG boot ROM doesn't cover the CGB boot ROM's second part
+    assert_eq!(control_registers.read_boot_rom(0x0200), None);
+    control_registers.load_boot_rom(&[0x31; 0x1000]);
+    assert_eq!(control_registers.read_boot_rom(0x08FF), Some(0x31));
+    // Anything past the CGB boot ROM's 2304 bytes is never mapped
+    assert_eq!(control_registers.read_boot_rom(0x0900), None);
+    control_registers.write(MemoryAddress::BANK, 0x01);
+    assert_eq!(control_registers.read_boot_rom(0x0000), None);
+  }
+
+  #[test]
+  fn key0_is_locked_after_unmapping_boot_rom() {
+    let mut control_registers = ControlRegisters::new();
+    control_registers.write(MemoryAddress::KEY0, 0x04);
+    assert_eq!(control_registers.read(MemoryAddress::KEY0), 0x04);
+    control_registers.write(MemoryAddress::BANK, 0x11);
+    control_registers.write(MemoryAddress::KEY0, 0x80);
+    assert_eq!(control_registers.read(MemoryAddress::KEY0), 0x04);
+  }
+}
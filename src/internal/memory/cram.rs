@@ -3,6 +3,7 @@ use mockall::automock;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 
+use crate::internal::controllers::lcd::{LCDController, LCDMode};
 use crate::internal::util::compatibility_palette::CompatibilityPalettes;
 use crate::internal::memory::memory::{Memory, MemoryAddress};
 use crate::renderer::Color;
@@ -20,6 +21,7 @@ pub struct ColorReference {
 
 #[automock]
 pub trait CRAM {
+  fn tick(&mut self, lcd: &dyn LCDController);
   fn write_compatibility_palettes(&mut self, compatibility_palettes: CompatibilityPalettes);
   fn monochrome_background_color(&self, color_ref: ColorReference) -> Color;
   fn background_color(&self, color_ref: ColorReference) -> Color;
@@ -39,6 +41,7 @@ pub struct CRAMImpl {
   object_palette_index: u8,
   #[serde_as(as = "[_;64]")]
   object_palettes: [u8; 2 * COLORS_PER_PALETTE * NUMBER_OF_PALETTES],
+  palettes_locked: bool,
 }
 
 impl CRAMImpl {
@@ -51,22 +54,38 @@ impl CRAMImpl {
       background_palettes: [0; 2 * COLORS_PER_PALETTE * NUMBER_OF_PALETTES],
       object_palette_index: 0,
       object_palettes: [0; 2 * COLORS_PER_PALETTE * NUMBER_OF_PALETTES],
+      palettes_locked: false,
     }
   }
+
+  // Sets the four colors of a palette, even while the palettes are locked and without touching BCPS or OCPS
+  #[cfg(test)]
+  pub fn write_raw_palette(&mut self, object_palette: bool, palette_index: u8, color_words: [u16; 4]) {
+    let palettes = if object_palette { &mut self.object_palettes } else { &mut self.background_palettes };
+    color_words.into_iter().enumerate().for_each(|(color_index, color_word)| {
+      (&mut palettes[(8 * palette_index as usize + 2 * color_index)..]).write_u16::<LittleEndian>(color_word).unwrap();
+    });
+  }
 }
 
 impl CRAM for CRAMImpl {
+  fn tick(&mut self, lcd: &dyn LCDController) {
+    // The PPU has exclusive access to the color palettes while it's drawing a line. Turning the LCD off leaves the
+    // mode as it was, but the palettes are free again.
+    self.palettes_locked = lcd.lcd_enabled() && lcd.get_mode() == LCDMode::Mode3;
+  }
+
   fn write_compatibility_palettes(&mut self, compatibility_palettes: CompatibilityPalettes) {
     compatibility_palettes.bgp.into_iter()
       .enumerate()
       .for_each(|(color_index, color)| {
-        (&mut self.background_palettes[(2 * color_index)..]).write_u16::<LittleEndian>(color.to_word()).unwrap();
+        (&mut self.background_palettes[(2 * color_index)..]).write_u16::<LittleEndian>(color.to_rgb555()).unwrap();
       });
     compatibility_palettes.obj0.into_iter()
       .chain(compatibility_palettes.obj1.into_iter())
       .enumerate()
       .for_each(|(color_index, color)| {
-        (&mut self.object_palettes[(2 * color_index)..]).write_u16::<LittleEndian>(color.to_word()).unwrap();
+        (&mut self.object_palettes[(2 * color_index)..]).write_u16::<LittleEndian>(color.to_rgb555()).unwrap();
       });
   }
 
@@ -82,7 +101,7 @@ impl CRAM for CRAMImpl {
   fn background_color(&self, color_ref: ColorReference) -> Color {
     let lower_byte_address = (8 * color_ref.palette_index + 2 * color_ref.color_index) as usize;
     let color_word = (&self.background_palettes[lower_byte_address..=lower_byte_address + 1]).read_u16::<LittleEndian>().unwrap();
-    Color::from_word(color_word)
+    Color::from_rgb555(color_word)
   }
 
   fn monochrome_object_color(&self, color_ref: ColorReference) -> Color {
@@ -92,7 +111,7 @@ impl CRAM for CRAMImpl {
     let real_color_index = (if color_ref.palette_index == 0 { self.monochrome_object_palette_0 } else { self.monochrome_object_palette_1 } >> (2 * color_ref.color_index)) & 0x3;
     let lower_byte_address = (8 * color_ref.palette_index + 2 * real_color_index) as usize;
     let color_word = (&self.object_palettes[lower_byte_address..=lower_byte_address + 1]).read_u16::<LittleEndian>().unwrap();
-    Color::from_word(color_word)
+    Color::from_rgb555(color_word)
   }
 
   fn object_color(&self, color_ref: ColorReference) -> Color {
@@ -101,7 +120,7 @@ impl CRAM for CRAMImpl {
     }
     let lower_byte_address = (8 * color_ref.palette_index + 2 * color_ref.color_index) as usize;
     let color_word = (&self.object_palettes[lower_byte_address..=lower_byte_address + 1]).read_u16::<LittleEndian>().unwrap();
-    Color::from_word(color_word)
+    Color::from_rgb555(color_word)
   }
 }
 
@@ -126,7 +145,9 @@ impl Memory for CRAMImpl {
       MemoryAddress::OBP1 => self.monochrome_object_palette_1 = value,
       MemoryAddress::BCPS => self.background_palette_index = value & 0xBF,
       MemoryAddress::BCPD => {
-        self.background_palettes[(self.background_palette_index & 0x3F) as usize] = value;
+        if !self.palettes_locked {
+          self.background_palettes[(self.background_palette_index & 0x3F) as usize] = value;
+        }
         if self.background_palette_index.get_bit(7) { // Auto-increment bcps
           // By clearing bit 6 (which is unused) after increment,
           // we prevent incrementing into the higher bits and allow the index to wrap back to 0
@@ -135,7 +156,9 @@ impl Memory for CRAMImpl {
       }
       MemoryAddress::OCPS => self.object_palette_index = value & 0xBF,
       MemoryAddress::OCPD => {
-        self.object_palettes[(self.object_palette_index & 0x3F) as usize] = value;
+        if !self.palettes_locked {
+          self.object_palettes[(self.object_palette_index & 0x3F) as usize] = value;
+        }
         if self.object_palette_index.get_bit(7) { // Auto-increment ocps
           // By clearing bit 6 (which is unused) after increment,
           // we prevent incrementing into the higher bits and allow the index to wrap back to 0
@@ -151,8 +174,27 @@ impl Memory for CRAMImpl {
 mod tests {
   use test_case::test_case;
 
+  use crate::internal::controllers::lcd::{LCDControllerImpl, MockLCDController};
+
   use super::*;
 
+  #[test_case(0x0000, 0x00, 0x00, 0x00; "black")]
+  #[test_case(0x7FFF, 0x1F, 0x1F, 0x1F; "white")]
+  #[test_case(0x001F, 0x1F, 0x00, 0x00; "red")]
+  #[test_case(0x03E0, 0x00, 0x1F, 0x00; "green")]
+  #[test_case(0x7C00, 0x00, 0x00, 0x1F; "blue")]
+  #[test_case(0x2B54, 0x14, 0x1A, 0x0A; "mixed")]
+  fn rgb555_round_trips_and_matches_palette_lookup(value: u16, red: u8, green: u8, blue: u8) {
+    let color = Color::from_rgb555(value);
+    assert_eq!(color, Color::from_rgb(red, green, blue));
+    assert_eq!(color.to_rgb555(), value);
+    let mut cram = CRAMImpl::new();
+    cram.write(MemoryAddress::BCPS, 0x82);
+    cram.write(MemoryAddress::BCPD, value as u8);
+    cram.write(MemoryAddress::BCPD, (value >> 8) as u8);
+    assert_eq!(cram.background_color(ColorReference { color_index: 1, palette_index: 0, foreground: false }), color);
+  }
+
   #[test_case(0xFF68, 0xFF69; "background color")]
   #[test_case(0xFF6A, 0xFF6B; "object color")]
   fn writes_color_to_correct_location(index_address: u16, data_address: u16) {
@@ -180,6 +222,52 @@ mod tests {
     assert_eq!(cram.read(data_address), 0x2B);
   }
 
+  #[test_case(0xFF68, 0xFF69; "background color")]
+  #[test_case(0xFF6A, 0xFF6B; "object color")]
+  fn auto_increment_fills_successive_palette_entries(index_address: u16, data_address: u16) {
+    let mut cram = CRAMImpl::new();
+    cram.write(index_address, 0x80);
+    (0..64u8).for_each(|value| cram.write(data_address, value));
+    assert_eq!(cram.read(index_address), 0x80); // Index wrapped back around to 0
+    (0..64u8).for_each(|index| {
+      cram.write(index_address, index);
+      assert_eq!(cram.read(data_address), index);
+    });
+  }
+
+  #[test_case(0xFF68, 0xFF69; "background color")]
+  #[test_case(0xFF6A, 0xFF6B; "object color")]
+  fn ignores_palette_writes_during_mode_3(index_address: u16, data_address: u16) {
+    let mut cram = CRAMImpl::new();
+    let mut lcd = MockLCDController::new();
+    lcd.expect_lcd_enabled().return_const(true);
+    lcd.expect_get_mode().once().return_const(LCDMode::Mode3);
+    lcd.expect_get_mode().once().return_const(LCDMode::HBlank);
+    cram.write(index_address, 0x82);
+    cram.tick(&lcd);
+    cram.write(data_address, 0xD5);
+    assert_eq!(cram.read(index_address), 0x83); // Index is still incremented
+    cram.tick(&lcd);
+    cram.write(data_address, 0x2B);
+    cram.write(index_address, 0x02);
+    assert_eq!(cram.read(data_address), 0x00);
+    cram.write(index_address, 0x03);
+    assert_eq!(cram.read(data_address), 0x2B);
+  }
+
+  #[test]
+  fn accepts_palette_writes_after_lcd_is_turned_off_during_mode_3() {
+    let mut cram = CRAMImpl::new();
+    let mut lcd = LCDControllerImpl::new();
+    lcd.force_mode(LCDMode::Mode3, 10);
+    lcd.write(MemoryAddress::LCDC, 0x11);
+    cram.tick(&lcd);
+    cram.write(0xFF68, 0x80);
+    cram.write(0xFF69, 0xD5);
+    cram.write(0xFF68, 0x00);
+    assert_eq!(cram.read(0xFF69), 0xD5);
+  }
+
   #[test]
   fn get_background_color_returns_correct_color() {
     let mut cram = CRAMImpl::new();
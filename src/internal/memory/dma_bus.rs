@@ -15,7 +15,8 @@ impl<'a> Memory for DMAMemoryBus<'a> {
       0x8000..=0x9FFF => self.vram.read(address),
       0xA000..=0xBFFF => self.rom.read(address),
       0xC000..=0xDFFF => self.wram.read(address),
-      _ => panic!("DMABus can't read from address {}", address)
+      // OAM DMA sources from 0xE000 onwards read from work RAM, like echo RAM does
+      0xE000..=0xFFFF => self.wram.read(address - 0x2000),
     }
   }
 
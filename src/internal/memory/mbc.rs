@@ -39,9 +39,53 @@ pub trait MBC: Memory + Loadable {
     CGBMode::from_byte(self.read(0x0143))
   }
 
+  // The ROM bank that's mapped to the given address in the 0x0000-0x7FFF range
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { 1 }
+  }
+
+  // The RAM bank that's mapped to 0xA000-0xBFFF
+  fn ram_bank(&self) -> usize {
+    0
+  }
+
+  // The ROM bank that's mapped to 0x4000-0x7FFF, for display in a frontend
+  fn current_rom_bank(&self) -> u16 {
+    self.rom_bank(0x4000) as u16
+  }
+
+  // The RAM bank that's mapped to 0xA000-0xBFFF, or None while an RTC register is mapped there instead
+  fn current_ram_bank(&self) -> Option<u8> {
+    match self.rtc_register() {
+      Some(_) => None,
+      None => Some(self.ram_bank() as u8)
+    }
+  }
+
+  // The RTC register that's mapped to 0xA000-0xBFFF, if any
+  fn rtc_register(&self) -> Option<u8> {
+    None
+  }
+
   fn tick(&mut self, _double_speed: bool) {
 
   }
+
+  fn ram(&self) -> &[u8] {
+    &[]
+  }
+
+  fn load_ram(&mut self, _bytes: &[u8]) {
+
+  }
+
+  fn rtc_footer(&self, _unix_timestamp: u64) -> Option<Vec<u8>> {
+    None
+  }
+
+  fn load_rtc_footer(&mut self, _footer: &[u8]) {
+
+  }
 }
 
 mock! {
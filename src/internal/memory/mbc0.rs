@@ -1,4 +1,4 @@
-use log::info;
+use crate::internal::infrastructure::logging;
 use crate::internal::memory::mbc::{Loadable, MBC};
 use crate::internal::memory::memory::Memory;
 use crate::memory::ROMSize;
@@ -11,7 +11,7 @@ impl MBC for MBC0 {}
 
 impl MBC0 {
     pub fn new(rom_size: ROMSize) -> MBC0 {
-        info!("Loading new MBC0 cartridge of size {:?}", rom_size);
+        logging::info(format_args!("Loading new MBC0 cartridge of size {:?}", rom_size));
         MBC0 {
             rom: vec![0; rom_size.bytes()],
         }
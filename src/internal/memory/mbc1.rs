@@ -1,4 +1,4 @@
-use log::info;
+use crate::internal::infrastructure::logging;
 use crate::internal::memory::mbc::{Loadable, MBC};
 use crate::internal::memory::memory::Memory;
 use crate::memory::{RAMSize, ROMSize};
@@ -12,11 +12,34 @@ pub struct MBC1 {
   ram: Vec<u8>,
 }
 
-impl MBC for MBC1 {}
+impl MBC for MBC1 {
+  fn rom_bank(&self, address: u16) -> usize {
+    let bank = if address < 0x4000 {
+      if self.upper_bank_address_enabled { self.upper_bank_address << 5 } else { 0 }
+    } else {
+      self.lower_bank_address | (self.upper_bank_address << 5)
+    };
+    bank % (self.rom.len() >> 14)
+  }
+
+  fn ram_bank(&self) -> usize {
+    let bank = if self.upper_bank_address_enabled { self.upper_bank_address } else { 0 };
+    bank % (self.ram.len() >> 13).max(1)
+  }
+
+  fn ram(&self) -> &[u8] {
+    &self.ram
+  }
+
+  fn load_ram(&mut self, bytes: &[u8]) {
+    let length = self.ram.len().min(bytes.len());
+    self.ram[..length].copy_from_slice(&bytes[..length]);
+  }
+}
 
 impl MBC1 {
   pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC1 {
-    info!("Loading new MBC1 cartridge with ROM size {:?} and RAM size {:?}", rom_size, ram_size);
+    logging::info(format_args!("Loading new MBC1 cartridge with ROM size {:?} and RAM size {:?}", rom_size, ram_size));
     MBC1 {
       ram_enabled: false,
       upper_bank_address_enabled: false,
@@ -50,8 +73,11 @@ impl Memory for MBC1 {
         self.rom[address_in_rom % self.rom.len()]
       }
       0xA000..=0xBFFF => {
+        if !self.ram_enabled || self.ram.is_empty() {
+          return 0xFF;
+        }
         let address_in_ram = ((address as usize) & 0x1FFF) | (if self.upper_bank_address_enabled { self.upper_bank_address << 13 } else { 0 });
-        self.ram[address_in_ram]
+        self.ram[address_in_ram % self.ram.len()]
       }
       _ => panic!("Can't read from address {:#06x} on MBC1", address)
     }
@@ -67,6 +93,7 @@ impl Memory for MBC1 {
         if self.lower_bank_address == 0 {
           self.lower_bank_address = 1;
         }
+        logging::trace(format_args!("Switching MBC1 to lower ROM bank {}", self.lower_bank_address));
       }
       0x4000..=0x5FFF => {
         self.upper_bank_address = (value & 0x03) as usize;
@@ -75,9 +102,10 @@ impl Memory for MBC1 {
         self.upper_bank_address_enabled = (value & 0x01) == 0x01;
       }
       0xA000..=0xBFFF => {
-        if self.ram_enabled {
+        if self.ram_enabled && !self.ram.is_empty() {
           let address_in_ram = ((address as usize) & 0x1FFF) | (if self.upper_bank_address_enabled { self.upper_bank_address << 13 } else { 0 });
-          self.ram[address_in_ram] = value;
+          let ram_length = self.ram.len();
+          self.ram[address_in_ram % ram_length] = value;
         }
       }
       _ => panic!("Can't write to address {:#06x} on MBC1", address)
@@ -119,6 +147,17 @@ mod tests {
     assert_eq!(memory.read(0xB456), 0xCD);
   }
 
+  #[test]
+  fn disabled_ram_reads_open_bus() {
+    let mut memory = MBC1::new(ROMSize::MB8, RAMSize::KB32);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    memory.write(0xA123, 0xAB);
+    memory.write(0x0000, 0x00); // Disable RAM
+    assert_eq!(memory.read(0xA123), 0xFF);
+    memory.write(0x0000, 0x0A); // Re-enable RAM
+    assert_eq!(memory.read(0xA123), 0xAB);
+  }
+
   #[test]
   fn read_lower_rom_without_upper_bank_address() {
     let mut memory = MBC1::new(ROMSize::MB8, RAMSize::KB32);
@@ -165,4 +204,51 @@ mod tests {
     memory.write(0x4000, 0x2); // Set upper bank address to 2
     assert_eq!(memory.read(0x72A7), 0xAB);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn bank_0_check_applies_to_masked_lower_bank_address() {
+    let mut memory = MBC1::new(ROMSize::KB512, RAMSize::KB32);
+    memory.load_byte(0x4000, 0x12); // Bank 1
+    memory.write(0x2000, 0x20); // Only the lower 5 bits are used, so this selects bank 0 and maps bank 1
+    assert_eq!(memory.read(0x4000), 0x12);
+  }
+
+  #[test]
+  fn current_banks_depend_on_banking_mode() {
+    let mut memory = MBC1::new(ROMSize::MB2, RAMSize::KB32);
+    memory.load_byte(0x114000, 0x12); // Bank 0x45
+    memory.write(0x2000, 0x05);
+    memory.write(0x4000, 0x02);
+    // The upper bits always apply to 0x4000-0x7FFF, but only select the RAM bank in mode 1
+    assert_eq!(memory.current_rom_bank(), 0x45);
+    assert_eq!(memory.current_ram_bank(), Some(0));
+    assert_eq!(memory.read(0x4000), 0x12);
+    memory.write(0x6000, 0x01);
+    assert_eq!(memory.current_rom_bank(), 0x45);
+    assert_eq!(memory.current_ram_bank(), Some(2));
+    assert_eq!(memory.read(0x4000), 0x12);
+  }
+
+  #[test]
+  fn out_of_range_ram_bank_mirrors_small_ram() {
+    let mut memory = MBC1::new(ROMSize::MB8, RAMSize::KB8);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    memory.write(0xA123, 0xAB);
+    memory.write(0x6000, 0x01); // Enable upper bank address
+    memory.write(0x4000, 0x03); // Switch to bank 3, which doesn't exist
+    assert_eq!(memory.read(0xA123), 0xAB);
+    memory.write(0xA123, 0xCD);
+    memory.write(0x4000, 0x00);
+    assert_eq!(memory.read(0xA123), 0xCD);
+  }
+
+  #[test]
+  fn missing_ram_reads_open_bus() {
+    let mut memory = MBC1::new(ROMSize::MB8, RAMSize::Unavailable);
+    memory.write(0x0000, 0x0A); // Enable RAM
+    memory.write(0x6000, 0x01);
+    memory.write(0x4000, 0x02);
+    memory.write(0xA123, 0xAB);
+    assert_eq!(memory.read(0xA123), 0xFF);
+  }
+}
@@ -1,4 +1,4 @@
-use log::info;
+use crate::internal::infrastructure::logging;
 use crate::internal::memory::mbc::{Loadable, MBC};
 use crate::internal::memory::memory::Memory;
 use crate::internal::util::bit_util::BitUtil;
@@ -11,11 +11,24 @@ pub struct MBC2 {
   ram: Vec<u8>,
 }
 
-impl MBC for MBC2 {}
+impl MBC for MBC2 {
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { self.bank_address % (self.rom.len() >> 14) }
+  }
+
+  fn ram(&self) -> &[u8] {
+    &self.ram
+  }
+
+  fn load_ram(&mut self, bytes: &[u8]) {
+    let length = self.ram.len().min(bytes.len());
+    self.ram[..length].copy_from_slice(&bytes[..length]);
+  }
+}
 
 impl MBC2 {
   pub fn new(rom_size: ROMSize) -> MBC2 {
-    info!("Loading new MBC2 cartridge with ROM size {:?}", rom_size);
+    logging::info(format_args!("Loading new MBC2 cartridge with ROM size {:?}", rom_size));
 
     MBC2 {
       ram_enabled: false,
@@ -34,9 +47,12 @@ impl Memory for MBC2 {
       },
       0x4000..=0x7FFF => {
         let address_in_rom = ((address as usize) & 0x3FFF) | (self.bank_address << 14);
-        self.rom[address_in_rom]
+        self.rom[address_in_rom % self.rom.len()]
       },
       0xA000..=0xBFFF => {
+        if !self.ram_enabled {
+          return 0xFF;
+        }
         let address_in_ram = (address as usize) & 0x1FF;
         self.ram[address_in_ram]
       },
@@ -48,17 +64,21 @@ impl Memory for MBC2 {
     match address {
       0x0000..=0x3FFF => {
         if address.get_bit(8) {
-          self.bank_address = (value & 0x1F) as usize;
+          // MBC2 only has 4 bank bits, so it can address up to 256KB of ROM
+          self.bank_address = (value & 0x0F) as usize;
           if self.bank_address == 0 {
             self.bank_address = 1;
           }
+          logging::trace(format_args!("Switching MBC2 to ROM bank {}", self.bank_address));
         } else {
           self.ram_enabled = (value & 0x0F) == 0x0A;
         }
       },
       0xA000..=0xBFFF => {
-        let address_in_ram = (address as usize) & 0x1FF;
-        self.ram[address_in_ram] = value;
+        if self.ram_enabled {
+          let address_in_ram = (address as usize) & 0x1FF;
+          self.ram[address_in_ram] = value;
+        }
       },
       _ => panic!("Can't write to address {:#06x} on MBC2", address)
     };
@@ -105,6 +125,18 @@ mod tests {
     assert_eq_hex!(memory.read(0xA1FF), 0xEF);
   }
 
+  #[test]
+  fn disabled_ram_ignores_writes_and_reads_open_bus() {
+    let mut memory = MBC2::new(ROMSize::KB256);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0xA000, 0xAB);
+    memory.write(0x0000, 0x0); // Disable RAM
+    memory.write(0xA000, 0xCD);
+    assert_eq_hex!(memory.read(0xA000), 0xFF);
+    memory.write(0x0000, 0xA); // Re-enable RAM
+    assert_eq_hex!(memory.read(0xA000), 0xAB);
+  }
+
   #[test]
   fn read_lower_rom() {
     let mut memory = MBC2::new(ROMSize::KB256);
@@ -146,4 +178,17 @@ mod tests {
     assert_eq_hex!(memory.read(0x7FFF), 0x56);
 
   }
+
+  #[test]
+  fn bank_address_ignores_upper_bits_and_wraps_to_rom_size() {
+    let mut memory = MBC2::new(ROMSize::KB64);
+    memory.load_byte(0x4000, 0x12); // Bank 1
+    memory.load_byte(0x8000, 0x34); // Bank 2
+    memory.write(0x0100, 0x11); // Only the lower 4 bits select the bank
+    assert_eq_hex!(memory.read(0x4000), 0x12);
+    assert_eq!(memory.rom_bank(0x4000), 1);
+    memory.write(0x0100, 0x06); // Only 4 banks, so bank 6 mirrors bank 2
+    assert_eq_hex!(memory.read(0x4000), 0x34);
+    assert_eq!(memory.rom_bank(0x4000), 2);
+  }
 }
\ No newline at end of file
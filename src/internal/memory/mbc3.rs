@@ -1,6 +1,8 @@
 use std::cell::{RefCell, RefMut};
-use log::info;
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::internal::infrastructure::logging;
 use crate::internal::memory::mbc::{Loadable, MBC};
 use crate::internal::memory::memory::Memory;
 use crate::internal::util::bit_util::{BitUtil, WordUtil};
@@ -145,15 +147,79 @@ pub struct MBC3 {
 }
 
 impl MBC for MBC3 {
+    fn rom_bank(&self, address: u16) -> usize {
+        if address < 0x4000 { 0 } else { self.rom_bank_address % (self.rom.len() >> 14) }
+    }
+
+    // Bank addresses 0x08-0x0C select an RTC register instead of a RAM bank
+    fn ram_bank(&self) -> usize {
+        match self.ram_bank_address {
+            0x0..=0x7 => self.ram_bank_address % (self.ram.len() >> 13).max(1),
+            _ => self.ram_bank_address
+        }
+    }
+
+    fn rtc_register(&self) -> Option<u8> {
+        match self.ram_bank_address {
+            0x8..=0xC => Some(self.ram_bank_address as u8),
+            _ => None
+        }
+    }
+
     fn tick(&mut self, double_speed: bool) {
         let passed_nanoseconds = if double_speed { 500 } else { 1000 };
         self.rtc.tick(passed_nanoseconds);
     }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, bytes: &[u8]) {
+        let length = self.ram.len().min(bytes.len());
+        self.ram[..length].copy_from_slice(&bytes[..length]);
+    }
+
+    // The footer holds the current time registers followed by the latched ones, each stored as a 32 bit
+    // little endian word, and a 64 bit timestamp of when the file was written.
+    fn rtc_footer(&self, unix_timestamp: u64) -> Option<Vec<u8>> {
+        let mut footer: Vec<u8> = Vec::with_capacity(MBC3::RTC_FOOTER_SIZE);
+        for rtc in [&self.rtc, &self.rtc_registers] {
+            let formatted_rtc = *rtc.get_formatted_rtc();
+            for value in [formatted_rtc.seconds, formatted_rtc.minutes, formatted_rtc.hours, formatted_rtc.days_low, formatted_rtc.days_high] {
+                footer.write_u32::<LittleEndian>(value as u32).unwrap();
+            }
+        }
+        footer.write_u64::<LittleEndian>(unix_timestamp).unwrap();
+        Some(footer)
+    }
+
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        // Some emulators write a 32 bit timestamp, which we ignore anyway
+        if footer.len() < MBC3::RTC_FOOTER_SIZE - 4 {
+            return;
+        }
+        let mut reader = footer;
+        let mut read_formatted_rtc = || FormattedRTC {
+            nanoseconds: 0,
+            seconds: reader.read_u32::<LittleEndian>().unwrap() as u8,
+            minutes: reader.read_u32::<LittleEndian>().unwrap() as u8,
+            hours: reader.read_u32::<LittleEndian>().unwrap() as u8,
+            days_low: reader.read_u32::<LittleEndian>().unwrap() as u8,
+            days_high: reader.read_u32::<LittleEndian>().unwrap() as u8,
+        };
+        let rtc = read_formatted_rtc();
+        let rtc_registers = read_formatted_rtc();
+        self.rtc.update_from_formatted_rtc(rtc);
+        self.rtc_registers.update_from_formatted_rtc(rtc_registers);
+    }
 }
 
 impl MBC3 {
+    const RTC_FOOTER_SIZE: usize = 48;
+
     pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC3 {
-        info!("Loading new MBC3 cartridge with ROM size {:?} and RAM size {:?}", rom_size, ram_size);
+        logging::info(format_args!("Loading new MBC3 cartridge with ROM size {:?} and RAM size {:?}", rom_size, ram_size));
         MBC3 {
             rtc: RTC::new(),
             rtc_registers: RTC::new(),
@@ -179,13 +245,17 @@ impl Memory for MBC3 {
             }
             0x4000..=0x7FFF => {
                 let address_in_rom = ((address as usize) & 0x3FFF) | (self.rom_bank_address << 14);
-                self.rom[address_in_rom]
+                self.rom[address_in_rom % self.rom.len()]
             }
             0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
                 match self.ram_bank_address {
+                    0x0..=0x7 if self.ram.is_empty() => 0xFF,
                     0x0..=0x7 => {
                         let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
-                        self.ram[address_in_ram]
+                        self.ram[address_in_ram % self.ram.len()]
                     }
                     0x8 => self.rtc_registers.get_formatted_rtc().seconds,
                     0x9 => self.rtc_registers.get_formatted_rtc().minutes,
@@ -205,10 +275,12 @@ impl Memory for MBC3 {
                 self.ram_enabled = (value & 0x0F) == 0x0A;
             }
             0x2000..=0x3FFF => {
-                self.rom_bank_address = value as usize;
+                // The bank number is 7 bits wide, and bank 0 is remapped to bank 1 after masking
+                self.rom_bank_address = (value & 0x7F) as usize;
                 if self.rom_bank_address == 0 {
                     self.rom_bank_address = 1;
                 }
+                logging::trace(format_args!("Switching MBC3 to ROM bank {}", self.rom_bank_address));
             }
             0x4000..=0x5FFF if value <= 0x0C => {
                 self.ram_bank_address = (value & 0x0F) as usize;
@@ -223,9 +295,11 @@ impl Memory for MBC3 {
             0xA000..=0xBFFF => {
                 if self.ram_enabled {
                     match self.ram_bank_address {
+                        0x0..=0x7 if self.ram.is_empty() => {}
                         0x0..=0x7 => {
                             let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
-                            self.ram[address_in_ram] = value;
+                            let ram_length = self.ram.len();
+                            self.ram[address_in_ram % ram_length] = value;
                         }
                         0x8 => {
                             self.rtc_registers.set_seconds(value);
@@ -291,6 +365,21 @@ mod tests {
         memory.write(0xA080, 0xAB);
         memory.write(0x0000, 0xB); // Disable RAM
         memory.write(0xA080, 0xCD);
+        memory.write(0x0000, 0xA); // Re-enable RAM
+        assert_eq_hex!(memory.read(0xA080), 0xAB);
+    }
+
+    #[test]
+    fn disabled_ram_reads_open_bus() {
+        let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+        memory.write(0x0000, 0xA); // Enable RAM
+        memory.write(0xA080, 0xAB);
+        memory.write(0x0000, 0x0); // Disable RAM
+        assert_eq_hex!(memory.read(0xA080), 0xFF);
+        memory.write(0x4000, 0x08); // Select the RTC seconds register
+        assert_eq_hex!(memory.read(0xA000), 0xFF);
+        memory.write(0x4000, 0x00);
+        memory.write(0x0000, 0xA); // Re-enable RAM
         assert_eq_hex!(memory.read(0xA080), 0xAB);
     }
 
@@ -336,6 +425,34 @@ mod tests {
         assert_eq_hex!(memory.read(0x7FFF), 0x56);
     }
 
+    #[test]
+    fn rom_bank_address_ignores_highest_bit() {
+        let mut memory = MBC3::new(ROMSize::MB2, RAMSize::KB32);
+        memory.load_byte(0x4000, 0x12); // Bank 1
+        memory.load_byte(0x14000, 0x34); // Bank 5
+        memory.write(0x2000, 0x85);
+        assert_eq_hex!(memory.read(0x4000), 0x34);
+        memory.write(0x2000, 0x80); // Masks to bank 0, which selects bank 1
+        assert_eq_hex!(memory.read(0x4000), 0x12);
+    }
+
+    #[test]
+    fn current_banks_match_selected_banks() {
+        let mut memory = MBC3::new(ROMSize::MB2, RAMSize::KB32);
+        memory.load_byte(0x5C000, 0x12); // Bank 0x17
+        memory.write(0x2000, 0x17);
+        memory.write(0x4000, 0x02);
+        assert_eq!(memory.current_rom_bank(), 0x17);
+        assert_eq!(memory.current_ram_bank(), Some(0x02));
+        assert_eq!(memory.rtc_register(), None);
+        assert_eq_hex!(memory.read(0x4000), 0x12);
+        memory.write(0x4000, 0x05); // Only 4 banks of RAM, so bank 5 mirrors bank 1
+        assert_eq!(memory.current_ram_bank(), Some(0x01));
+        memory.write(0x4000, 0x08); // RTC seconds
+        assert_eq!(memory.current_ram_bank(), None);
+        assert_eq!(memory.rtc_register(), Some(0x08));
+    }
+
     #[test]
     fn read_write_rtc() {
         let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
@@ -350,7 +467,6 @@ mod tests {
         memory.write(0xA000, 105); // Write 105 days low
         memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
         memory.write(0xA000, 0x81); // Write 768 days high (non-halted)
-        memory.write(0x0000, 0xB); // Disable RAM
         memory.write(0x4000, 0x08); // Set RAM bank to RTC seconds
         assert_eq!(memory.read(0xA000), 56); // Read seconds
         memory.write(0x4000, 0x09); // Set RAM bank to RTC minutes
@@ -379,7 +495,6 @@ mod tests {
         memory.write(0xA000, 0xFF); // Write 512 days
         memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
         memory.write(0xA000, 0x01); // Write 512 days (non-halted, no carry)
-        memory.write(0x0000, 0xB); // Disable RAM
         // Tick a full second (1 tick = 1 microsecond)
         for _ in 0..1_000_000usize {
             memory.tick(false);
@@ -411,4 +526,46 @@ mod tests {
         // Set RAM bank to RTC days high
         assert_eq_hex!(memory.read(0xA000), 0x80); // Read days high (non-halted, carry enabled)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rtc_footer_round_trip() {
+        let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+        memory.write(0x0000, 0xA); // Enable RAM
+        memory.write(0x4000, 0x08); // Set RAM bank to RTC seconds
+        memory.write(0xA000, 42); // Write 42 seconds
+        memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
+        memory.write(0xA000, 0x41); // Write 256 days (halted)
+        let footer = memory.rtc_footer(0x0123456789ABCDEF).unwrap();
+        assert_eq!(footer.len(), 48);
+        assert_eq!(footer[0..4], [42, 0, 0, 0]);
+        assert_eq!(footer[16..20], [0x41, 0, 0, 0]);
+        assert_eq!(footer[40..48], [0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01]);
+
+        let mut loaded_memory = MBC3::new(ROMSize::KB256, RAMSize::KB32);
+        loaded_memory.load_rtc_footer(&footer);
+        loaded_memory.write(0x0000, 0xA); // Enable RAM
+        loaded_memory.write(0x4000, 0x08); // Set RAM bank to RTC seconds
+        assert_eq!(loaded_memory.read(0xA000), 42);
+        loaded_memory.write(0x4000, 0x0C); // Set RAM bank to RTC days high
+        assert_eq_hex!(loaded_memory.read(0xA000), 0x41);
+        assert_eq!(loaded_memory.rtc_footer(0x0123456789ABCDEF).unwrap(), footer);
+    }
+
+    #[test]
+    fn out_of_range_ram_bank_mirrors_small_ram() {
+        let mut memory = MBC3::new(ROMSize::KB256, RAMSize::KB8);
+        memory.write(0x0000, 0xA); // Enable RAM
+        memory.write(0xA123, 0xAB);
+        memory.write(0x4000, 0x05); // Switch to RAM bank 5, which doesn't exist
+        assert_eq_hex!(memory.read(0xA123), 0xAB);
+    }
+
+    #[test]
+    fn missing_ram_reads_open_bus() {
+        let mut memory = MBC3::new(ROMSize::KB256, RAMSize::Unavailable);
+        memory.write(0x0000, 0xA); // Enable RAM
+        memory.write(0x4000, 0x02);
+        memory.write(0xA123, 0xAB);
+        assert_eq_hex!(memory.read(0xA123), 0xFF);
+    }
+}
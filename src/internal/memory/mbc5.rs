@@ -1,4 +1,4 @@
-use log::info;
+use crate::internal::infrastructure::logging;
 use crate::internal::memory::mbc::{Loadable, MBC};
 use crate::internal::memory::memory::Memory;
 use crate::memory::{RAMSize, ROMSize};
@@ -11,15 +11,32 @@ pub struct MBC5 {
   ram: Vec<u8>,
 }
 
-impl MBC for MBC5 {}
+impl MBC for MBC5 {
+  fn rom_bank(&self, address: u16) -> usize {
+    if address < 0x4000 { 0 } else { self.rom_bank_address % (self.rom.len() >> 14) }
+  }
+
+  fn ram_bank(&self) -> usize {
+    self.ram_bank_address % (self.ram.len() >> 13).max(1)
+  }
+
+  fn ram(&self) -> &[u8] {
+    &self.ram
+  }
+
+  fn load_ram(&mut self, bytes: &[u8]) {
+    let length = self.ram.len().min(bytes.len());
+    self.ram[..length].copy_from_slice(&bytes[..length]);
+  }
+}
 
 impl MBC5 {
   pub fn new(rom_size: ROMSize, ram_size: RAMSize) -> MBC5 {
-    info!("Loading new MBC5 cartridge with ROM size {:?} and RAM size {:?}", rom_size, ram_size);
+    logging::info(format_args!("Loading new MBC5 cartridge with ROM size {:?} and RAM size {:?}", rom_size, ram_size));
     MBC5 {
       ram_enabled: false,
       ram_bank_address: 0x00,
-      rom_bank_address: 0x00,
+      rom_bank_address: 0x01,
       ram: vec![0; ram_size.bytes()],
       rom: vec![0; rom_size.bytes()],
     }
@@ -34,11 +51,14 @@ impl Memory for MBC5 {
       }
       0x4000..=0x7FFF => {
         let address_in_rom = ((address as usize) & 0x3FFF) | (self.rom_bank_address << 14);
-        self.rom[address_in_rom]
+        self.rom[address_in_rom % self.rom.len()]
       }
       0xA000..=0xBFFF => {
+        if !self.ram_enabled || self.ram.is_empty() {
+          return 0xFF;
+        }
         let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
-        self.ram[address_in_ram]
+        self.ram[address_in_ram % self.ram.len()]
       }
       _ => panic!("Can't read from address {:#06x} on MBC5", address)
     }
@@ -49,19 +69,23 @@ impl Memory for MBC5 {
       0x0000..=0x1FFF => {
         self.ram_enabled = (value & 0x0F) == 0x0A;
       }
+      // Unlike the other MBCs, MBC5 doesn't remap bank 0, so it can be mapped into both halves of the ROM area
       0x2000..=0x2FFF => {
         self.rom_bank_address = (self.rom_bank_address & 0x100) | (value as usize);
+        logging::trace(format_args!("Switching MBC5 to ROM bank {}", self.rom_bank_address));
       }
       0x3000..=0x3FFF => {
-        self.rom_bank_address = ((value as usize) << 8) | (self.rom_bank_address & 0xFF);
+        self.rom_bank_address = (((value & 0x01) as usize) << 8) | (self.rom_bank_address & 0xFF);
+        logging::trace(format_args!("Switching MBC5 to ROM bank {}", self.rom_bank_address));
       }
       0x4000..=0x5FFF => {
-        self.ram_bank_address = value as usize;
+        self.ram_bank_address = (value & 0x0F) as usize;
       }
       0xA000..=0xBFFF => {
-        if self.ram_enabled {
+        if self.ram_enabled && !self.ram.is_empty() {
           let address_in_ram = ((address as usize) & 0x1FFF) | (self.ram_bank_address << 13);
-          self.ram[address_in_ram] = value
+          let ram_length = self.ram.len();
+          self.ram[address_in_ram % ram_length] = value
         }
       }
       _ => {
@@ -110,6 +134,17 @@ mod tests {
     assert_eq_hex!(memory.read(0xBFFF), 0x56);
   }
 
+  #[test]
+  fn disabled_ram_reads_open_bus() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB64);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0xA000, 0xAB);
+    memory.write(0x0000, 0x0); // Disable RAM
+    assert_eq_hex!(memory.read(0xA000), 0xFF);
+    memory.write(0x0000, 0xA); // Re-enable RAM
+    assert_eq_hex!(memory.read(0xA000), 0xAB);
+  }
+
   #[test]
   fn read_lower_rom() {
     let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB64);
@@ -150,4 +185,56 @@ mod tests {
     assert_eq_hex!(memory.read(0x5ABC), 0xBB);
     assert_eq_hex!(memory.read(0x7FFF), 0xCC);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn rom_bank_0_can_be_selected() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB64);
+    memory.load_byte(0x0123, 0x12);
+    memory.load_byte(0x4123, 0x34);
+    assert_eq_hex!(memory.read(0x4123), 0x34); // Bank 1 is mapped on reset
+    memory.write(0x2000, 0x00);
+    assert_eq_hex!(memory.read(0x4123), 0x12);
+    assert_eq!(memory.rom_bank(0x4123), 0);
+  }
+
+  #[test]
+  fn upper_rom_bank_register_only_uses_bit_0() {
+    let mut memory = MBC5::new(ROMSize::MB8, RAMSize::KB64);
+    memory.load_byte(0x404123, 0x56); // Bank 0x101
+    memory.write(0x2000, 0x01);
+    memory.write(0x3000, 0xFF);
+    assert_eq_hex!(memory.read(0x4123), 0x56);
+    assert_eq!(memory.rom_bank(0x4123), 0x101);
+  }
+
+  #[test]
+  fn current_banks_match_selected_banks() {
+    let mut memory = MBC5::new(ROMSize::MB8, RAMSize::KB128);
+    memory.load_byte(0x4C8000, 0x12); // Bank 0x132
+    memory.write(0x2000, 0x32);
+    memory.write(0x3000, 0x01);
+    memory.write(0x4000, 0x0B);
+    assert_eq!(memory.current_rom_bank(), 0x132);
+    assert_eq!(memory.current_ram_bank(), Some(0x0B));
+    assert_eq_hex!(memory.read(0x4000), 0x12);
+  }
+
+  #[test]
+  fn out_of_range_ram_bank_mirrors_small_ram() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::KB32);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x01);
+    memory.write(0xA123, 0xAB);
+    memory.write(0x4000, 0xFD); // Only the lower 4 bits select a bank, and bank 13 mirrors bank 1
+    assert_eq_hex!(memory.read(0xA123), 0xAB);
+  }
+
+  #[test]
+  fn missing_ram_reads_open_bus() {
+    let mut memory = MBC5::new(ROMSize::KB256, RAMSize::Unavailable);
+    memory.write(0x0000, 0xA); // Enable RAM
+    memory.write(0x4000, 0x0F);
+    memory.write(0xA123, 0xAB);
+    assert_eq_hex!(memory.read(0xA123), 0xFF);
+  }
+}
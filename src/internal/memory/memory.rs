@@ -8,8 +8,8 @@ pub struct MemoryAddress {}
 impl MemoryAddress {
   pub const BANK: u16 = 0xFF50; // Bank register unmaps boot ROM
   pub const P1: u16 = 0xFF00; // Port P15-10
-  pub const _SB: u16 = 0xFF01; // Serial transfer register
-  pub const _SC: u16 = 0xFF02; // Serial control
+  pub const SB: u16 = 0xFF01; // Serial transfer register
+  pub const SC: u16 = 0xFF02; // Serial control
 
   // Timer control
   pub const DIV: u16 = 0xFF04; // Divider
@@ -62,6 +62,12 @@ impl OAMImpl {
       bytes: [0; 160]
     }
   }
+
+  // Copies bytes into OAM starting at the given byte index, without going through the memory bus
+  #[cfg(test)]
+  pub fn write_raw(&mut self, index: usize, bytes: &[u8]) {
+    self.bytes[index..(index + bytes.len())].copy_from_slice(bytes);
+  }
 }
 
 impl OAM for OAMImpl {
@@ -120,4 +126,33 @@ impl Memory for OAMImpl {
   fn write(&mut self, address: u16, value: u8) {
     self.bytes[address as usize - OAMImpl::START_ADDRESS] = value;
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use test_case::test_case;
+
+  use super::*;
+
+  #[test_case(false, false, 0x04; "top tile")]
+  #[test_case(true, false, 0x05; "bottom tile")]
+  #[test_case(false, true, 0x05; "top tile flipped vertically")]
+  #[test_case(true, true, 0x04; "bottom tile flipped vertically")]
+  fn tall_object_ignores_lowest_bit_of_tile_index(use_bottom_tile: bool, flip_vertical: bool, expected_tile_index: u8) {
+    let mut oam = OAMImpl::new();
+    oam.write(0xFE00, 0x20);
+    oam.write(0xFE01, 0x10);
+    oam.write(0xFE02, 0x05);
+    oam.write(0xFE03, if flip_vertical { 0x40 } else { 0x00 });
+    let object = oam.get_object(ObjectReference { object_index: 0, use_bottom_tile }, true);
+    assert_eq!(object.tile_index, expected_tile_index);
+  }
+
+  #[test]
+  fn small_object_uses_tile_index_as_is() {
+    let mut oam = OAMImpl::new();
+    oam.write(0xFE02, 0x05);
+    let object = oam.get_object(ObjectReference { object_index: 0, use_bottom_tile: false }, false);
+    assert_eq!(object.tile_index, 0x05);
+  }
+}
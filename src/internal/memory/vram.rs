@@ -120,6 +120,8 @@ pub struct WindowParams {
     pub tile_addressing_mode: TileAddressingMode,
     pub line: u8,
     pub window_position: Point,
+    // How many pixels at the start of the window line aren't shown, because they'd fall left of the screen
+    pub discarded_pixels: u8,
 }
 
 #[automock]
@@ -150,6 +152,31 @@ impl VRAMImpl {
         }
     }
 
+    pub fn bank(&self, index: usize) -> &[u8] {
+        &self.bytes[index]
+    }
+
+    // Copies bytes into the given bank starting at the given address, regardless of which bank is selected
+    #[cfg(test)]
+    pub fn write_raw(&mut self, bank: usize, address: u16, bytes: &[u8]) {
+        let offset = (address - VRAMImpl::START_ADDRESS) as usize;
+        self.bytes[bank][offset..(offset + bytes.len())].copy_from_slice(bytes);
+    }
+
+    // The 32x32 tile indices of the tile map, row by row
+    pub fn dump_tile_map(&self, tile_map_index: TileMapIndex) -> [u8; 1024] {
+        let mut tile_indices = [0u8; 1024];
+        tile_indices.copy_from_slice(self.tile_map(tile_map_index).bytes[0]);
+        tile_indices
+    }
+
+    // The CGB attributes of the tiles in the tile map, which are stored in bank 1
+    pub fn dump_tile_map_attributes(&self, tile_map_index: TileMapIndex) -> [u8; 1024] {
+        let mut attributes = [0u8; 1024];
+        attributes.copy_from_slice(self.tile_map(tile_map_index).bytes[1]);
+        attributes
+    }
+
     fn tile_map(&self, tile_map_index: TileMapIndex) -> TileMapView {
         match tile_map_index {
             TileMapIndex::TileMap1 => TileMapView {
@@ -224,11 +251,7 @@ impl VRAM for VRAMImpl {
         let pixel_row = params.line - params.window_position.y;
         let tile_row = pixel_row / 8;
         let pixel_row_offset = pixel_row % 8;
-        let pixels_to_draw = if params.window_position.x < 7 {
-            160
-        } else {
-            160 - params.window_position.x + 7
-        };
+        let pixels_to_draw = 160 - (params.window_position.x.clamp(7, 167) as usize - 7);
 
         tile_map.row(tile_row)
             .flat_map(|Tile { chr_code, attributes }| tile_data_view
@@ -240,7 +263,8 @@ impl VRAM for VRAMImpl {
                     palette_index: attributes.palette_index(),
                 })
             )
-            .take(pixels_to_draw as usize)
+            .skip(params.discarded_pixels as usize)
+            .take(pixels_to_draw)
             .collect()
     }
 
@@ -312,6 +336,57 @@ pub mod tests {
         vram.write(MemoryAddress::VBK, 0);
         assert_eq_hex!(vram.read(VRAMImpl::START_ADDRESS), 0xAB);
     }
+
+    #[test]
+    fn dump_tile_map_returns_indices_and_attributes() {
+        let mut vram = VRAMImpl::new();
+        vram.write(0x9C00, 0x11);
+        vram.write(0x9C21, 0x22); // Row 1, column 1
+        vram.write(0x9FFF, 0x33);
+        vram.write(0x9800, 0x44);
+        vram.write(MemoryAddress::VBK, 1);
+        vram.write(0x9C21, 0x8F);
+        let tile_indices = vram.dump_tile_map(TileMapIndex::TileMap2);
+        assert_eq_hex!(tile_indices[0], 0x11);
+        assert_eq_hex!(tile_indices[33], 0x22);
+        assert_eq_hex!(tile_indices[1023], 0x33);
+        assert_eq!(tile_indices.iter().filter(|&&tile_index| tile_index != 0).count(), 3);
+        assert_eq_hex!(vram.dump_tile_map(TileMapIndex::TileMap1)[0], 0x44);
+        let attributes = vram.dump_tile_map_attributes(TileMapIndex::TileMap2);
+        assert_eq_hex!(attributes[33], 0x8F);
+        assert_eq!(attributes.iter().filter(|&&attribute| attribute != 0).count(), 1);
+    }
+
+    fn background_color_indices(vram: &VRAMImpl, scx: u8) -> Vec<u8> {
+        vram.background_line_colors(BackgroundParams {
+            tile_map_index: TileMapIndex::TileMap1,
+            tile_addressing_mode: TileAddressingMode::Mode8000,
+            line: 0,
+            viewport_position: Point { x: scx, y: 0 },
+        }).into_iter()
+            .map(|color_ref| color_ref.color_index)
+            .collect()
+    }
+
+    #[test]
+    fn background_fine_scroll_discards_pixels_of_first_tile() {
+        let mut vram = VRAMImpl::new();
+        // Tile 0, row 0 has color indices 0, 1, 2, 3, 0, 1, 2, 3
+        vram.write(0x8000, 0x55);
+        vram.write(0x8001, 0x33);
+        // Tile 1, row 0 has color indices 2, 2, 2, 2, 3, 3, 3, 3
+        vram.write(0x8010, 0x0F);
+        vram.write(0x8011, 0xFF);
+        (0..32u16).for_each(|column| vram.write(0x9800 + column, (column % 2) as u8));
+
+        let unscrolled_colors = background_color_indices(&vram, 0);
+        let scrolled_colors = background_color_indices(&vram, 3);
+
+        assert_eq!(unscrolled_colors[0..16], [0, 1, 2, 3, 0, 1, 2, 3, 2, 2, 2, 2, 3, 3, 3, 3]);
+        assert_eq!(scrolled_colors.len(), 160);
+        assert_eq!(scrolled_colors[0..13], [3, 0, 1, 2, 3, 2, 2, 2, 2, 3, 3, 3, 3]);
+        assert_eq!(scrolled_colors[0..157], unscrolled_colors[3..160]);
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -24,6 +24,14 @@ impl WRAMImpl {
       bytes: [[0; WRAMImpl::BANK_SIZE]; 8],
     }
   }
+
+  pub fn bank(&self, index: usize) -> &[u8] {
+    &self.bytes[index]
+  }
+
+  pub fn fill(&mut self, value: u8) {
+    self.bytes.iter_mut().for_each(|bank| bank.fill(value));
+  }
 }
 
 impl Memory for WRAMImpl {
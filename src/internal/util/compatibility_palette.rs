@@ -187,8 +187,10 @@ impl CompatibilityPaletteLoader {
     'R'
   ];
 
-  pub fn get_compatibility_palettes(cartridge_info: &CartridgeInfo) -> CompatibilityPalettes {
-    let palette_id = if cartridge_info.licensee.is_licensed_by_nintendo() {
+  // Returns the palette id the CGB boot ROM picks for the cartridge. The lower 5 bits select a palette combination,
+  // the upper 3 bits determine which of its palettes are used for the objects.
+  pub fn get_palette_id(cartridge_info: &CartridgeInfo) -> u8 {
+    let table_index = if cartridge_info.licensee.is_licensed_by_nintendo() {
       let title_checksum = cartridge_info.title_checksum;
       if let Some(checksum_index) = CompatibilityPaletteLoader::TITLE_CHECKSUMS.into_iter().position(|value| value == title_checksum) {
         if checksum_index <= 64 {
@@ -210,9 +212,12 @@ impl CompatibilityPaletteLoader {
     } else {
       0x00
     };
-    let palette_index_index_and_flags = CompatibilityPaletteLoader::PALETTE_INDEX_INDEXES_AND_FLAGS[palette_id as usize];
-    let palette_index_index = (palette_index_index_and_flags & 0x1F) as usize;
-    let shuffle_flags = (palette_index_index_and_flags & 0xE0) >> 5;
+    CompatibilityPaletteLoader::PALETTE_INDEX_INDEXES_AND_FLAGS[table_index]
+  }
+
+  pub fn get_palettes_for_id(palette_id: u8) -> CompatibilityPalettes {
+    let palette_index_index = (palette_id & 0x1F) as usize;
+    let shuffle_flags = (palette_id & 0xE0) >> 5;
     let palette_index_offset = palette_index_index * 3;
     let palette_indexes = &CompatibilityPaletteLoader::PALETTE_INDEXES[palette_index_offset..palette_index_offset + 3];
     // Divide index by number of bytes per Color
@@ -244,19 +249,33 @@ mod tests {
       ram_size: RAMSize::KB32,
       cgb_mode: CGBMode::Monochrome,
     };
-    let result = CompatibilityPaletteLoader::get_compatibility_palettes(&cartridge_info);
-    assert_eq!(result.bgp[0], Color::from_rgb(0xFF, 0xFF, 0xFF).to_rgb555());
-    assert_eq!(result.bgp[1], Color::from_rgb(0xFF, 0x84, 0x84).to_rgb555());
-    assert_eq!(result.bgp[2], Color::from_rgb(0x94, 0x3A, 0x3A).to_rgb555());
-    assert_eq!(result.bgp[3], Color::from_rgb(0x00, 0x00, 0x00).to_rgb555());
-    assert_eq!(result.obj0[0], Color::from_rgb(0xFF, 0xFF, 0xFF).to_rgb555());
-    assert_eq!(result.obj0[1], Color::from_rgb(0x7B, 0xFF, 0x31).to_rgb555());
-    assert_eq!(result.obj0[2], Color::from_rgb(0x00, 0x84, 0x00).to_rgb555());
-    assert_eq!(result.obj0[3], Color::from_rgb(0x00, 0x00, 0x00).to_rgb555());
-    assert_eq!(result.obj1[0], Color::from_rgb(0xFF, 0xFF, 0xFF).to_rgb555());
-    assert_eq!(result.obj1[1], Color::from_rgb(0xFF, 0x84, 0x84).to_rgb555());
-    assert_eq!(result.obj1[2], Color::from_rgb(0x94, 0x3A, 0x3A).to_rgb555());
-    assert_eq!(result.obj1[3], Color::from_rgb(0x00, 0x00, 0x00).to_rgb555());
+    let result = CompatibilityPaletteLoader::get_palettes_for_id(CompatibilityPaletteLoader::get_palette_id(&cartridge_info));
+    assert_eq!(result.bgp[0], Color::from_rgb888(0xFF, 0xFF, 0xFF));
+    assert_eq!(result.bgp[1], Color::from_rgb888(0xFF, 0x84, 0x84));
+    assert_eq!(result.bgp[2], Color::from_rgb888(0x94, 0x3A, 0x3A));
+    assert_eq!(result.bgp[3], Color::from_rgb888(0x00, 0x00, 0x00));
+    assert_eq!(result.obj0[0], Color::from_rgb888(0xFF, 0xFF, 0xFF));
+    assert_eq!(result.obj0[1], Color::from_rgb888(0x7B, 0xFF, 0x31));
+    assert_eq!(result.obj0[2], Color::from_rgb888(0x00, 0x84, 0x00));
+    assert_eq!(result.obj0[3], Color::from_rgb888(0x00, 0x00, 0x00));
+    assert_eq!(result.obj1[0], Color::from_rgb888(0xFF, 0xFF, 0xFF));
+    assert_eq!(result.obj1[1], Color::from_rgb888(0xFF, 0x84, 0x84));
+    assert_eq!(result.obj1[2], Color::from_rgb888(0x94, 0x3A, 0x3A));
+    assert_eq!(result.obj1[3], Color::from_rgb888(0x00, 0x00, 0x00));
+  }
+
+  #[test]
+  fn get_pokemon_red_palette_id() {
+    let cartridge_info = CartridgeInfo {
+      title: String::from("POKEMON RED"),
+      title_checksum: 0x14,
+      licensee: Licensee::NewLicensee('0', '1'),
+      cartridge_type: CartridgeType::MBC1,
+      rom_size: ROMSize::KB512,
+      ram_size: RAMSize::KB32,
+      cgb_mode: CGBMode::Monochrome,
+    };
+    assert_eq!(CompatibilityPaletteLoader::get_palette_id(&cartridge_info), 0x30);
   }
 
   #[test]
@@ -270,19 +289,19 @@ mod tests {
       ram_size: RAMSize::KB8,
       cgb_mode: CGBMode::Monochrome,
     };
-    let result = CompatibilityPaletteLoader::get_compatibility_palettes(&cartridge_info);
-    assert_eq!(result.bgp[0], Color::from_rgb(0xFF, 0xFF, 0xFF).to_rgb555());
-    assert_eq!(result.bgp[1], Color::from_rgb(0xFF, 0x84, 0x84).to_rgb555());
-    assert_eq!(result.bgp[2], Color::from_rgb(0x94, 0x3A, 0x3A).to_rgb555());
-    assert_eq!(result.bgp[3], Color::from_rgb(0x00, 0x00, 0x00).to_rgb555());
-    assert_eq!(result.obj0[0], Color::from_rgb(0xFF, 0xFF, 0xFF).to_rgb555());
-    assert_eq!(result.obj0[1], Color::from_rgb(0x00, 0xFF, 0x00).to_rgb555());
-    assert_eq!(result.obj0[2], Color::from_rgb(0x31, 0x84, 0x00).to_rgb555());
-    assert_eq!(result.obj0[3], Color::from_rgb(0x00, 0x4A, 0x00).to_rgb555());
-    assert_eq!(result.obj1[0], Color::from_rgb(0xFF, 0xFF, 0xFF).to_rgb555());
-    assert_eq!(result.obj1[1], Color::from_rgb(0x63, 0xA5, 0xFF).to_rgb555());
-    assert_eq!(result.obj1[2], Color::from_rgb(0x00, 0x00, 0xFF).to_rgb555());
-    assert_eq!(result.obj1[3], Color::from_rgb(0x00, 0x00, 0x00).to_rgb555());
+    let result = CompatibilityPaletteLoader::get_palettes_for_id(CompatibilityPaletteLoader::get_palette_id(&cartridge_info));
+    assert_eq!(result.bgp[0], Color::from_rgb888(0xFF, 0xFF, 0xFF));
+    assert_eq!(result.bgp[1], Color::from_rgb888(0xFF, 0x84, 0x84));
+    assert_eq!(result.bgp[2], Color::from_rgb888(0x94, 0x3A, 0x3A));
+    assert_eq!(result.bgp[3], Color::from_rgb888(0x00, 0x00, 0x00));
+    assert_eq!(result.obj0[0], Color::from_rgb888(0xFF, 0xFF, 0xFF));
+    assert_eq!(result.obj0[1], Color::from_rgb888(0x00, 0xFF, 0x00));
+    assert_eq!(result.obj0[2], Color::from_rgb888(0x31, 0x84, 0x00));
+    assert_eq!(result.obj0[3], Color::from_rgb888(0x00, 0x4A, 0x00));
+    assert_eq!(result.obj1[0], Color::from_rgb888(0xFF, 0xFF, 0xFF));
+    assert_eq!(result.obj1[1], Color::from_rgb888(0x63, 0xA5, 0xFF));
+    assert_eq!(result.obj1[2], Color::from_rgb888(0x00, 0x00, 0xFF));
+    assert_eq!(result.obj1[3], Color::from_rgb888(0x00, 0x00, 0x00));
   }
 
   #[test]
@@ -296,10 +315,10 @@ mod tests {
       ram_size: RAMSize::Unavailable,
       cgb_mode: CGBMode::Monochrome,
     };
-    let result = CompatibilityPaletteLoader::get_compatibility_palettes(&cartridge_info);
-    assert_eq!(result.bgp[0], Color::from_rgb(0xA5, 0x9C, 0xFF).to_rgb555());
-    assert_eq!(result.bgp[1], Color::from_rgb(0xFF, 0xFF, 0x00).to_rgb555());
-    assert_eq!(result.bgp[2], Color::from_rgb(0x00, 0x63, 0x00).to_rgb555());
-    assert_eq!(result.bgp[3], Color::from_rgb(0x00, 0x00, 0x00).to_rgb555());
+    let result = CompatibilityPaletteLoader::get_palettes_for_id(CompatibilityPaletteLoader::get_palette_id(&cartridge_info));
+    assert_eq!(result.bgp[0], Color::from_rgb888(0xA5, 0x9C, 0xFF));
+    assert_eq!(result.bgp[1], Color::from_rgb888(0xFF, 0xFF, 0x00));
+    assert_eq!(result.bgp[2], Color::from_rgb888(0x00, 0x63, 0x00));
+    assert_eq!(result.bgp[3], Color::from_rgb888(0x00, 0x00, 0x00));
   }
 }
\ No newline at end of file
@@ -9,5 +9,9 @@ pub mod renderer;
 pub mod input;
 pub mod cpu;
 pub mod memory;
+pub mod logger;
+pub mod debug;
+pub mod serial;
+pub mod test_harness;
 mod internal;
 
@@ -0,0 +1,65 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+  Trace,
+  Debug,
+  Info,
+  Warn,
+}
+
+pub trait EmulatorLogger {
+  fn trace(&self, message: &str);
+  fn debug(&self, message: &str);
+  fn info(&self, message: &str);
+  fn warn(&self, message: &str);
+
+  // Messages are only formatted for levels that are enabled, so loggers that drop some levels should say so here
+  fn enabled(&self, _level: LogLevel) -> bool {
+    true
+  }
+}
+
+// Forwards all diagnostics to the log crate, this is the logger that's used unless another one is installed
+#[cfg(feature = "log")]
+pub struct LogCrateLogger;
+
+#[cfg(feature = "log")]
+impl EmulatorLogger for LogCrateLogger {
+  fn trace(&self, message: &str) {
+    log::trace!("{}", message);
+  }
+
+  fn debug(&self, message: &str) {
+    log::debug!("{}", message);
+  }
+
+  fn info(&self, message: &str) {
+    log::info!("{}", message);
+  }
+
+  fn warn(&self, message: &str) {
+    log::warn!("{}", message);
+  }
+
+  fn enabled(&self, level: LogLevel) -> bool {
+    match level {
+      LogLevel::Trace => log::log_enabled!(log::Level::Trace),
+      LogLevel::Debug => log::log_enabled!(log::Level::Debug),
+      LogLevel::Info => log::log_enabled!(log::Level::Info),
+      LogLevel::Warn => log::log_enabled!(log::Level::Warn),
+    }
+  }
+}
+
+// Drops all diagnostics, this is the logger that's used unless another one is installed when the log feature is off
+pub struct NullLogger;
+
+impl EmulatorLogger for NullLogger {
+  fn trace(&self, _message: &str) {}
+  fn debug(&self, _message: &str) {}
+  fn info(&self, _message: &str) {}
+  fn warn(&self, _message: &str) {}
+
+  fn enabled(&self, _level: LogLevel) -> bool {
+    false
+  }
+}
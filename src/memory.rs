@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use crate::internal::memory::oam::ObjectAttributes;
 use crate::memory::Licensee::{NewLicensee, OldLicensee};
 
+pub use crate::internal::memory::vram::TileMapIndex;
+
 #[derive(Copy, Clone)]
 pub struct OAMObject {
   pub lcd_y: u8,
@@ -288,20 +290,24 @@ pub enum CartridgeType {
 
 impl CartridgeType {
   pub fn from_byte(byte: u8) -> Self {
+    CartridgeType::try_from_byte(byte).unwrap_or_else(|| panic!("Unknown cartridge for byte {:#x}", byte))
+  }
+
+  pub fn try_from_byte(byte: u8) -> Option<Self> {
     match byte {
-      0x00 => CartridgeType::MBC,
-      0x01..=0x03 => CartridgeType::MBC1,
-      0x05..=0x06 => CartridgeType::MBC2,
-      0x0B..=0x0D => CartridgeType::MMM01,
-      0x0F..=0x13 => CartridgeType::MBC3,
-      0x19..=0x1E => CartridgeType::MBC5,
-      0x20 => CartridgeType::MBC6,
-      0x22 => CartridgeType::MBC7,
-      0xFC => CartridgeType::PocketCamera,
-      0xFD => CartridgeType::Bandai,
-      0xFE => CartridgeType::HuC3,
-      0xFF => CartridgeType::HuC1,
-      _ => panic!("Unknown cartridge for byte {:#x}", byte)
+      0x00 => Some(CartridgeType::MBC),
+      0x01..=0x03 => Some(CartridgeType::MBC1),
+      0x05..=0x06 => Some(CartridgeType::MBC2),
+      0x0B..=0x0D => Some(CartridgeType::MMM01),
+      0x0F..=0x13 => Some(CartridgeType::MBC3),
+      0x19..=0x1E => Some(CartridgeType::MBC5),
+      0x20 => Some(CartridgeType::MBC6),
+      0x22 => Some(CartridgeType::MBC7),
+      0xFC => Some(CartridgeType::PocketCamera),
+      0xFD => Some(CartridgeType::Bandai),
+      0xFE => Some(CartridgeType::HuC3),
+      0xFF => Some(CartridgeType::HuC1),
+      _ => None
     }
   }
 }
@@ -409,7 +415,7 @@ impl RAMSize {
   pub fn bytes(&self) -> usize {
     match self {
       RAMSize::Unavailable => 0,
-      RAMSize::KB8 => 0x8000,
+      RAMSize::KB8 => 0x2000,
       RAMSize::KB32 => 0x8000,
       RAMSize::KB64 => 0x10000,
       RAMSize::KB128 => 0x20000,
@@ -15,6 +15,122 @@ pub enum RenderTarget {
   TileAtlas
 }
 
+// The palettes that can be picked manually on the CGB boot screen by holding a direction and optionally A or B
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CompatibilityPalette {
+  Up,
+  UpA,
+  UpB,
+  Left,
+  LeftA,
+  LeftB,
+  Down,
+  DownA,
+  DownB,
+  Right,
+  RightA,
+  RightB,
+}
+
+impl CompatibilityPalette {
+  pub fn id(&self) -> u8 {
+    match self {
+      CompatibilityPalette::Up => 0x12,
+      CompatibilityPalette::UpA => 0xB0,
+      CompatibilityPalette::UpB => 0x79,
+      CompatibilityPalette::Left => 0xB8,
+      CompatibilityPalette::LeftA => 0xAD,
+      CompatibilityPalette::LeftB => 0x16,
+      CompatibilityPalette::Down => 0x17,
+      CompatibilityPalette::DownA => 0x07,
+      CompatibilityPalette::DownB => 0xBA,
+      CompatibilityPalette::Right => 0x05,
+      CompatibilityPalette::RightA => 0x7C,
+      CompatibilityPalette::RightB => 0x13,
+    }
+  }
+}
+
+// Color transforms for color vision deficiencies, applied to everything drawn to the main render target.
+// The daltonization filters shift the colors a viewer can't tell apart towards ones they can. With S the simulation
+// matrix for the deficiency (Machado et al., 2009) and E the matrix that redistributes the lost information over the
+// remaining channels (Fidaner et al.), each color is mapped through I + E * (I - S). Greys are left unchanged.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccessibilityFilter {
+  Protanopia,
+  Deuteranopia,
+  Tritanopia,
+  // Doubles the contrast of each channel around its midpoint, so its darkest and brightest quarters become black and white
+  HighContrast,
+}
+
+impl AccessibilityFilter {
+  const PROTANOPIA_SIMULATION: [[f32; 3]; 3] = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+  ];
+  const DEUTERANOPIA_SIMULATION: [[f32; 3]; 3] = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+  ];
+  const TRITANOPIA_SIMULATION: [[f32; 3]; 3] = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.147602],
+    [0.004733, 0.691367, 0.303900],
+  ];
+  // Red-green deficiencies move the error into green and blue, tritanopia moves it into red and green
+  const RED_GREEN_ERROR_SHIFT: [[f32; 3]; 3] = [
+    [0.0, 0.0, 0.0],
+    [0.7, 1.0, 0.0],
+    [0.7, 0.0, 1.0],
+  ];
+  const BLUE_YELLOW_ERROR_SHIFT: [[f32; 3]; 3] = [
+    [1.0, 0.0, 0.7],
+    [0.0, 1.0, 0.7],
+    [0.0, 0.0, 0.0],
+  ];
+
+  pub fn daltonization_matrix(&self) -> Option<[[f32; 3]; 3]> {
+    let (simulation, error_shift) = match self {
+      AccessibilityFilter::Protanopia => (AccessibilityFilter::PROTANOPIA_SIMULATION, AccessibilityFilter::RED_GREEN_ERROR_SHIFT),
+      AccessibilityFilter::Deuteranopia => (AccessibilityFilter::DEUTERANOPIA_SIMULATION, AccessibilityFilter::RED_GREEN_ERROR_SHIFT),
+      AccessibilityFilter::Tritanopia => (AccessibilityFilter::TRITANOPIA_SIMULATION, AccessibilityFilter::BLUE_YELLOW_ERROR_SHIFT),
+      AccessibilityFilter::HighContrast => return None,
+    };
+    let identity = |row: usize, column: usize| if row == column { 1.0f32 } else { 0.0f32 };
+    let mut matrix = [[0.0f32; 3]; 3];
+    for (row, matrix_row) in matrix.iter_mut().enumerate() {
+      for (column, value) in matrix_row.iter_mut().enumerate() {
+        let error: f32 = (0..3).map(|k| error_shift[row][k] * (identity(k, column) - simulation[k][column])).sum();
+        *value = identity(row, column) + error;
+      }
+    }
+    Some(matrix)
+  }
+
+  pub fn apply(&self, color: Color) -> Color {
+    self.apply_with_matrix(self.daltonization_matrix().as_ref(), color)
+  }
+
+  // Lets callers that filter every pixel compute the daltonization matrix only once
+  pub(crate) fn apply_with_matrix(&self, daltonization_matrix: Option<&[[f32; 3]; 3]>, color: Color) -> Color {
+    if color.transparent {
+      return color;
+    }
+    let channels = [color.red, color.green, color.blue];
+    let [red, green, blue] = match daltonization_matrix {
+      Some(matrix) => matrix.map(|row| {
+        let channel: f32 = (0..3).map(|column| row[column] * channels[column] as f32).sum();
+        channel.clamp(0.0, 31.0).round() as u8
+      }),
+      None => channels.map(|channel| (2 * channel as i16 - 16).clamp(0, 31) as u8),
+    };
+    Color::from_rgb(red, green, blue)
+  }
+}
+
 impl PartialEq for Color {
   fn eq(&self, other: &Self) -> bool {
     self.red == other.red &&
@@ -41,15 +157,34 @@ impl Color {
     Color::from_rgb(0x15, 0x15, 0x15)
   }
 
-  pub fn from_word(color_word: u16) -> Color {
+  // CGB palette entries pack 5 bits per channel as 0bbbbbgggggrrrrr, which is how colors are kept internally
+  pub fn from_rgb555(value: u16) -> Color {
     Color {
-      red: (color_word & 0x1F) as u8,
-      green: ((color_word & 0x3E0) >> 5) as u8,
-      blue: ((color_word & 0x7C00) >> 10) as u8,
+      red: (value & 0x1F) as u8,
+      green: ((value & 0x3E0) >> 5) as u8,
+      blue: ((value & 0x7C00) >> 10) as u8,
       transparent: false,
     }
   }
 
+  // Packs the 5 bit channels into a palette entry. This used to return a Color with the channels scaled down from
+  // 8 to 5 bits, which is what from_rgb888 does now.
+  pub fn to_rgb555(&self) -> u16 {
+    (self.red & 0x1F) as u16 |
+      ((self.green & 0x1F) as u16) << 5 |
+      ((self.blue & 0x1F) as u16) << 10
+  }
+
+  #[deprecated(note = "use Color::from_rgb555 instead")]
+  pub fn from_word(color_word: u16) -> Color {
+    Color::from_rgb555(color_word)
+  }
+
+  #[deprecated(note = "use Color::to_rgb555 instead")]
+  pub fn to_word(&self) -> u16 {
+    self.to_rgb555()
+  }
+
   pub const fn from_rgb(red: u8, green: u8, blue: u8) -> Color {
     Color {
       red,
@@ -59,10 +194,8 @@ impl Color {
     }
   }
 
-  pub fn to_word(&self) -> u16 {
-    (self.red & 0x1F) as u16 |
-      ((self.green & 0x1F) as u16) << 5 |
-      ((self.blue & 0x1F) as u16) << 10
+  pub fn from_rgb888(red: u8, green: u8, blue: u8) -> Color {
+    Color::from_rgb(Color::to_5_bit(red), Color::to_5_bit(green), Color::to_5_bit(blue))
   }
 
   fn to_5_bit(value: u8) -> u8 {
@@ -77,15 +210,6 @@ impl Color {
     }
   }
 
-  pub fn to_rgb555(&self) -> Color {
-    Color {
-      red: Color::to_5_bit(self.red),
-      green: Color::to_5_bit(self.green),
-      blue: Color::to_5_bit(self.blue),
-      transparent: self.transparent,
-    }
-  }
-
   pub fn to_rgb888(&self) -> Color {
     Color {
       red: Color::to_8_bit(self.red),
@@ -111,4 +235,19 @@ pub trait Renderer {
   fn set_render_target_enabled(&mut self, target: RenderTarget, enabled: bool);
   fn draw_pixel(&mut self, x: usize, y: usize, z: u8, color: Color, target: RenderTarget);
   fn flush(&mut self);
+
+  // The main render target as RGB888, row by row. Renderers that don't keep a frame buffer can leave this empty.
+  fn read_partial_frame(&self) -> Vec<u8> {
+    Vec::new()
+  }
+}
+
+// Discards everything, for running the emulator without video
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+  fn render_target_is_enabled(&self, _target: RenderTarget) -> bool { false }
+  fn set_render_target_enabled(&mut self, _target: RenderTarget, _enabled: bool) {}
+  fn draw_pixel(&mut self, _x: usize, _y: usize, _z: u8, _color: Color, _target: RenderTarget) {}
+  fn flush(&mut self) {}
 }
\ No newline at end of file
@@ -0,0 +1,23 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+// The other end of the link cable. The side that drives the clock calls exchange_bit for every bit it shifts out
+// and shifts in the bit that's returned, so a peer on the external clock sees the transfer one bit at a time.
+pub trait SerialPeer {
+  fn exchange_bit(&mut self, bit: bool) -> bool;
+}
+
+// Lets a peer be shared with whoever else needs to reach it
+impl<P: SerialPeer> SerialPeer for Rc<RefCell<P>> {
+  fn exchange_bit(&mut self, bit: bool) -> bool {
+    RefCell::borrow_mut(self).exchange_bit(bit)
+  }
+}
+
+// Links two emulators without a reference cycle: keep both in an Rc<RefCell<_>> and connect each to a Weak
+// reference to the other. Once the peer is dropped, the cable acts as if it's been unplugged and the line stays high.
+impl<P: SerialPeer> SerialPeer for Weak<RefCell<P>> {
+  fn exchange_bit(&mut self, bit: bool) -> bool {
+    self.upgrade().map_or(true, |peer| RefCell::borrow_mut(&peer).exchange_bit(bit))
+  }
+}
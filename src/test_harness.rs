@@ -0,0 +1,116 @@
+use crate::emulator::{Emulator, EmulatorBuilder};
+
+pub use crate::audio::NullAudioDriver;
+pub use crate::renderer::NullRenderer;
+
+// Test ROMs report their result over the serial port, these are the strings we look out for
+const PASS_MARKERS: [&str; 2] = ["Passed", "OK"];
+const FAIL_MARKERS: [&str; 1] = ["Failed"];
+
+// Roughly one minute of emulated time
+const DEFAULT_MAX_MACHINE_CYCLES: u64 = 60 * 1048576;
+
+// Each variant holds everything the ROM printed over the serial port
+#[derive(Clone, Debug, PartialEq)]
+pub enum TestResult {
+  Passed(String),
+  Failed(String),
+  TimedOut(String),
+}
+
+pub struct TestHarness {
+  emulator: Emulator<NullAudioDriver, NullRenderer>,
+  max_machine_cycles: u64,
+  serial_output: Vec<u8>,
+}
+
+impl TestHarness {
+  pub fn new(rom_bytes: &[u8]) -> Result<TestHarness, String> {
+    let mut emulator = EmulatorBuilder::default().build(rom_bytes, NullAudioDriver, NullRenderer)?;
+    emulator.set_video_enabled(false);
+    Ok(TestHarness {
+      emulator,
+      max_machine_cycles: DEFAULT_MAX_MACHINE_CYCLES,
+      serial_output: Vec::new(),
+    })
+  }
+
+  pub fn with_max_machine_cycles(mut self, max_machine_cycles: u64) -> TestHarness {
+    self.max_machine_cycles = max_machine_cycles;
+    self
+  }
+
+  pub fn serial_output(&self) -> String {
+    String::from_utf8_lossy(&self.serial_output).into_owned()
+  }
+
+  // Runs the ROM until it reports a result over the serial port, or until the cycle cap is hit
+  pub fn run(&mut self) -> TestResult {
+    for _ in 0..self.max_machine_cycles {
+      self.emulator.tick();
+      let sent_bytes = self.emulator.take_serial_output();
+      if sent_bytes.is_empty() {
+        continue;
+      }
+      self.serial_output.extend(sent_bytes);
+      let output = self.serial_output();
+      if FAIL_MARKERS.iter().any(|marker| output.contains(marker)) {
+        return TestResult::Failed(output);
+      }
+      if PASS_MARKERS.iter().any(|marker| output.contains(marker)) {
+        return TestResult::Passed(output);
+      }
+    }
+    TestResult::TimedOut(self.serial_output())
+  }
+}
+
+pub fn run_test_rom(rom: &[u8]) -> TestResult {
+  match TestHarness::new(rom) {
+    Ok(mut harness) => harness.run(),
+    Err(message) => TestResult::Failed(message),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Creates a 32kB ROM without MBC that sends the given bytes over the serial port and then loops forever
+  fn create_serial_rom(message: &[u8]) -> Vec<u8> {
+    let mut rom_bytes = vec![0u8; 0x8000];
+    let mut program: Vec<u8> = Vec::new();
+    for &byte in message {
+      program.extend_from_slice(&[
+        0x3E, byte, // LD A, byte
+        0xE0, 0x01, // LDH (SB), A
+        0x3E, 0x81, // LD A, 0x81
+        0xE0, 0x02, // LDH (SC), A
+        0xF0, 0x02, // LDH A, (SC)
+        0xCB, 0x7F, // BIT 7, A
+        0x20, 0xFA, // JR NZ, -6
+      ]);
+    }
+    program.extend_from_slice(&[0x18, 0xFE]); // JR -2
+    rom_bytes[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]); // JP 0x0150
+    rom_bytes[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+    rom_bytes[0x0143] = 0x80;
+    rom_bytes
+  }
+
+  #[test]
+  fn rom_printing_ok_passes() {
+    assert_eq!(run_test_rom(&create_serial_rom(b"OK")), TestResult::Passed(String::from("OK")));
+  }
+
+  #[test]
+  fn rom_printing_failed_fails() {
+    assert_eq!(run_test_rom(&create_serial_rom(b"Failed #2")), TestResult::Failed(String::from("Failed")));
+  }
+
+  #[test]
+  fn silent_rom_times_out() {
+    let mut harness = TestHarness::new(&create_serial_rom(b"")).unwrap().with_max_machine_cycles(10000);
+    assert_eq!(harness.run(), TestResult::TimedOut(String::new()));
+  }
+}